@@ -0,0 +1,38 @@
+// puts and deletes that `Db::write_with_options` applies together: one log
+// write and at most one fsync for the lot, with consecutive sequence numbers
+// in the order they were added
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct WriteBatch {
+    // None marks a delete
+    records: Vec<(String, Option<String>)>,
+}
+
+impl WriteBatch {
+    pub fn new() -> WriteBatch {
+        WriteBatch::default()
+    }
+
+    pub fn put(&mut self, key: &str, value: &str) -> &mut WriteBatch {
+        self.records.push((key.into(), Some(value.into())));
+        self
+    }
+
+    pub fn delete(&mut self, key: &str) -> &mut WriteBatch {
+        self.records.push((key.into(), None));
+        self
+    }
+
+    pub fn len(&self) -> usize {
+        self.records.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.records.is_empty()
+    }
+
+    pub(crate) fn records(&self) -> impl Iterator<Item = (&str, Option<&str>)> {
+        self.records
+            .iter()
+            .map(|(key, value)| (key.as_str(), value.as_deref()))
+    }
+}
@@ -0,0 +1,184 @@
+use serde::{Deserialize, Serialize};
+
+const BITS_PER_KEY: usize = 10;
+
+// FNV-1a, picked because it's stable across builds. the filter is persisted
+// with the sst, so the hash can never change underneath it.
+fn fnv1a(bytes: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in bytes {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
+
+// which hash a filter was built with. it's stored in the filter, so an sst
+// is read with its own hash whatever the db is configured with now.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum KeyHasher {
+    // what every filter used before there was a choice
+    Fnv1a,
+    // xxh64 with seed 0, quicker on long keys
+    #[default]
+    XxHash64,
+}
+
+impl KeyHasher {
+    pub fn hash(&self, bytes: &[u8]) -> u64 {
+        match self {
+            KeyHasher::Fnv1a => fnv1a(bytes),
+            KeyHasher::XxHash64 => xxh64(bytes),
+        }
+    }
+}
+
+const PRIME64_1: u64 = 0x9e3779b185ebca87;
+const PRIME64_2: u64 = 0xc2b2ae3d27d4eb4f;
+const PRIME64_3: u64 = 0x165667b19e3779f9;
+const PRIME64_4: u64 = 0x85ebca77c2b2ae63;
+const PRIME64_5: u64 = 0x27d4eb2f165667c5;
+
+fn xxh64_round(acc: u64, lane: u64) -> u64 {
+    acc.wrapping_add(lane.wrapping_mul(PRIME64_2))
+        .rotate_left(31)
+        .wrapping_mul(PRIME64_1)
+}
+
+fn xxh64_merge(acc: u64, val: u64) -> u64 {
+    (acc ^ xxh64_round(0, val))
+        .wrapping_mul(PRIME64_1)
+        .wrapping_add(PRIME64_4)
+}
+
+fn read_u64(bytes: &[u8]) -> u64 {
+    u64::from_le_bytes(bytes[..8].try_into().expect("8 bytes"))
+}
+
+fn read_u32(bytes: &[u8]) -> u64 {
+    u32::from_le_bytes(bytes[..4].try_into().expect("4 bytes")) as u64
+}
+
+fn xxh64(bytes: &[u8]) -> u64 {
+    let mut rest = bytes;
+    let mut hash = if bytes.len() >= 32 {
+        let mut acc = [
+            PRIME64_1.wrapping_add(PRIME64_2),
+            PRIME64_2,
+            0,
+            0u64.wrapping_sub(PRIME64_1),
+        ];
+        while rest.len() >= 32 {
+            for (i, acc) in acc.iter_mut().enumerate() {
+                *acc = xxh64_round(*acc, read_u64(&rest[i * 8..]));
+            }
+            rest = &rest[32..];
+        }
+        let mut hash = acc[0]
+            .rotate_left(1)
+            .wrapping_add(acc[1].rotate_left(7))
+            .wrapping_add(acc[2].rotate_left(12))
+            .wrapping_add(acc[3].rotate_left(18));
+        for acc in acc {
+            hash = xxh64_merge(hash, acc);
+        }
+        hash
+    } else {
+        PRIME64_5
+    };
+    hash = hash.wrapping_add(bytes.len() as u64);
+    while rest.len() >= 8 {
+        hash ^= xxh64_round(0, read_u64(rest));
+        hash = hash
+            .rotate_left(27)
+            .wrapping_mul(PRIME64_1)
+            .wrapping_add(PRIME64_4);
+        rest = &rest[8..];
+    }
+    if rest.len() >= 4 {
+        hash ^= read_u32(rest).wrapping_mul(PRIME64_1);
+        hash = hash
+            .rotate_left(23)
+            .wrapping_mul(PRIME64_2)
+            .wrapping_add(PRIME64_3);
+        rest = &rest[4..];
+    }
+    for byte in rest {
+        hash ^= (*byte as u64).wrapping_mul(PRIME64_5);
+        hash = hash.rotate_left(11).wrapping_mul(PRIME64_1);
+    }
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(PRIME64_2);
+    hash ^= hash >> 29;
+    hash = hash.wrapping_mul(PRIME64_3);
+    hash ^ (hash >> 32)
+}
+
+fn unrecorded_hasher() -> KeyHasher {
+    KeyHasher::Fnv1a
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct BloomFilter {
+    bits: Vec<u64>,
+    hashes: u32,
+    // filters from before there was a choice are fnv-1a
+    #[serde(default = "unrecorded_hasher")]
+    hasher: KeyHasher,
+}
+
+impl BloomFilter {
+    pub fn new(keys: usize) -> BloomFilter {
+        let nbits = (keys * BITS_PER_KEY).max(64);
+        // k = ln2 * bits per key is optimal
+        let hashes = ((BITS_PER_KEY as f64 * 0.69) as u32).clamp(1, 30);
+        BloomFilter {
+            bits: vec![0; nbits.div_ceil(64)],
+            hashes,
+            hasher: KeyHasher::default(),
+        }
+    }
+
+    // builds a filter from key hashes collected while streaming records out,
+    // all of them made by hasher
+    pub fn from_hashes(hashes: &[u64], hasher: KeyHasher) -> BloomFilter {
+        let mut filter = BloomFilter::new(hashes.len());
+        filter.hasher = hasher;
+        for hash in hashes {
+            filter.insert_hash(*hash);
+        }
+        filter
+    }
+
+    // double hashing: probe i lands on h1 + i * h2
+    fn probes(&self, hash: u64) -> impl Iterator<Item = usize> {
+        let h1 = hash as u32 as u64;
+        let h2 = (hash >> 32) | 1;
+        let nbits = self.bits.len() as u64 * 64;
+        (0..self.hashes as u64).map(move |i| (h1.wrapping_add(i.wrapping_mul(h2)) % nbits) as usize)
+    }
+
+    fn insert_hash(&mut self, hash: u64) {
+        for bit in self.probes(hash).collect::<Vec<_>>() {
+            self.bits[bit / 64] |= 1 << (bit % 64);
+        }
+    }
+
+    pub fn hasher(&self) -> KeyHasher {
+        self.hasher
+    }
+
+    // false means the key is definitely not present
+    pub fn may_contain(&self, key: &str) -> bool {
+        self.may_contain_bytes(key.as_bytes())
+    }
+
+    pub fn may_contain_bytes(&self, bytes: &[u8]) -> bool {
+        self.may_contain_hash(self.hasher.hash(bytes))
+    }
+
+    pub fn may_contain_hash(&self, hash: u64) -> bool {
+        self.probes(hash)
+            .all(|bit| self.bits[bit / 64] & (1 << (bit % 64)) != 0)
+    }
+}
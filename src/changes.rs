@@ -0,0 +1,82 @@
+use futures::{stream, Stream};
+use tokio::sync::broadcast::{self, error::RecvError};
+
+use crate::log::Put;
+
+// what `Db::subscribe` streams
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Change {
+    Put {
+        key: String,
+        value: String,
+        seq: u64,
+    },
+    Delete {
+        key: String,
+        seq: u64,
+    },
+    // a `Db::merge` operand, as written rather than folded
+    Merge {
+        key: String,
+        operand: String,
+        seq: u64,
+    },
+    // the subscriber fell this many changes behind the buffer and they were
+    // dropped, the feed carries on with the oldest one still held
+    Lagged {
+        missed: u64,
+    },
+}
+
+// hands every committed write to whoever's subscribed. each subscriber
+// buffers up to the capacity on its own, so a slow one holds up nobody.
+pub(crate) struct ChangeFeed {
+    sender: broadcast::Sender<Change>,
+}
+
+impl ChangeFeed {
+    pub fn new(capacity: usize) -> ChangeFeed {
+        ChangeFeed {
+            sender: broadcast::channel(capacity.max(1)).0,
+        }
+    }
+
+    pub fn has_subscribers(&self) -> bool {
+        self.sender.receiver_count() > 0
+    }
+
+    pub fn publish(&self, put: &Put) {
+        if !self.has_subscribers() {
+            return;
+        }
+        let change = match &put.value {
+            Some(operand) if put.merge => Change::Merge {
+                key: put.key.clone(),
+                operand: operand.clone(),
+                seq: put.seq,
+            },
+            Some(value) => Change::Put {
+                key: put.key.clone(),
+                value: value.clone(),
+                seq: put.seq,
+            },
+            None => Change::Delete {
+                key: put.key.clone(),
+                seq: put.seq,
+            },
+        };
+        // only fails once the last subscriber has gone
+        let _ = self.sender.send(change);
+    }
+
+    // changes from now on, ending once the feed is dropped
+    pub fn subscribe(&self) -> impl Stream<Item = Change> + Send + 'static {
+        stream::unfold(self.sender.subscribe(), |mut receiver| async move {
+            match receiver.recv().await {
+                Ok(change) => Some((change, receiver)),
+                Err(RecvError::Lagged(missed)) => Some((Change::Lagged { missed }, receiver)),
+                Err(RecvError::Closed) => None,
+            }
+        })
+    }
+}
@@ -0,0 +1,90 @@
+use std::{
+    fmt::Debug,
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime},
+};
+
+use crate::trace::event;
+
+// where the db gets the time from, swappable so tests can move it by hand
+pub trait Clock: Debug + Send + Sync {
+    fn now(&self) -> SystemTime;
+}
+
+#[derive(Debug, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> SystemTime {
+        SystemTime::now()
+    }
+}
+
+// stands still until advanced. starts at the unix epoch.
+#[derive(Debug)]
+pub struct MockClock {
+    now: Mutex<SystemTime>,
+}
+
+impl Default for MockClock {
+    fn default() -> Self {
+        MockClock::new(SystemTime::UNIX_EPOCH)
+    }
+}
+
+impl MockClock {
+    pub fn new(now: SystemTime) -> MockClock {
+        MockClock {
+            now: Mutex::new(now),
+        }
+    }
+
+    pub fn advance(&self, by: Duration) {
+        *self.now.lock().expect("clock lock poisoned") += by;
+    }
+
+    pub fn set(&self, now: SystemTime) {
+        *self.now.lock().expect("clock lock poisoned") = now;
+    }
+}
+
+impl Clock for MockClock {
+    fn now(&self) -> SystemTime {
+        *self.now.lock().expect("clock lock poisoned")
+    }
+}
+
+// never goes backwards. a wall clock that's set back, by hand or by ntp, is
+// held at the latest time it showed until it catches up, so elapsed times
+// worked out from it stall rather than come out negative or huge.
+#[derive(Debug)]
+pub struct MonotonicClock {
+    clock: Arc<dyn Clock>,
+    latest: Mutex<SystemTime>,
+}
+
+impl MonotonicClock {
+    pub fn new(clock: Arc<dyn Clock>) -> MonotonicClock {
+        MonotonicClock {
+            latest: Mutex::new(clock.now()),
+            clock,
+        }
+    }
+}
+
+impl Clock for MonotonicClock {
+    fn now(&self) -> SystemTime {
+        let now = self.clock.now();
+        let mut latest = self.latest.lock().expect("clock lock poisoned");
+        if now >= *latest {
+            *latest = now;
+        } else {
+            event!(
+                warn,
+                behind_ms = latest.duration_since(now).unwrap_or_default().as_millis() as u64,
+                "clock went backwards"
+            );
+        }
+        *latest
+    }
+}
@@ -0,0 +1,3400 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    future::Future,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, SystemTime},
+};
+
+use bytes::Bytes;
+use futures::Stream;
+use tokio::{
+    sync::{RwLock, Semaphore},
+    task::{JoinHandle, JoinSet},
+    time::{Instant, MissedTickBehavior},
+};
+
+use crate::{
+    batch::WriteBatch,
+    changes::{Change, ChangeFeed},
+    clock::{Clock, MonotonicClock, SystemClock},
+    error::DBError,
+    files::FileCache,
+    iter::{fold_operands, DbIter, MergeIter, Source},
+    latency::Latencies,
+    log::{Durability, Log, Put, PutRef, RecordIter},
+    manifest::{self, Manifest},
+    memtable::{Entry, Memtable, MemtableReader},
+    options::{DbOptions, ScanOptions, Seek, WriteOptions},
+    recovery::RecoveryReport,
+    snapshot::{Snapshot, Snapshots},
+    sst::{self, Sst, SstMeta, SstWriter},
+    trace::{event, timer},
+    verify::{Problem, VerifyReport},
+    vlog::{self, ValueLog, ValueRef},
+};
+
+const DEFAULT_COMPACTION_TRIGGER: usize = 4;
+const DEFAULT_OPEN_PARALLELISM: usize = 8;
+const DEFAULT_YIELD_EVERY: usize = 1024;
+const DEFAULT_CHANGE_FEED_CAPACITY: usize = 1024;
+const DEFAULT_MAX_LEVELS: usize = 7;
+const DEFAULT_LEVEL_BASE_BYTES: u64 = 64 << 20;
+const DEFAULT_LEVEL_MULTIPLIER: u64 = 10;
+// the share of tombstones that puts an sst first in line for compaction
+const TOMBSTONE_PRIORITY: f64 = 0.5;
+// leveled compaction cuts its output into ssts of a quarter of L1's size
+const SSTS_PER_LEVEL_BASE: u64 = 4;
+
+// what `Db::plan_compaction` says the next leveled compaction would do
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CompactionPlan {
+    pub level: usize,
+    pub target_level: usize,
+    // data files of the ssts it would merge, newest first
+    pub inputs: Vec<PathBuf>,
+    pub input_bytes: u64,
+    pub estimated_output_bytes: u64,
+}
+
+// the inputs of one step of `Db::compact_leveled`, see
+// `Db::level_compaction`
+struct LevelCompaction {
+    inputs: Vec<Arc<Sst>>,
+    position: usize,
+    keep_tombstones: bool,
+}
+
+// what `Db::run_background_once` did
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BackgroundWork {
+    Flush,
+    Compaction,
+}
+
+// a compaction running on its own task, started by `maybe_compact`
+struct Background {
+    inputs: Vec<Arc<Sst>>,
+    task: JoinHandle<Result<Option<Merged>, DBError>>,
+    progress: Arc<Progress>,
+}
+
+// what a background compaction publishes as it goes, for
+// `Db::compaction_status`
+#[derive(Default)]
+struct Progress {
+    // the data files it's merging, empty until it has picked them
+    inputs: Mutex<Vec<PathBuf>>,
+    total: AtomicU64,
+    read: Arc<AtomicU64>,
+}
+
+impl Progress {
+    fn started(&self, inputs: &[Arc<Sst>]) {
+        *self.inputs.lock().expect("progress lock poisoned") = inputs
+            .iter()
+            .map(|sst| sst.data_path().to_path_buf())
+            .collect();
+        let total = inputs.iter().map(|sst| sst.size()).sum();
+        self.total.store(total, Ordering::Relaxed);
+    }
+
+    fn report(&self) -> CompactionProgress {
+        CompactionProgress {
+            inputs: self.inputs.lock().expect("progress lock poisoned").clone(),
+            bytes_read: self.read.load(Ordering::Relaxed),
+            total_bytes: self.total.load(Ordering::Relaxed),
+        }
+    }
+}
+
+// a running compaction as `Db::compaction_status` sees it
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CompactionProgress {
+    // data files of the ssts being merged, newest first
+    pub inputs: Vec<PathBuf>,
+    pub bytes_read: u64,
+    // of all the inputs together, 0 until they're picked
+    pub total_bytes: u64,
+}
+
+impl CompactionProgress {
+    // how much of the input has been read, from 0 to 100
+    pub fn percent(&self) -> f64 {
+        match self.total_bytes {
+            0 => 0.0,
+            total => (self.bytes_read as f64 / total as f64 * 100.0).min(100.0),
+        }
+    }
+}
+
+// the inputs a compaction actually merged, and the output. there's none if
+// nothing was worth merging.
+type Merged = (Vec<Arc<Sst>>, Sst);
+
+// a full memtable being written out on its own task, see
+// `DbOptions::async_flush`. its records are in its flushing log until the
+// sst is in the manifest. reads look at it after the memtable and any
+// flushing one filled since, which are newer, and before the ssts and any
+// flushing one filled before, which are older. their ssts go in in the
+// order the memtables filled, whichever task finishes first.
+struct Flushing {
+    memtable: Arc<Memtable>,
+    task: JoinHandle<Result<Sst, DBError>>,
+    // which flushing log holds its records
+    id: u64,
+}
+
+const FLUSHING_LOG: &str = "log.flushing";
+
+// the log of the flushing memtable with id, the first in the queue has the
+// plain name
+fn flushing_log(log_dir: &Path, id: u64) -> PathBuf {
+    match id {
+        0 => log_dir.join(FLUSHING_LOG),
+        id => log_dir.join(format!("{}.{}", FLUSHING_LOG, id)),
+    }
+}
+
+// the flushing logs in log_dir, oldest first
+async fn flushing_logs(log_dir: &Path) -> Result<Vec<PathBuf>, DBError> {
+    let mut logs = Vec::new();
+    let mut entries = tokio::fs::read_dir(log_dir).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        let name = entry.file_name();
+        let Some(rest) = name
+            .to_str()
+            .and_then(|name| name.strip_prefix(FLUSHING_LOG))
+        else {
+            continue;
+        };
+        let id = match rest.strip_prefix('.') {
+            Some(id) => id.parse().ok(),
+            None => rest.is_empty().then_some(0),
+        };
+        if let Some(id) = id {
+            logs.push((id, entry.path()));
+        }
+    }
+    logs.sort();
+    Ok(logs.into_iter().map(|(_, path)| path).collect())
+}
+
+pub struct Db {
+    dir: PathBuf,
+    // where the log is, dir unless `DbOptions::wal_dir` says otherwise
+    log_dir: PathBuf,
+    options: DbOptions,
+    log: Log,
+    vlog: ValueLog,
+    memtable: Memtable,
+    // newest first
+    ssts: Vec<Arc<Sst>>,
+    // atomic so `maybe_compact` can take an id through `&self`
+    next_sst_id: AtomicU64,
+    // as last committed to disk
+    manifest: Manifest,
+    background: Mutex<Option<Background>>,
+    // worked out from the ssts on first write when they're opened lazily
+    next_seq: Option<u64>,
+    // from the seq file, keeps sequence numbers from going backwards when
+    // compaction has dropped the newest records
+    seq_floor: u64,
+    poisoned: bool,
+    // read-only database under this one, see `open_overlay`
+    base: Option<Box<Db>>,
+    clock: Arc<dyn Clock>,
+    last_write: SystemTime,
+    // records found deep in the ssts by `DbOptions::read_repair`, valid
+    // until the sst set next changes
+    repaired: Mutex<HashMap<String, Put>>,
+    // see `DbOptions::max_open_files`
+    files: Option<Arc<FileCache>>,
+    // see `DbOptions::record_latency`
+    latency: Option<Arc<Latencies>>,
+    // oldest first, at most `DbOptions::max_flushing_memtables`
+    flushing: VecDeque<Flushing>,
+    snapshots: Snapshots,
+    changes: ChangeFeed,
+}
+
+// past this many repaired keys the cache starts over
+const REPAIR_CAPACITY: usize = 1024;
+
+fn sst_id(path: &Path) -> Option<u64> {
+    if path.extension()? != "data" {
+        return None;
+    }
+    path.file_stem()?.to_str()?.parse().ok()
+}
+
+// dropping the future on expiry cancels whatever it was doing
+async fn with_timeout<T>(
+    limit: Option<Duration>,
+    work: impl Future<Output = Result<T, DBError>>,
+) -> Result<T, DBError> {
+    match limit {
+        Some(limit) => tokio::time::timeout(limit, work)
+            .await
+            .map_err(|_| DBError::Timeout)?,
+        None => work.await,
+    }
+}
+
+// the smallest string greater than everything starting with prefix, None
+// when there isn't one
+fn prefix_end(prefix: &str) -> Option<String> {
+    let mut chars: Vec<char> = prefix.chars().collect();
+    while let Some(last) = chars.pop() {
+        let next = (last as u32 + 1..=char::MAX as u32).find_map(char::from_u32);
+        if let Some(next) = next {
+            chars.push(next);
+            return Some(chars.into_iter().collect());
+        }
+    }
+    None
+}
+
+// where key lives in namespace ns. a namespace is never empty and holds no
+// nul, so no namespace's keys are a prefix of another's.
+fn namespace_key(ns: &str, key: &str) -> Result<String, DBError> {
+    if ns.is_empty() || ns.contains('\0') {
+        return Err(DBError::InvalidInput(format!(
+            "namespace {:?} must be non-empty with no nul",
+            ns
+        )));
+    }
+    Ok(format!("{}\0{}", ns, key))
+}
+
+fn yield_every(options: &DbOptions) -> usize {
+    match options.yield_every {
+        0 => DEFAULT_YIELD_EVERY,
+        every => every,
+    }
+}
+
+// a writer for a new sst set up the way the options ask for, created as
+// of the db's clock
+async fn sst_writer(
+    options: &DbOptions,
+    clock: &dyn Clock,
+    path: &Path,
+    level: usize,
+) -> Result<SstWriter, DBError> {
+    let buffer = match options.sst_write_buffer {
+        0 => sst::DEFAULT_WRITE_BUFFER,
+        buffer => buffer,
+    };
+    let writer = SstWriter::create_with_buffer(path, buffer)
+        .await?
+        .with_level(level)
+        .with_hasher(options.bloom_hasher)
+        .with_created(clock.now());
+    let writer = if options.compressed_index {
+        writer.with_compressed_index()
+    } else {
+        writer
+    };
+    Ok(match options.prefix_bloom_len {
+        Some(len) => writer.with_prefix_bloom(len),
+        None => writer,
+    })
+}
+
+// which of `candidates` to compact into `next_level`. an sst that's at
+// least `TOMBSTONE_PRIORITY` tombstones goes first, the one with the most of
+// them, since compacting it frees the most. otherwise by size it's the
+// largest. by overlap it's the one overlapping the fewest bytes of the next
+// level per byte of its own, so the least of the next level gets rewritten
+// along with it; ties go to the larger sst.
+pub async fn pick_compaction_input(
+    candidates: &[Arc<Sst>],
+    next_level: &[Arc<Sst>],
+    by_overlap: bool,
+) -> Result<Option<Arc<Sst>>, DBError> {
+    let mut most_deleted: Option<(&Arc<Sst>, f64)> = None;
+    for sst in candidates {
+        let Some(stats) = sst.meta().await?.stats() else {
+            continue;
+        };
+        let ratio = stats.tombstone_ratio();
+        if ratio >= TOMBSTONE_PRIORITY && most_deleted.is_none_or(|(_, most)| ratio > most) {
+            most_deleted = Some((sst, ratio));
+        }
+    }
+    if let Some((sst, _)) = most_deleted {
+        return Ok(Some(sst.clone()));
+    }
+    if !by_overlap {
+        return Ok(candidates.iter().max_by_key(|sst| sst.size()).cloned());
+    }
+    let mut best: Option<(&Arc<Sst>, u64)> = None;
+    for sst in candidates {
+        let meta = sst.meta().await?;
+        let mut overlap = 0;
+        for next in next_level {
+            if meta.overlaps(next.meta().await?) {
+                overlap += next.size();
+            }
+        }
+        // overlap / size against the best so far, without dividing
+        let better = best.is_none_or(|(best, best_overlap)| {
+            let ours = overlap as u128 * best.size().max(1) as u128;
+            let theirs = best_overlap as u128 * sst.size().max(1) as u128;
+            ours < theirs || (ours == theirs && sst.size() > best.size())
+        });
+        if better {
+            best = Some((sst, overlap));
+        }
+    }
+    Ok(best.map(|(sst, _)| sst.clone()))
+}
+
+// merges ssts, newest first, into one L1 sst at path. they have to be the
+// oldest ssts there are, with nothing older left that a dropped tombstone
+// would need to hide.
+// tombstones are kept when something outside ssts, an overlay's base, may
+// still hold older values for their keys.
+async fn merge_ssts(
+    ssts: &[Arc<Sst>],
+    options: &DbOptions,
+    clock: &dyn Clock,
+    path: &Path,
+    keep_tombstones: bool,
+    oldest_snapshot: Option<u64>,
+    read: Option<&Arc<AtomicU64>>,
+) -> Result<Sst, DBError> {
+    let output = MergeOutput::One(path);
+    let merged = merge_ssts_into(
+        ssts,
+        options,
+        clock,
+        output,
+        keep_tombstones,
+        oldest_snapshot,
+        read,
+    );
+    Ok(merged
+        .await?
+        .pop()
+        .expect("a merge writes at least one sst"))
+}
+
+// where a merge writes to: one L1 sst at a path, or a run of ssts at level
+// of about max_bytes each, cut between keys, at paths handed out as needed
+enum MergeOutput<'a> {
+    One(&'a Path),
+    Split {
+        level: usize,
+        max_bytes: u64,
+        next_path: &'a mut (dyn FnMut() -> PathBuf + Send),
+    },
+}
+
+// `merge_ssts`, writing the output where output says. there's always
+// at least one sst, though it may be empty.
+async fn merge_ssts_into(
+    ssts: &[Arc<Sst>],
+    options: &DbOptions,
+    clock: &dyn Clock,
+    mut output: MergeOutput<'_>,
+    keep_tombstones: bool,
+    oldest_snapshot: Option<u64>,
+    // the bytes of the inputs read so far are added here
+    read: Option<&Arc<AtomicU64>>,
+) -> Result<Vec<Sst>, DBError> {
+    let read_ahead = match options.compaction_read_ahead {
+        0 => sst::DEFAULT_READ_AHEAD,
+        bytes => bytes,
+    };
+    let mut sources = Vec::with_capacity(ssts.len());
+    for sst in ssts {
+        let mut iter = sst.iter_with_read_ahead("", read_ahead).await?;
+        if let Some(read) = read {
+            iter = iter.counting(read.clone());
+        }
+        sources.push(Source::Sst(Box::new(iter)));
+    }
+    let versions = options.versions_to_keep.max(1);
+    // a merge operand needs the versions under it kept too
+    let all_versions =
+        versions > 1 || oldest_snapshot.is_some() || options.merge_operator.is_some();
+    let mut merge = if all_versions {
+        MergeIter::all_versions(sources)
+    } else {
+        MergeIter::new(sources)
+    }
+    .checked(options.paranoid_checks)
+    .yielding(yield_every(options));
+
+    // the inputs' data is no younger than the newest of them
+    let mut created = None;
+    for sst in ssts {
+        created = created.max(sst.meta().await?.created());
+    }
+    let level = match &output {
+        MergeOutput::One(_) => 1,
+        MergeOutput::Split { level, .. } => *level,
+    };
+    let new_writer = |path: PathBuf| async move {
+        let writer = sst_writer(options, clock, &path, level).await?;
+        Ok::<_, DBError>(match created {
+            Some(created) => writer.with_created(created),
+            None => writer,
+        })
+    };
+    let first = match &mut output {
+        MergeOutput::One(path) => path.to_path_buf(),
+        MergeOutput::Split { next_path, .. } => next_path(),
+    };
+    let mut writer = new_writer(first).await?;
+    let mut outputs = Vec::new();
+    let limiter = options.compaction_rate_limit.as_deref();
+    let merged = async {
+        // versions of the current key written so far, None once a tombstone
+        // has hidden the rest
+        let mut current: Option<(String, Option<usize>)> = None;
+        // bytes merged not yet taken from the rate limiter
+        let mut unpaid = 0;
+        while let Some(put) = merge.next().await? {
+            let new_key = current.as_ref().is_none_or(|(key, _)| *key != put.key);
+            if let MergeOutput::Split {
+                max_bytes,
+                next_path,
+                ..
+            } = &mut output
+            {
+                if new_key && !writer.is_empty() && writer.size() >= *max_bytes {
+                    let next = new_writer(next_path()).await?;
+                    outputs.push(std::mem::replace(&mut writer, next).finish().await?);
+                }
+            }
+            let kept = match &mut current {
+                Some((_, kept)) if !new_key => kept,
+                _ => &mut current.insert((put.key.clone(), Some(0))).1,
+            };
+            // newer than a live snapshot, which may still need what's older,
+            // so every one is kept and the rules start from the next version
+            let write = if oldest_snapshot.is_some_and(|oldest| put.seq > oldest) {
+                true
+            } else {
+                match kept {
+                    // operands aren't versions of their own, and the version
+                    // under them is kept for them to be folded onto
+                    Some(count) if *count < versions && put.merge => true,
+                    Some(count) if *count < versions && put.value.is_some() => {
+                        *count += 1;
+                        true
+                    }
+                    Some(count) if put.value.is_none() => {
+                        let write = keep_tombstones && *count == 0;
+                        *kept = None;
+                        write
+                    }
+                    _ => false,
+                }
+            };
+            // read once, and written again if kept
+            let bytes = record_bytes(&put);
+            unpaid += if write { bytes * 2 } else { bytes };
+            if write {
+                writer.add(&put).await?;
+            }
+            if let Some(limiter) = limiter.filter(|_| unpaid >= RATE_LIMIT_CHUNK) {
+                limiter.take(std::mem::take(&mut unpaid)).await;
+            }
+        }
+        if let Some(limiter) = limiter.filter(|_| unpaid > 0) {
+            limiter.take(unpaid).await;
+        }
+        event!(info, keys = writer.len(), "compaction merged");
+        outputs.push(writer.finish().await?);
+        Ok(())
+    }
+    .await;
+    if let Err(err) = merged {
+        // the ones finished already aren't in any manifest
+        for output in &outputs {
+            output.mark_obsolete();
+        }
+        return Err(err);
+    }
+    Ok(outputs)
+}
+
+// compaction goes to the rate limiter in steps of at least this many bytes
+// rather than record by record
+const RATE_LIMIT_CHUNK: u64 = 16 * 1024;
+
+// roughly what a record costs to read or write, near enough for rate limiting
+fn record_bytes(put: &Put) -> u64 {
+    (put.key.len() + put.value.as_ref().map_or(0, String::len)) as u64
+}
+
+// whether every key in sst has a record in newer, the ssts in front of it,
+// that a snapshot at oldest would see too
+async fn is_shadowed(
+    sst: &Arc<Sst>,
+    newer: &[Arc<Sst>],
+    oldest: Option<u64>,
+    files: Option<&FileCache>,
+) -> Result<bool, DBError> {
+    let meta = sst.meta().await?;
+    // an sst with no records has no first key
+    let Some(min_key) = meta.min_key() else {
+        return Ok(true);
+    };
+    // without a recorded last key a range reaches to the end of the key
+    // space, as with `SstMeta::overlaps`
+    let mut ranges = Vec::with_capacity(newer.len());
+    for other in newer {
+        let other = other.meta().await?;
+        if let Some(min) = other.min_key() {
+            ranges.push((min, other.max_key()));
+        }
+    }
+    let in_some = |key: &str| {
+        ranges
+            .iter()
+            .any(|(min, max)| *min <= key && max.is_none_or(|max| key <= max))
+    };
+    // an sst's own unknown last key leaves every key to be looked up
+    if !in_some(min_key) || meta.max_key().is_some_and(|max_key| !in_some(max_key)) {
+        return Ok(false);
+    }
+    let mut iter = sst.iter().await?;
+    let mut last_key: Option<String> = None;
+    while let Some(put) = iter.next().await? {
+        // an older version of the key just checked
+        if last_key.as_deref() == Some(put.key.as_str()) {
+            continue;
+        }
+        let mut hidden = false;
+        for other in newer {
+            if let Some(record) = other.get_record_in(&put.key, files).await? {
+                // an operand needs what's under it
+                hidden = !record.merge && oldest.is_none_or(|oldest| record.seq <= oldest);
+                break;
+            }
+        }
+        if !hidden {
+            return Ok(false);
+        }
+        last_key = Some(put.key);
+    }
+    Ok(true)
+}
+
+fn contains(ssts: &[Arc<Sst>], sst: &Arc<Sst>) -> bool {
+    ssts.iter().any(|other| Arc::ptr_eq(other, sst))
+}
+
+// whether sst's key range overlaps any of others'. with all, every sst
+// counts as overlapping, the way L0 ssts are all compacted together.
+async fn overlaps_any(sst: &Sst, others: &[Arc<Sst>], all: bool) -> Result<bool, DBError> {
+    if all {
+        return Ok(true);
+    }
+    let meta = sst.meta().await?;
+    for other in others {
+        if meta.overlaps(other.meta().await?) {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+// what a compaction of ssts merges: all of them, or with skip_disjoint
+// only those overlapping another, the rest are left as they are
+async fn compaction_inputs(
+    ssts: &[Arc<Sst>],
+    skip_disjoint: bool,
+) -> Result<Vec<Arc<Sst>>, DBError> {
+    if !skip_disjoint {
+        return Ok(ssts.to_vec());
+    }
+    let mut metas = Vec::with_capacity(ssts.len());
+    for sst in ssts {
+        metas.push(sst.meta().await?);
+    }
+    Ok(ssts
+        .iter()
+        .enumerate()
+        .filter(|(idx, _)| {
+            metas
+                .iter()
+                .enumerate()
+                .any(|(other, meta)| other != *idx && meta.overlaps(metas[*idx]))
+        })
+        .map(|(_, sst)| sst.clone())
+        .collect())
+}
+
+// the record an overlay's base holds for key. separated values have to come
+// out of the base's own value log.
+async fn base_record(base: &Db, key: &str) -> Result<Option<Put>, DBError> {
+    match Box::pin(base.get_record(key)).await? {
+        Some(mut put) => {
+            put.value = vlog::resolve_value(base.vlog.path(), put.clone()).await?;
+            put.value_ref = None;
+            Ok(Some(put))
+        }
+        None => Ok(None),
+    }
+}
+
+// where `Db::flush_all_and_checkpoint` keeps the checkpoint taken at seq
+fn seq_checkpoint_dir(dir: &Path, seq: u64) -> PathBuf {
+    dir.join("checkpoints").join(seq.to_string())
+}
+
+fn new_memtable(options: &DbOptions) -> Memtable {
+    if options.skip_list_memtable {
+        Memtable::skip_list()
+    } else if options.memtable_versions {
+        Memtable::versioned()
+    } else {
+        Memtable::default()
+    }
+}
+
+// borrowed memtable entries, in order, into an sst
+async fn write_entries<'a>(
+    mut writer: SstWriter,
+    entries: impl Iterator<Item = (&'a str, &'a Entry)>,
+) -> Result<Sst, DBError> {
+    for (key, entry) in entries {
+        if let Err(err) = writer.add_entry(key, entry).await {
+            writer.abandon().await;
+            return Err(err);
+        }
+    }
+    writer.finish().await
+}
+
+fn level_dir(dir: &Path, level: usize) -> PathBuf {
+    dir.join(format!("L{}", level))
+}
+
+// the directories ssts can live in: the database directory itself, or each
+// `L<n>` subdirectory when ssts are split up by level
+async fn sst_dirs(dir: &Path, level_dirs: bool) -> Result<Vec<PathBuf>, DBError> {
+    if !level_dirs {
+        return Ok(vec![dir.to_path_buf()]);
+    }
+    let mut dirs = Vec::new();
+    let mut entries = tokio::fs::read_dir(dir).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        let name = entry.file_name();
+        let is_level = name
+            .to_str()
+            .and_then(|name| name.strip_prefix('L'))
+            .is_some_and(|level| level.parse::<usize>().is_ok());
+        if is_level && entry.file_type().await?.is_dir() {
+            dirs.push(entry.path());
+        }
+    }
+    Ok(dirs)
+}
+
+// first open of a directory. ssts already in it, from before there was a
+// manifest, are adopted as they are. the manifest goes last, so a crash part
+// way through is simply bootstrapped again on the next open.
+async fn bootstrap(dir: &Path, log_dir: &Path, level_dirs: bool) -> Result<Manifest, DBError> {
+    let found = discover_ssts(dir, level_dirs).await?;
+    let manifest = Manifest {
+        next_sst_id: found.first().map_or(0, |(id, _)| id + 1),
+        ssts: found
+            .into_iter()
+            .filter_map(|(_, path)| Some(path.strip_prefix(dir).ok()?.to_path_buf()))
+            .collect(),
+        ..Default::default()
+    };
+    if manifest::load_seq(dir).await?.is_none() {
+        manifest::store_seq(dir, 0).await?;
+    }
+    Log::open(log_dir.join("log")).await?;
+    manifest.store(dir).await?;
+    event!(info, ssts = manifest.ssts.len(), "bootstrapped");
+    Ok(manifest)
+}
+
+// opens the ssts at paths, up to `parallelism` at a time, keeping their order
+async fn open_ssts(
+    paths: Vec<PathBuf>,
+    lazy: bool,
+    parallelism: usize,
+) -> Result<Vec<Arc<Sst>>, DBError> {
+    let permits = Arc::new(Semaphore::new(parallelism));
+    let mut opens = JoinSet::new();
+    for (idx, path) in paths.into_iter().enumerate() {
+        let permits = permits.clone();
+        opens.spawn(async move {
+            let _permit = permits.acquire_owned().await.expect("never closed");
+            let sst = if lazy {
+                Sst::open_lazy(path).await?
+            } else {
+                Sst::open(path).await?
+            };
+            Ok::<_, DBError>((idx, sst))
+        });
+    }
+    let mut ssts = Vec::new();
+    while let Some(opened) = opens.join_next().await {
+        ssts.push(opened.map_err(std::io::Error::other)??);
+    }
+    ssts.sort_unstable_by_key(|(idx, _)| *idx);
+    Ok(ssts.into_iter().map(|(_, sst)| Arc::new(sst)).collect())
+}
+
+// what an sst write cut short by a crash left behind. never in the
+// manifest, they're unreachable.
+async fn remove_temp_files(dir: &Path, level_dirs: bool) -> Result<Vec<PathBuf>, DBError> {
+    let mut removed = Vec::new();
+    for dir in sst_dirs(dir, level_dirs).await? {
+        let mut entries = tokio::fs::read_dir(&dir).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            if sst::is_temp(&entry.path()) {
+                event!(info, path = %entry.path().display(), "removing temp file");
+                tokio::fs::remove_file(entry.path()).await?;
+                removed.push(entry.path());
+            }
+        }
+    }
+    Ok(removed)
+}
+
+// ssts on disk the manifest doesn't list, which a crash left behind: the
+// inputs of a compaction whose edit was written before they were deleted,
+// or an sst written before the edit adding it. nothing reads them.
+async fn remove_orphans(
+    dir: &Path,
+    level_dirs: bool,
+    manifest: &Manifest,
+) -> Result<Vec<PathBuf>, DBError> {
+    let mut removed = Vec::new();
+    for (_, path) in discover_ssts(dir, level_dirs).await? {
+        let listed = path
+            .strip_prefix(dir)
+            .is_ok_and(|relative| manifest.ssts.iter().any(|sst| sst == relative));
+        if !listed {
+            event!(info, path = %path.display(), "removing orphaned sst");
+            sst::remove_files(&path).await;
+            removed.push(path);
+        }
+    }
+    Ok(removed)
+}
+
+// every sst on disk as (id, path without extension), newest first
+async fn discover_ssts(dir: &Path, level_dirs: bool) -> Result<Vec<(u64, PathBuf)>, DBError> {
+    let mut found = Vec::new();
+    for dir in sst_dirs(dir, level_dirs).await? {
+        let mut entries = tokio::fs::read_dir(&dir).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            if let Some(id) = sst_id(&entry.path()) {
+                found.push((id, entry.path().with_extension("")));
+            }
+        }
+    }
+    found.sort_unstable_by_key(|(id, _)| std::cmp::Reverse(*id));
+    Ok(found)
+}
+
+impl Db {
+    pub async fn new(dir: impl AsRef<Path>) -> Result<Db, DBError> {
+        Db::open(dir, DbOptions::default()).await
+    }
+
+    pub async fn open(dir: impl AsRef<Path>, options: DbOptions) -> Result<Db, DBError> {
+        Ok(Db::open_with_report(dir, options).await?.0)
+    }
+
+    // `open`, also saying what recovery found and did along the way
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip_all, fields(dir = %dir.as_ref().display())))]
+    pub async fn open_with_report(
+        dir: impl AsRef<Path>,
+        options: DbOptions,
+    ) -> Result<(Db, RecoveryReport), DBError> {
+        timer!(started);
+        let mut report = RecoveryReport::default();
+        if !dir.as_ref().exists() {
+            tokio::fs::create_dir_all(&dir).await?;
+        }
+        let log_dir = options
+            .wal_dir
+            .clone()
+            .unwrap_or_else(|| dir.as_ref().to_path_buf());
+        if !log_dir.exists() {
+            tokio::fs::create_dir_all(&log_dir).await?;
+        }
+        let manifest = match Manifest::load(dir.as_ref()).await? {
+            Some(manifest) => manifest,
+            None => {
+                report.bootstrapped = true;
+                bootstrap(dir.as_ref(), &log_dir, options.level_dirs).await?
+            }
+        };
+        report.temp_files_removed = remove_temp_files(dir.as_ref(), options.level_dirs).await?;
+        report.orphans_removed =
+            remove_orphans(dir.as_ref(), options.level_dirs, &manifest).await?;
+        // the seq file is only a floor, recovered from the manifest's copy
+        // when it's gone or unreadable. the ssts and log push it higher.
+        let seq_file = match manifest::load_seq(dir.as_ref()).await {
+            Err(DBError::InvalidInput(_err)) => {
+                event!(warn, error = %_err, "unreadable seq file");
+                None
+            }
+            seq_file => seq_file?,
+        };
+        let seq_floor = seq_file.unwrap_or(0).max(manifest.seq);
+        if seq_file.is_none() {
+            report.seq_file_rebuilt = !report.bootstrapped;
+            manifest::store_seq(dir.as_ref(), seq_floor).await?;
+        }
+        let log = Log::open(log_dir.join("log")).await?;
+        let memtable = new_memtable(&options);
+
+        let parallelism = match options.open_parallelism {
+            0 => DEFAULT_OPEN_PARALLELISM,
+            parallelism => parallelism,
+        };
+        let ssts = open_ssts(
+            manifest
+                .ssts
+                .iter()
+                .map(|relative| dir.as_ref().join(relative))
+                .collect(),
+            options.lazy_sst_open,
+            parallelism,
+        )
+        .await?;
+        let clock: Arc<dyn Clock> = Arc::new(MonotonicClock::new(
+            options
+                .clock
+                .clone()
+                .unwrap_or_else(|| Arc::new(SystemClock)),
+        ));
+        let files = options
+            .max_open_files
+            .map(|max| Arc::new(FileCache::new(max)));
+        let latency = options
+            .record_latency
+            .then(|| Arc::new(Latencies::default()));
+        let changes = ChangeFeed::new(match options.change_feed_capacity {
+            0 => DEFAULT_CHANGE_FEED_CAPACITY,
+            capacity => capacity,
+        });
+        let mut db = Db {
+            dir: dir.as_ref().to_path_buf(),
+            log_dir,
+            options,
+            log,
+            vlog: ValueLog::new(dir.as_ref().join("vlog")),
+            memtable,
+            ssts,
+            next_sst_id: AtomicU64::new(manifest.next_sst_id),
+            manifest,
+            background: Mutex::new(None),
+            next_seq: None,
+            seq_floor,
+            poisoned: false,
+            base: None,
+            last_write: clock.now(),
+            repaired: Mutex::new(HashMap::new()),
+            files,
+            latency,
+            flushing: VecDeque::new(),
+            snapshots: Snapshots::default(),
+            changes,
+            clock,
+        };
+        report.ssts_opened = db.ssts.len();
+        db.replay_log(&mut report).await?;
+        event!(
+            info,
+            replayed = report.records_replayed,
+            ssts = db.ssts.len(),
+            elapsed_us = started.elapsed().as_micros() as u64,
+            "recovered"
+        );
+        if !db.options.lazy_sst_open {
+            db.next_seq().await?;
+        }
+        Ok((db, report))
+    }
+
+    // reads look in overlay_dir first and fall through to base_dir, writes
+    // only ever go to overlay_dir, so the base is never modified. tombstones
+    // in the overlay hide base values, and survive the overlay's compactions
+    // to keep doing so. the base must not be written to while it's in use.
+    pub async fn open_overlay(
+        base_dir: impl AsRef<Path>,
+        overlay_dir: impl AsRef<Path>,
+        options: DbOptions,
+    ) -> Result<Db, DBError> {
+        // a wal_dir is the overlay's, the base keeps its log with its data
+        let base_options = DbOptions {
+            wal_dir: None,
+            ..options.clone()
+        };
+        let mut base = Db::open(base_dir, base_options).await?;
+        let base_seq = base.next_seq().await?;
+        let mut overlay = Db::open(overlay_dir, options).await?;
+        // overlay records have to win any comparison with base records
+        overlay.seq_floor = overlay.seq_floor.max(base_seq);
+        overlay.next_seq = None;
+        if !overlay.options.lazy_sst_open {
+            overlay.next_seq().await?;
+        }
+        overlay.base = Some(Box::new(base));
+        Ok(overlay)
+    }
+
+    // rebuilds the memtable from the log. past `memtable_budget` it's flushed
+    // to an sst along the way, and if that happened the rest is flushed too
+    // so the log can be emptied rather than replayed into extra ssts again
+    // on the next open.
+    async fn replay_log(&mut self, report: &mut RecoveryReport) -> Result<(), DBError> {
+        // async flushes that didn't finish. their records are older than
+        // the log's, and flushing them all now lets their logs go.
+        let interrupted = flushing_logs(&self.log_dir).await?;
+        let mut flushed = !interrupted.is_empty();
+        let log = self.log_dir.join("log");
+        let segments = interrupted.iter().cloned().chain([log.clone()]);
+        let mut records = RecordIter::new(segments, self.options.log_corruption);
+        flushed |= self.replay_records(&mut records, report).await?;
+        report.corrupt_records_skipped += records.skipped();
+        if let Some((path, offset)) = records.stopped_at() {
+            // records after a corrupt one in the middle would be lost by
+            // carrying on, they're left for `CorruptionPolicy::SkipCorrupt`.
+            // the flushing logs were rotated away whole, so only the log can
+            // have a torn tail.
+            if !records.stopped_at_tail() {
+                return Err(DBError::CorruptLog(path.to_path_buf(), offset));
+            }
+            // cut it off, or the next append would land behind it and be
+            // lost with it
+            let len = tokio::fs::metadata(&log).await?.len();
+            report.log_bytes_truncated = len.saturating_sub(offset);
+            self.log.truncate_to(offset).await?;
+        }
+        if flushed && !self.memtable.is_empty() {
+            report.ssts_flushed += 1;
+        }
+        if flushed {
+            self.flush().await?;
+        }
+        for flushing in interrupted {
+            tokio::fs::remove_file(flushing).await?;
+        }
+        Ok(())
+    }
+
+    // into the memtable, returning whether `memtable_budget` made it flush
+    async fn replay_records(
+        &mut self,
+        records: &mut RecordIter,
+        report: &mut RecoveryReport,
+    ) -> Result<bool, DBError> {
+        let mut flushed = false;
+        while let Some(put) = records.next_valid().await? {
+            self.memtable.insert(put);
+            report.records_replayed += 1;
+            if self
+                .options
+                .memtable_budget
+                .is_some_and(|budget| self.memtable.size_bytes() > budget)
+            {
+                self.flush_memtable().await?;
+                // worked out before the rest of the log was seen
+                self.next_seq = None;
+                flushed = true;
+                report.ssts_flushed += 1;
+            }
+        }
+        Ok(flushed)
+    }
+
+    async fn next_seq(&mut self) -> Result<u64, DBError> {
+        if let Some(seq) = self.next_seq {
+            return Ok(seq);
+        }
+        let mut max_seq = self.memtable.max_seq();
+        for sst in &self.ssts {
+            max_seq = max_seq.max(sst.meta().await?.max_seq());
+        }
+        let seq = (max_seq + 1).max(self.seq_floor);
+        self.next_seq = Some(seq);
+        Ok(seq)
+    }
+
+    // `next_seq` without working it out from lazily opened ssts, which it
+    // can only be behind when they were written before the seq file
+    fn next_seq_hint(&self) -> u64 {
+        self.next_seq
+            .unwrap_or(self.seq_floor)
+            .max(self.memtable.max_seq() + 1)
+    }
+
+    // the database as of now, kept readable by compaction until the
+    // snapshot is dropped. meanwhile memtable overwrites keep the version
+    // they replace, as with `DbOptions::memtable_versions`. the skip list
+    // memtable can't, so it can't be read at a snapshot either.
+    pub async fn snapshot(&mut self) -> Result<Snapshot, DBError> {
+        self.check_poisoned()?;
+        if self.options.skip_list_memtable {
+            return Err(DBError::InvalidInput(
+                "the skip list memtable keeps no versions for a snapshot".to_string(),
+            ));
+        }
+        self.memtable.keep_versions();
+        let seq = self.next_seq().await?.saturating_sub(1);
+        Ok(self.snapshots.take(seq))
+    }
+
+    // every write committed from now on, in commit order, as a stream that
+    // ends when the db is dropped. each subscriber buffers
+    // `DbOptions::change_feed_capacity` changes on its own; one that falls
+    // further behind is told with `Change::Lagged` how many it missed.
+    // writes to an overlay show up in its feed, not its base's.
+    pub fn subscribe(&self) -> impl Stream<Item = Change> + Send + 'static {
+        self.changes.subscribe()
+    }
+
+    // the oldest sequence number a live snapshot reads at, or the newest one
+    // written when there's no snapshot. for each key, versions older than
+    // its newest one at or below this aren't needed by anything.
+    pub fn min_seq_in_use(&self) -> u64 {
+        self.snapshots
+            .oldest()
+            .unwrap_or_else(|| self.next_seq_hint().saturating_sub(1))
+    }
+
+    fn manifest_for(&self, ssts: &[Arc<Sst>], next_sst_id: u64) -> Result<Manifest, DBError> {
+        let mut manifest = Manifest {
+            next_sst_id,
+            ssts: Vec::with_capacity(ssts.len()),
+            ..Default::default()
+        };
+        for sst in ssts {
+            let relative = sst
+                .data_path()
+                .strip_prefix(&self.dir)
+                .map_err(std::io::Error::other)?;
+            manifest.ssts.push(relative.with_extension(""));
+        }
+        Ok(manifest)
+    }
+
+    // records the current sst set, and the sequence number it has reached
+    async fn save_state(&mut self) -> Result<(), DBError> {
+        let seq = self.next_seq().await?;
+        manifest::store_seq(&self.dir, seq).await?;
+        let mut next = self.manifest_for(&self.ssts, self.next_sst_id.load(Ordering::SeqCst))?;
+        next.seq = seq;
+        self.manifest.commit(&self.dir, next).await
+    }
+
+    // after a write failed part way, memory may no longer match what's on
+    // disk. everything but `verify` then fails with `Poisoned` until the db
+    // is reopened and recovers from the files.
+    fn poison_on_err<T>(&mut self, result: Result<T, DBError>) -> Result<T, DBError> {
+        if result.is_err() {
+            self.poisoned = true;
+            event!(error, "poisoned");
+        }
+        result
+    }
+
+    fn check_poisoned(&self) -> Result<(), DBError> {
+        if self.poisoned {
+            return Err(DBError::Poisoned);
+        }
+        Ok(())
+    }
+
+    pub fn is_poisoned(&self) -> bool {
+        self.poisoned
+    }
+
+    // any string is a valid key, the empty one included. it sorts before
+    // every other key, so it's the first thing a scan from "" yields.
+    pub async fn put(&mut self, key: &str, value: &str) -> Result<(), DBError> {
+        self.put_with_flags(key, value, 0).await
+    }
+
+    // flags are stored alongside the value and survive flushes and
+    // compactions; a later plain `put` resets them to 0
+    pub async fn put_with_flags(
+        &mut self,
+        key: &str,
+        value: &str,
+        flags: u8,
+    ) -> Result<(), DBError> {
+        self.write(key, Some(value), flags).await?;
+        event!(trace, key_len = key.len(), value_len = value.len(), "put");
+
+        Ok(())
+    }
+
+    // writes only when the key has no live value, tombstoned keys count as
+    // absent. `&mut self` makes the check and the write one step: callers
+    // sharing a db have to go through a lock, so two of them can't both see
+    // the key missing.
+    pub async fn put_if_absent(&mut self, key: &str, value: &str) -> Result<bool, DBError> {
+        if self.get(key).await?.is_some() {
+            return Ok(false);
+        }
+        self.put(key, value).await?;
+        Ok(true)
+    }
+
+    pub async fn delete(&mut self, key: &str) -> Result<(), DBError> {
+        self.write(key, None, 0).await?;
+        event!(trace, key_len = key.len(), "delete");
+
+        Ok(())
+    }
+
+    // deletes only while the live value is still `expected`, atomic for the
+    // same reason as `put_if_absent`
+    pub async fn delete_if(&mut self, key: &str, expected: &str) -> Result<bool, DBError> {
+        if self.get(key).await?.as_deref() != Some(expected) {
+            return Ok(false);
+        }
+        self.delete(key).await?;
+        Ok(true)
+    }
+
+    // namespaces are separate keyspaces in the one database, sharing its log
+    // and ssts. a namespaced key is stored as the namespace, a nul and the
+    // key, so the same key in two namespaces never collides, though a plain
+    // key written in that form can be read back through the namespace.
+    pub async fn put_ns(&mut self, ns: &str, key: &str, value: &str) -> Result<(), DBError> {
+        self.put(&namespace_key(ns, key)?, value).await
+    }
+
+    pub async fn get_ns(&self, ns: &str, key: &str) -> Result<Option<String>, DBError> {
+        self.get(&namespace_key(ns, key)?).await
+    }
+
+    pub async fn delete_ns(&mut self, ns: &str, key: &str) -> Result<(), DBError> {
+        self.delete(&namespace_key(ns, key)?).await
+    }
+
+    // deletes every live key in ns, returning how many. there are no range
+    // tombstones, so it's a tombstone per key, written after the scan.
+    pub async fn drop_namespace(&mut self, ns: &str) -> Result<u64, DBError> {
+        let prefix = namespace_key(ns, "")?;
+        let mut iter = self.scan_prefix(&prefix).await?;
+        let mut keys = Vec::new();
+        while let Some(key) = iter.next_key().await? {
+            keys.push(key);
+        }
+        drop(iter);
+        for key in &keys {
+            self.delete(key).await?;
+        }
+        Ok(keys.len() as u64)
+    }
+
+    // an operand for `DbOptions::merge_operator` to fold onto whatever
+    // value key holds when it's read, without reading it now. not for the
+    // skip list memtable, which can't keep the value under it.
+    pub async fn merge(&mut self, key: &str, operand: &str) -> Result<(), DBError> {
+        if self.options.merge_operator.is_none() {
+            return Err(DBError::InvalidInput(
+                "merge needs DbOptions::merge_operator".to_string(),
+            ));
+        }
+        if self.options.skip_list_memtable {
+            return Err(DBError::InvalidInput(
+                "the skip list memtable keeps no versions to merge onto".to_string(),
+            ));
+        }
+        self.write_timed(
+            &[(key, Some(operand), 0)],
+            self.options.durability,
+            true,
+            None,
+        )
+        .await?;
+        event!(trace, key_len = key.len(), "merge");
+
+        Ok(())
+    }
+
+    async fn write(&mut self, key: &str, value: Option<&str>, flags: u8) -> Result<(), DBError> {
+        self.write_timed(&[(key, value, flags)], self.options.durability, false, None)
+            .await
+    }
+
+    // a value that needn't be utf-8, for `get_raw` to hand back as it is.
+    // the bytes go straight to the value log and the record only points at
+    // them, so `get` and scans fail on it where the bytes aren't utf-8, and
+    // the change feed shows it with an empty value. not with replication,
+    // whose sink would get a pointer into a value log it doesn't have.
+    pub async fn put_raw(&mut self, key: &str, value: &[u8]) -> Result<(), DBError> {
+        self.check_poisoned()?;
+        if self.options.replication.is_some() {
+            return Err(DBError::InvalidInput(
+                "raw values stay in the local value log, they can't be replicated".to_string(),
+            ));
+        }
+        let appended = self.vlog.append(value).await;
+        let value_ref = self.poison_on_err(appended)?;
+        // the bytes have to be durable before the log points at them
+        if self.options.durability == Durability::Sync {
+            let synced = self.vlog.sync().await;
+            self.poison_on_err(synced)?;
+        }
+        self.write_timed(
+            &[(key, Some(""), 0)],
+            self.options.durability,
+            false,
+            Some(value_ref),
+        )
+        .await?;
+        event!(
+            trace,
+            key_len = key.len(),
+            value_len = value.len(),
+            "put_raw"
+        );
+
+        Ok(())
+    }
+
+    // applies batch as one write, made as durable as options ask instead of
+    // `DbOptions::durability`
+    pub async fn write_with_options(
+        &mut self,
+        batch: &WriteBatch,
+        options: WriteOptions,
+    ) -> Result<(), DBError> {
+        let durability = match options.sync {
+            Some(true) => Durability::Sync,
+            Some(false) => Durability::NoSync,
+            None => self.options.durability,
+        };
+        let records: Vec<_> = batch
+            .records()
+            .map(|(key, value)| (key, value, 0))
+            .collect();
+        self.write_timed(&records, durability, false, None).await
+    }
+
+    async fn write_timed(
+        &mut self,
+        records: &[(&str, Option<&str>, u8)],
+        durability: Durability,
+        merge: bool,
+        value_ref: Option<ValueRef>,
+    ) -> Result<(), DBError> {
+        let started = Instant::now();
+        let written = self
+            .write_records(records, durability, merge, value_ref)
+            .await;
+        if let Some(latency) = &self.latency {
+            latency.put.record(started.elapsed());
+        }
+        written
+    }
+
+    // records as (key, value, flags), with consecutive sequence numbers.
+    // with merge they're all merge operands, with value_ref their values are
+    // in the value log there.
+    async fn write_records(
+        &mut self,
+        records: &[(&str, Option<&str>, u8)],
+        durability: Durability,
+        merge: bool,
+        value_ref: Option<ValueRef>,
+    ) -> Result<(), DBError> {
+        self.check_poisoned()?;
+        if records.is_empty() {
+            return Ok(());
+        }
+        self.finish_background(false).await;
+        self.finish_flush(false).await?;
+        let first_seq = self.next_seq().await?;
+        let rollback = match self.options.replication {
+            Some(_) => Some(self.log.size().await?),
+            None => None,
+        };
+        // a failed append may leave half a record at the end of the log
+        let appended = self
+            .log
+            .append_with(
+                records
+                    .iter()
+                    .zip(first_seq..)
+                    .map(|(&(key, value, flags), seq)| PutRef {
+                        key,
+                        value,
+                        seq,
+                        flags,
+                        value_ref,
+                        merge,
+                    }),
+                durability,
+            )
+            .await;
+        self.poison_on_err(appended)?;
+        // owned copies only once the records are in the log
+        let puts: Vec<Put> = records
+            .iter()
+            .zip(first_seq..)
+            .map(|(&(key, value, flags), seq)| Put {
+                key: key.into(),
+                value: value.map(String::from),
+                seq,
+                flags,
+                value_ref,
+                merge,
+            })
+            .collect();
+        if let (Some(sink), Some(len)) = (self.options.replication.clone(), rollback) {
+            if let Err(err) = sink.replicate(&puts).await {
+                let truncated = self.log.truncate_to(len).await;
+                self.poison_on_err(truncated)?;
+                return Err(err);
+            }
+        }
+        self.next_seq = Some(first_seq + puts.len() as u64);
+        self.last_write = self.clock.now();
+        for put in puts {
+            if let Some(on_write) = &self.options.callbacks.on_write {
+                on_write(&put);
+            }
+            self.changes.publish(&put);
+            self.memtable.insert(put);
+        }
+        if self
+            .options
+            .flush_threshold
+            .is_some_and(|threshold| self.memtable.size_bytes() >= threshold)
+        {
+            let in_background = self.options.async_flush
+                && !self.options.skip_list_memtable
+                && self.options.value_separation_threshold.is_none();
+            // left for `run_background_once` when it's driven by hand
+            if in_background && !self.options.manual_background {
+                self.start_flush().await?;
+            } else if !in_background {
+                self.flush().await?;
+            }
+        }
+        Ok(())
+    }
+
+    pub async fn get(&self, key: &str) -> Result<Option<String>, DBError> {
+        let started = Instant::now();
+        let lookup = async {
+            match self.get_record(key).await? {
+                Some(put) => vlog::resolve_value(self.vlog.path(), put).await,
+                None => Ok(None),
+            }
+        };
+        let value = with_timeout(self.options.get_timeout, lookup).await;
+        if let Some(latency) = &self.latency {
+            latency.get.record(started.elapsed());
+        }
+        value
+    }
+
+    // the values of keys as they all stood at one sequence number. writes
+    // take `&mut self`, so none can land while this holds the db borrowed:
+    // behind a `RwLock`, this is one read lock for the whole batch where
+    // separate gets each take their own and may see writes in between.
+    pub async fn get_batch_consistent(
+        &self,
+        keys: &[&str],
+    ) -> Result<Vec<Option<String>>, DBError> {
+        self.check_poisoned()?;
+        let mut values = Vec::with_capacity(keys.len());
+        for key in keys {
+            values.push(self.get(key).await?);
+        }
+        Ok(values)
+    }
+
+    // the values of many keys at once, in the order of keys. they're looked
+    // up in key order, each sst is probed only for the keys no newer source
+    // has answered, through the file cache when there is one. with
+    // `parallel_reads` every sst is probed for the remaining keys at once
+    // instead. the read repair cache is read but not filled.
+    pub async fn bulk_get_random(&self, keys: &[String]) -> Result<Vec<Option<String>>, DBError> {
+        self.check_poisoned()?;
+        let mut order: Vec<usize> = (0..keys.len()).collect();
+        order.sort_unstable_by(|a, b| keys[*a].cmp(&keys[*b]));
+        let mut found: Vec<Option<Put>> = vec![None; keys.len()];
+        let mut pending = Vec::with_capacity(keys.len());
+        for idx in order {
+            match self.get_memory_record(&keys[idx]) {
+                Some(put) => found[idx] = Some(put),
+                None => pending.push(idx),
+            }
+        }
+        let files = self.files.as_deref();
+        if self.options.parallel_reads && self.ssts.len() > 1 {
+            let probes = self.ssts.iter().map(|sst| async {
+                let mut records = Vec::new();
+                for &idx in &pending {
+                    records.push(sst.get_record_in(&keys[idx], files).await);
+                }
+                records
+            });
+            for records in futures::future::join_all(probes).await {
+                for (&idx, record) in pending.iter().zip(records) {
+                    if let Some(Some(put)) = self.skip_missing(record)? {
+                        let newest = &mut found[idx];
+                        if newest.as_ref().is_none_or(|newest| put.seq > newest.seq) {
+                            *newest = Some(put);
+                        }
+                    }
+                }
+            }
+            pending.retain(|&idx| found[idx].is_none());
+        } else {
+            let mut remaining = Vec::with_capacity(pending.len());
+            for sst in &self.ssts {
+                if pending.is_empty() {
+                    break;
+                }
+                for &idx in &pending {
+                    let record = sst.get_record_in(&keys[idx], files).await;
+                    match self.skip_missing(record)? {
+                        Some(Some(put)) => found[idx] = Some(put),
+                        _ => remaining.push(idx),
+                    }
+                }
+                std::mem::swap(&mut pending, &mut remaining);
+                remaining.clear();
+            }
+        }
+        if let Some(base) = &self.base {
+            for idx in pending {
+                found[idx] = base_record(base, &keys[idx]).await?;
+            }
+        }
+        let mut values = Vec::with_capacity(keys.len());
+        for put in found {
+            values.push(match put {
+                Some(put) => vlog::resolve_value(self.vlog.path(), put).await?,
+                None => None,
+            });
+        }
+        Ok(values)
+    }
+
+    // the value, or default when there's no live one. nothing is written.
+    pub async fn get_or(&self, key: &str, default: &str) -> Result<String, DBError> {
+        self.get_or_else(key, || default.to_string()).await
+    }
+
+    // `get_or` with the default only made when it's needed
+    pub async fn get_or_else(
+        &self,
+        key: &str,
+        default: impl FnOnce() -> String,
+    ) -> Result<String, DBError> {
+        Ok(self.get(key).await?.unwrap_or_else(default))
+    }
+
+    // the value's bytes as stored, without making a String of them, so what
+    // `put_raw` wrote comes back whether it's utf-8 or not
+    pub async fn get_raw(&self, key: &str) -> Result<Option<Bytes>, DBError> {
+        let lookup = async {
+            match self
+                .get_record(key)
+                .await?
+                .map(|put| (put.value, put.value_ref))
+            {
+                Some((Some(_), Some(value_ref))) => Ok(Some(
+                    vlog::read_raw(self.vlog.path(), value_ref).await?.into(),
+                )),
+                Some((value, _)) => Ok(value.map(Bytes::from)),
+                None => Ok(None),
+            }
+        };
+        with_timeout(self.options.get_timeout, lookup).await
+    }
+
+    // None when the key is missing or deleted
+    pub async fn get_flags(&self, key: &str) -> Result<Option<u8>, DBError> {
+        Ok(self
+            .get_record(key)
+            .await?
+            .filter(|put| put.value.is_some())
+            .map(|put| put.flags))
+    }
+
+    // the retained versions of key as (seq, value), newest first, stopping
+    // at the newest delete. only compaction with `versions_to_keep` keeps
+    // more than the latest one. merge operands are listed as written.
+    pub async fn get_versions(&self, key: &str) -> Result<Vec<(u64, String)>, DBError> {
+        self.check_poisoned()?;
+        let mut records = self.memtable.get_versions(key);
+        for flushing in &self.flushing {
+            records.extend(flushing.memtable.get_versions(key));
+        }
+        for sst in &self.ssts {
+            records.extend(
+                self.skip_missing(sst.get_versions_in(key, self.files.as_deref()).await)?
+                    .unwrap_or_default(),
+            );
+        }
+        records.sort_by_key(|put| std::cmp::Reverse(put.seq));
+        records.dedup_by_key(|put| put.seq);
+
+        let mut versions = Vec::new();
+        for put in records {
+            let seq = put.seq;
+            match vlog::resolve_value(self.vlog.path(), put).await? {
+                Some(value) => versions.push((seq, value)),
+                None => return Ok(versions),
+            }
+        }
+        if let Some(base) = &self.base {
+            versions.extend(Box::pin(base.get_versions(key)).await?);
+        }
+        Ok(versions)
+    }
+
+    // with `skip_missing` an sst whose files are gone reads as empty, None
+    // here, rather than failing the read
+    fn skip_missing<T>(&self, result: Result<T, DBError>) -> Result<Option<T>, DBError> {
+        match result {
+            Err(DBError::MissingSst(_path)) if self.options.skip_missing => {
+                event!(warn, path = %_path.display(), "skipped missing sst");
+                Ok(None)
+            }
+            result => result.map(Some),
+        }
+    }
+
+    // the newest record for key, which may be a tombstone. a merge operand
+    // comes back folded.
+    async fn get_record(&self, key: &str) -> Result<Option<Put>, DBError> {
+        let found = self.get_local_record(key).await?;
+        match (&self.base, found) {
+            (Some(base), None) => base_record(base, key).await,
+            (_, Some(put)) if put.merge => self.fold_record(put).await.map(Some),
+            (_, found) => Ok(found),
+        }
+    }
+
+    // newest, an operand, folded onto the versions of its key under it
+    async fn fold_record(&self, newest: Put) -> Result<Put, DBError> {
+        let Some(operator) = &self.options.merge_operator else {
+            return Err(DBError::InvalidInput(format!(
+                "{:?} has merge operands and there's no DbOptions::merge_operator",
+                newest.key
+            )));
+        };
+        let key = newest.key.as_str();
+        let mut records = self.memtable.get_versions(key);
+        for flushing in &self.flushing {
+            records.extend(flushing.memtable.get_versions(key));
+        }
+        for sst in &self.ssts {
+            records.extend(
+                self.skip_missing(sst.get_versions_in(key, self.files.as_deref()).await)?
+                    .unwrap_or_default(),
+            );
+        }
+        records.sort_by_key(|put| std::cmp::Reverse(put.seq));
+        records.dedup_by_key(|put| put.seq);
+        if let Some(base) = &self.base {
+            if records.iter().all(|put| put.merge) {
+                records.extend(base_record(base, key).await?);
+            }
+        }
+        let value = fold_operands(operator.as_ref(), self.vlog.path(), key, records).await?;
+        Ok(Put {
+            value,
+            flags: 0,
+            value_ref: None,
+            merge: false,
+            ..newest
+        })
+    }
+
+    // from the memtables or the read repair cache, without touching an sst
+    fn get_memory_record(&self, key: &str) -> Option<Put> {
+        if let Some(put) = self.memtable.get_record(key) {
+            return Some(put);
+        }
+        // newest first
+        for flushing in self.flushing.iter().rev() {
+            if let Some(put) = flushing.memtable.get_record(key) {
+                return Some(put);
+            }
+        }
+        self.repaired
+            .lock()
+            .expect("repair lock poisoned")
+            .get(key)
+            .cloned()
+    }
+
+    async fn get_local_record(&self, key: &str) -> Result<Option<Put>, DBError> {
+        self.check_poisoned()?;
+        // a tombstone in a newer source hides anything older, so stop at the
+        // first source that knows about the key either way
+        let found = self.get_memory_record(key);
+        if found.is_some() {
+            return Ok(found);
+        }
+        if self.options.parallel_reads && self.ssts.len() > 1 {
+            let found = self.get_parallel(key).await?;
+            self.repair(key, &found, self.ssts.len());
+            return Ok(found);
+        }
+        for (idx, sst) in self.ssts.iter().enumerate() {
+            let found = sst.get_record_in(key, self.files.as_deref()).await;
+            if let Some(Some(put)) = self.skip_missing(found)? {
+                let found = Some(put);
+                self.repair(key, &found, idx + 1);
+                return Ok(found);
+            }
+        }
+        Ok(None)
+    }
+
+    // remembers a record that took reading `probed` ssts to find. it's the
+    // newest there is until an sst is added or removed, and anything written
+    // since is in the memtable, which is checked first.
+    fn repair(&self, key: &str, found: &Option<Put>, probed: usize) {
+        let Some(put) = found else {
+            return;
+        };
+        if self.options.read_repair.is_none_or(|min| probed < min) {
+            return;
+        }
+        let mut repaired = self.repaired.lock().expect("repair lock poisoned");
+        if repaired.len() >= REPAIR_CAPACITY {
+            repaired.clear();
+        }
+        repaired.insert(key.to_string(), put.clone());
+    }
+
+    // an sst no longer in the manifest, its files go once nothing reads it
+    fn retire(&self, sst: &Sst) {
+        sst.mark_obsolete();
+        if let Some(files) = &self.files {
+            files.forget(sst.data_path());
+        }
+    }
+
+    fn ssts_changed(&mut self) {
+        self.repaired
+            .get_mut()
+            .expect("repair lock poisoned")
+            .clear();
+    }
+
+    // probes run on their own tasks and finish in any order, so the answer is
+    // whichever record carries the highest sequence number
+    async fn get_parallel(&self, key: &str) -> Result<Option<Put>, DBError> {
+        let mut probes = JoinSet::new();
+        for sst in &self.ssts {
+            let sst = sst.clone();
+            let key = key.to_string();
+            let files = self.files.clone();
+            probes.spawn(async move { sst.get_record_in(&key, files.as_deref()).await });
+        }
+        let mut newest: Option<Put> = None;
+        while let Some(probe) = probes.join_next().await {
+            let found = probe.map_err(std::io::Error::other)?;
+            if let Some(Some(put)) = self.skip_missing(found)? {
+                if newest.as_ref().is_none_or(|newest| put.seq > newest.seq) {
+                    newest = Some(put);
+                }
+            }
+        }
+        Ok(newest)
+    }
+
+    // keys in [start, end), or to the end of the keyspace when end is None.
+    // the iterator holds its own references to the ssts it reads, so it stays
+    // valid across flushes and compactions.
+    pub async fn scan(&self, start: &str, end: Option<&str>) -> Result<DbIter, DBError> {
+        self.scan_with(start, end, |_| true).await
+    }
+
+    // a cursor from start to the end of the keyspace
+    pub async fn iter_from(&self, start: &str) -> Result<DbIter, DBError> {
+        self.scan(start, None).await
+    }
+
+    // every live key starting with prefix. ssts whose prefix bloom filter
+    // rules the prefix out aren't read at all.
+    pub async fn scan_prefix(&self, prefix: &str) -> Result<DbIter, DBError> {
+        let end = prefix_end(prefix);
+        self.scan_with(prefix, end.as_deref(), |meta| {
+            meta.may_contain_prefix(prefix)
+        })
+        .await
+    }
+
+    // `scan` as set out by `ScanOptions`. the smallest key after any key is
+    // that key followed by a nul, so exclusive starts and inclusive ends
+    // both come down to the usual [start, end).
+    pub async fn scan_options(&self, options: ScanOptions) -> Result<DbIter, DBError> {
+        let mut start = options.start;
+        if options.start_exclusive {
+            start.push('\0');
+        }
+        let end = options.end.map(|mut end| {
+            if options.end_inclusive {
+                end.push('\0');
+            }
+            end
+        });
+        let iter = self
+            .scan_with_versions(&start, end.as_deref(), |_| true, options.snapshot.is_some())
+            .await?
+            .with_snapshot(options.snapshot)
+            .keys_only(options.keys_only);
+        let iter = match options.reverse {
+            true => iter.into_reversed().await?,
+            false => iter,
+        };
+        Ok(iter.with_limit(options.limit))
+    }
+
+    async fn scan_with(
+        &self,
+        start: &str,
+        end: Option<&str>,
+        include: impl Fn(&SstMeta) -> bool + Copy,
+    ) -> Result<DbIter, DBError> {
+        self.scan_with_versions(start, end, include, false).await
+    }
+
+    async fn scan_with_versions(
+        &self,
+        start: &str,
+        end: Option<&str>,
+        include: impl Fn(&SstMeta) -> bool + Copy,
+        all_versions: bool,
+    ) -> Result<DbIter, DBError> {
+        self.check_poisoned()?;
+        let deadline = self
+            .options
+            .scan_timeout
+            .map(|limit| Instant::now() + limit);
+        let mut sources = Vec::with_capacity(self.ssts.len() + self.flushing.len() + 2);
+        sources.push(Source::Memtable(
+            self.memtable.range(start, end).into_iter(),
+        ));
+        for flushing in self.flushing.iter().rev() {
+            sources.push(Source::Memtable(
+                flushing.memtable.range(start, end).into_iter(),
+            ));
+        }
+        for sst in &self.ssts {
+            let iter = async {
+                if !include(sst.meta().await?) {
+                    return Ok(None);
+                }
+                match end {
+                    Some(end) => sst.range_iter(start, end).await.map(Some),
+                    None => sst.iter_from(start).await.map(Some),
+                }
+            };
+            if let Some(Some(iter)) = self.skip_missing(iter.await)? {
+                sources.push(Source::Sst(Box::new(iter)));
+            }
+        }
+        if let Some(base) = &self.base {
+            let iter = Box::pin(base.scan_with(start, end, include)).await?;
+            sources.push(Source::Base(Box::new(iter)));
+        }
+        // folding an operand takes the versions under it
+        let operator = self.options.merge_operator.clone();
+        let merge = match all_versions || operator.is_some() {
+            true => MergeIter::all_versions(sources),
+            false => MergeIter::new(sources),
+        }
+        .checked(self.options.paranoid_checks)
+        .yielding(yield_every(&self.options));
+        Ok(DbIter::new(
+            merge,
+            end.map(String::from),
+            deadline,
+            self.vlog.path().to_path_buf(),
+        )
+        .with_merge_operator(operator))
+    }
+
+    // an estimate of how many live keys start with prefix, cheap next to
+    // counting them with a scan. ssts the prefix bloom rules out are skipped,
+    // and large ranges within an sst are sized from its index rather than
+    // read. the sources are added up, so a key deleted or written again in
+    // a newer one than it's in counts more than once until compaction
+    // merges them; the memtable's count is exact.
+    pub async fn estimate_keys_with_prefix(&self, prefix: &str) -> Result<u64, DBError> {
+        self.check_poisoned()?;
+        let end = prefix_end(prefix);
+        let end = end.as_deref();
+        let mut estimate = self.memtable.range(prefix, end).len() as u64;
+        for flushing in &self.flushing {
+            estimate += flushing.memtable.range(prefix, end).len() as u64;
+        }
+        for sst in &self.ssts {
+            let len = async {
+                if !sst.meta().await?.may_contain_prefix(prefix) {
+                    return Ok(0);
+                }
+                sst.estimate_range_len(prefix, end).await
+            };
+            estimate += self.skip_missing(len.await)?.unwrap_or(0);
+        }
+        if let Some(base) = &self.base {
+            estimate += Box::pin(base.estimate_keys_with_prefix(prefix)).await?;
+        }
+        Ok(estimate)
+    }
+
+    // the live key nearest to key in the direction given, key itself
+    // included, with its value. deleted keys are passed over. scans only run
+    // forward, so looking back reads a window from the sst block boundary
+    // before key, widening it a block at a time while it finds nothing live.
+    pub async fn get_closest(
+        &self,
+        key: &str,
+        direction: Seek,
+    ) -> Result<Option<(String, String)>, DBError> {
+        if direction == Seek::AtOrAfter {
+            return self.scan(key, None).await?.next().await;
+        }
+        // just past key, to take it in
+        let mut end = format!("{}\0", key);
+        let mut boundaries = Vec::new();
+        for sst in &self.ssts {
+            if let Some(Some(meta)) = self.skip_missing(sst.meta().await.map(Some))? {
+                boundaries.extend(
+                    meta.block_keys()
+                        .filter(|block| *block < end.as_str())
+                        .map(String::from),
+                );
+            }
+        }
+        boundaries.sort_unstable();
+        boundaries.dedup();
+        loop {
+            let start = boundaries.pop().unwrap_or_default();
+            let mut iter = self.scan(&start, Some(&end)).await?;
+            let mut closest = None;
+            while let Some(entry) = iter.next().await? {
+                closest = Some(entry);
+            }
+            if closest.is_some() || start.is_empty() {
+                return Ok(closest);
+            }
+            end = start;
+        }
+    }
+
+    // live keys in [start, end), without reading separated values back
+    pub async fn range_count(&self, start: &str, end: &str) -> Result<u64, DBError> {
+        let mut iter = self.scan(start, Some(end)).await?;
+        let mut count = 0;
+        while iter.next_key().await?.is_some() {
+            count += 1;
+        }
+        Ok(count)
+    }
+
+    // writes the newest record of every key in [start, end) to a standalone
+    // sst at path, another database can take it in as it is. separated
+    // values are copied in from the value log. tombstones go in too with
+    // `keep_tombstones`, so deletes carry over into a database that may
+    // hold the keys already.
+    pub async fn export_range(
+        &self,
+        start: &str,
+        end: &str,
+        path: impl AsRef<Path>,
+        keep_tombstones: bool,
+    ) -> Result<Sst, DBError> {
+        let mut iter = self.scan(start, Some(end)).await?.with_tombstones();
+        let mut writer = sst_writer(&self.options, self.clock.as_ref(), path.as_ref(), 0).await?;
+        let written = async {
+            while let Some(put) = iter.next_record().await? {
+                if put.value.is_none() && !keep_tombstones {
+                    continue;
+                }
+                let put = Put {
+                    key: put.key.clone(),
+                    seq: put.seq,
+                    flags: put.flags,
+                    value: vlog::resolve_value(self.vlog.path(), put).await?,
+                    value_ref: None,
+                    merge: false,
+                };
+                writer.add(&put).await?;
+            }
+            Ok(())
+        };
+        if let Err(err) = written.await {
+            writer.abandon().await;
+            return Err(err);
+        }
+        writer.finish().await
+    }
+
+    // links an sst built elsewhere, by `export_range` say, into the database
+    // without rewriting it. path is without extension, like the ssts' own.
+    // it's verified first, and must hold its values inline. its records
+    // shadow and are shadowed by sequence number like any others, so it goes
+    // in behind every overlapping sst with newer records and ahead of those
+    // with older ones; sequence numbers that interleave with an overlapping
+    // sst can't be placed and fail with `InvalidInput`.
+    pub async fn ingest_sst(&mut self, path: impl AsRef<Path>) -> Result<(), DBError> {
+        let path = path.as_ref();
+        // the memtable is read before any sst, it can't hold anything older
+        self.flush().await?;
+        self.check_poisoned()?;
+        self.finish_background(true).await;
+        let external = Sst::open(path).await?;
+        if let Some(problem) = external.verify().await.1.into_iter().next() {
+            return Err(DBError::InvalidInput(format!("can't ingest: {problem}")));
+        }
+        let meta = external.meta().await?;
+        let mut after_newer = 0;
+        let mut before_older = self.ssts.len();
+        let mut interleaves = false;
+        for (idx, sst) in self.ssts.iter().enumerate() {
+            let existing = sst.meta().await?;
+            if !existing.overlaps(meta) {
+                continue;
+            }
+            if existing.min_seq() > meta.max_seq() {
+                after_newer = idx + 1;
+            } else if existing.max_seq() < meta.min_seq() {
+                before_older = before_older.min(idx);
+            } else {
+                interleaves = true;
+            }
+        }
+        if interleaves || before_older < after_newer {
+            return Err(DBError::InvalidInput(format!(
+                "can't ingest {}: its sequence numbers interleave with existing ssts",
+                path.display()
+            )));
+        }
+        let target = self.next_sst_path(meta.level()).await?;
+        for (from, to) in [
+            (external.data_path(), target.with_extension("data")),
+            (external.index_path(), target.with_extension("index")),
+        ] {
+            // a copy only when a link can't be made, across file systems say
+            if tokio::fs::hard_link(from, &to).await.is_err() {
+                tokio::fs::copy(from, &to).await?;
+            }
+        }
+        let next_seq = self.next_seq().await?.max(meta.max_seq() + 1);
+        let sst = Arc::new(Sst::open(&target).await?);
+        self.ssts_changed();
+        self.ssts.insert(after_newer, sst.clone());
+        self.next_seq = Some(next_seq);
+        if let Err(err) = self.save_state().await {
+            self.ssts.remove(after_newer);
+            sst.mark_obsolete();
+            return Err(err);
+        }
+        Ok(())
+    }
+
+    // bytes on disk per byte of live data, 1.0 once compaction has left one
+    // copy of each live record and nothing else. on disk is the ssts, the
+    // log and the value log; live is the size of every record a scan sees,
+    // with its separated value. infinite when nothing is live but something
+    // is still on disk.
+    pub async fn space_amplification(&self) -> Result<f64, DBError> {
+        let mut on_disk: u64 = self.ssts.iter().map(|sst| sst.size()).sum();
+        for path in [self.log_dir.join("log"), self.vlog.path().to_path_buf()] {
+            match tokio::fs::metadata(&path).await {
+                Ok(meta) => on_disk += meta.len(),
+                Err(err) if err.kind() == std::io::ErrorKind::NotFound => {}
+                Err(err) => return Err(err.into()),
+            }
+        }
+        let mut iter = self.scan("", None).await?;
+        let mut live: u64 = 0;
+        while let Some(put) = iter.next_record().await? {
+            // as written to an sst, newline included
+            live += serde_json::to_vec(&put)?.len() as u64 + 1;
+            live += put.value_ref.map_or(0, |value_ref| value_ref.len);
+        }
+        Ok(match (on_disk, live) {
+            (0, 0) => 1.0,
+            (_, 0) => f64::INFINITY,
+            (on_disk, live) => on_disk as f64 / live as f64,
+        })
+    }
+
+    // roughly how many bytes compaction has to rewrite before the ssts are
+    // back to not overlapping, for deciding when to call `maybe_compact`.
+    // once there are `compaction_trigger` ssts that's all of them, the merge
+    // `maybe_compact` would start. below it, only the ssts whose key range
+    // overlaps another's count. 0 with one sst or none.
+    pub async fn compaction_debt(&self) -> Result<u64, DBError> {
+        let trigger = self.compaction_trigger();
+        if self.ssts.len() < 2 {
+            return Ok(0);
+        }
+        if self.ssts.len() >= trigger {
+            return Ok(self.ssts.iter().map(|sst| sst.size()).sum());
+        }
+        let mut metas = Vec::with_capacity(self.ssts.len());
+        for sst in &self.ssts {
+            metas.push(sst.meta().await?);
+        }
+        let mut debt = 0;
+        for (idx, sst) in self.ssts.iter().enumerate() {
+            let overlapping = metas
+                .iter()
+                .enumerate()
+                .any(|(other, meta)| other != idx && meta.overlaps(metas[idx]));
+            if overlapping {
+                debt += sst.size();
+            }
+        }
+        Ok(debt)
+    }
+
+    // the smallest live key. the scan seeks each source to its first record
+    // and steps over tombstones, so only the leading run of deleted keys is read.
+    pub async fn first_key(&self) -> Result<Option<String>, DBError> {
+        self.scan("", None).await?.next_key().await
+    }
+
+    // the largest live key. there's no reverse iteration, so this scans the
+    // tail from the latest block start of any sst, moving back one block
+    // start at a time while everything after it turns out to be deleted.
+    pub async fn last_key(&self) -> Result<Option<String>, DBError> {
+        let mut starts = Vec::new();
+        for sst in &self.ssts {
+            starts.extend(sst.meta().await?.block_keys().map(String::from));
+        }
+        starts.sort_unstable();
+        starts.dedup();
+        // from the very start last, whatever precedes the first block start
+        let mut from = starts.last().cloned().unwrap_or_default();
+        loop {
+            let mut iter = self.scan(&from, None).await?;
+            let mut last = None;
+            while let Some(key) = iter.next_key().await? {
+                last = Some(key);
+            }
+            if last.is_some() || from.is_empty() {
+                return Ok(last);
+            }
+            starts.pop();
+            from = starts.last().cloned().unwrap_or_default();
+        }
+    }
+
+    // where sst `id` at `level` goes under `root`
+    fn sst_path(&self, root: &Path, level: usize, id: u64) -> PathBuf {
+        let dir = if self.options.level_dirs {
+            level_dir(root, level)
+        } else {
+            root.to_path_buf()
+        };
+        dir.join(format!("{:08}", id))
+    }
+
+    // `sst_path`, creating the level directory if needed
+    async fn sst_path_in(&self, root: &Path, level: usize, id: u64) -> Result<PathBuf, DBError> {
+        let path = self.sst_path(root, level, id);
+        if self.options.level_dirs {
+            tokio::fs::create_dir_all(level_dir(root, level)).await?;
+        }
+        Ok(path)
+    }
+
+    async fn next_sst_path(&mut self, level: usize) -> Result<PathBuf, DBError> {
+        let id = self.next_sst_id.fetch_add(1, Ordering::SeqCst);
+        self.sst_path_in(&self.dir, level, id).await
+    }
+
+    pub async fn flush(&mut self) -> Result<(), DBError> {
+        self.finish_flush(true).await?;
+        if self.memtable.is_empty() {
+            return Ok(());
+        }
+        self.check_poisoned()?;
+        self.finish_background(false).await;
+        let started = Instant::now();
+        self.flush_memtable().await?;
+        let truncated = self.log.truncate().await;
+        self.poison_on_err(truncated)?;
+        if let Some(latency) = &self.latency {
+            latency.flush.record(started.elapsed());
+        }
+        if let Some(on_flush) = &self.options.callbacks.on_flush {
+            on_flush(&self.ssts[0]);
+        }
+        if self.options.auto_drop_shadowed {
+            self.drop_shadowed_ssts().await?;
+        }
+        Ok(())
+    }
+
+    // drops, without merging anything, every sst all of whose keys have a
+    // newer record in a newer sst that every live snapshot sees as well,
+    // returning how many went. an sst whose first or last key falls in no
+    // newer sst's key range can't be, and is passed over without reading
+    // it. nothing goes while `versions_to_keep` wants older versions kept.
+    pub async fn drop_shadowed_ssts(&mut self) -> Result<usize, DBError> {
+        self.check_poisoned()?;
+        self.finish_background(true).await;
+        if self.options.versions_to_keep > 1 {
+            return Ok(0);
+        }
+        let oldest = self.snapshots.oldest();
+        let mut kept: Vec<Arc<Sst>> = Vec::with_capacity(self.ssts.len());
+        let mut dropped = Vec::new();
+        for sst in &self.ssts {
+            // only what's staying can hide anything
+            if !kept.is_empty() && is_shadowed(sst, &kept, oldest, self.files.as_deref()).await? {
+                dropped.push(sst.clone());
+            } else {
+                kept.push(sst.clone());
+            }
+        }
+        if dropped.is_empty() {
+            return Ok(0);
+        }
+        self.ssts_changed();
+        let before = std::mem::replace(&mut self.ssts, kept);
+        if let Err(err) = self.save_state().await {
+            self.ssts = before;
+            return Err(err);
+        }
+        for sst in &dropped {
+            self.retire(sst);
+        }
+        event!(info, ssts = dropped.len(), "dropped shadowed ssts");
+        Ok(dropped.len())
+    }
+
+    // loads a batch already sorted by key straight into a new sst, newer
+    // than everything else, instead of through the log and the memtable.
+    // the memtable is flushed first so none of its older records can shadow
+    // the batch. keys out of order or repeated fail with `InvalidInput`
+    // before anything becomes visible.
+    pub async fn put_batch_sorted(
+        &mut self,
+        entries: impl Iterator<Item = (String, String)>,
+    ) -> Result<(), DBError> {
+        self.flush().await?;
+        self.check_poisoned()?;
+        self.finish_background(false).await;
+        let mut seq = self.next_seq().await?;
+        let path = self.next_sst_path(0).await?;
+        let mut writer = sst_writer(&self.options, self.clock.as_ref(), &path, 0).await?;
+        let mut replicated = Vec::new();
+        for (key, value) in entries {
+            let put = Put {
+                key,
+                value: Some(value),
+                seq,
+                flags: 0,
+                value_ref: None,
+                merge: false,
+            };
+            if let Err(err) = writer.add(&put).await {
+                writer.abandon().await;
+                return Err(err);
+            }
+            if self.options.replication.is_some() || self.changes.has_subscribers() {
+                replicated.push(put);
+            }
+            seq += 1;
+        }
+        if writer.is_empty() {
+            writer.abandon().await;
+            return Ok(());
+        }
+        let sst = writer.finish().await?;
+        if let Some(sink) = self.options.replication.clone() {
+            if let Err(err) = sink.replicate(&replicated).await {
+                // not in the manifest yet, so nothing has seen it
+                sst.mark_obsolete();
+                return Err(err);
+            }
+        }
+        self.ssts_changed();
+        self.ssts.insert(0, Arc::new(sst));
+        self.next_seq = Some(seq);
+        let saved = self.save_state().await;
+        self.poison_on_err(saved)?;
+        self.last_write = self.clock.now();
+        for put in &replicated {
+            self.changes.publish(put);
+        }
+        Ok(())
+    }
+
+    // flushes a non-empty memtable once no write has come in for the
+    // `idle_flush` interval, so a quiet db doesn't keep its last writes only
+    // in the log indefinitely. `spawn_idle_flush` calls it periodically,
+    // returns whether it flushed.
+    pub async fn flush_if_idle(&mut self) -> Result<bool, DBError> {
+        if !self.idle() {
+            return Ok(false);
+        }
+        self.flush().await?;
+        Ok(true)
+    }
+
+    fn idle(&self) -> bool {
+        let Some(interval) = self.options.idle_flush else {
+            return false;
+        };
+        let idle = self
+            .clock
+            .now()
+            .duration_since(self.last_write)
+            .unwrap_or_default();
+        !self.memtable.is_empty() && idle >= interval
+    }
+
+    // starts a task calling `flush_if_idle` on db every half `idle_flush`
+    // interval, until db is dropped or poisoned. None when idle flushing is
+    // off, or left to `run_background_once` with `manual_background`.
+    pub async fn spawn_idle_flush(db: &Arc<RwLock<Db>>) -> Option<JoinHandle<()>> {
+        let options = &db.read().await.options;
+        let interval = options.idle_flush.filter(|_| !options.manual_background)?;
+        let db = Arc::downgrade(db);
+        Some(tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval / 2);
+            ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+            loop {
+                ticker.tick().await;
+                let Some(db) = db.upgrade() else { return };
+                // the write lock only once there's something to flush
+                if !db.read().await.idle() {
+                    continue;
+                }
+                let flushed = db.write().await.flush_if_idle().await;
+                if let Err(_err) = flushed {
+                    event!(warn, error = %_err, "idle flush failed");
+                    return;
+                }
+            }
+        }))
+    }
+
+    // the sst at `level` to compact into the one below, picked as
+    // `compaction_pick_by_overlap` says. None when the level is empty.
+    pub async fn pick_compaction_input(&self, level: usize) -> Result<Option<Arc<Sst>>, DBError> {
+        let mut candidates = Vec::new();
+        let mut next_level = Vec::new();
+        for sst in &self.ssts {
+            match sst.meta().await?.level() {
+                l if l == level => candidates.push(sst.clone()),
+                l if l == level + 1 => next_level.push(sst.clone()),
+                _ => {}
+            }
+        }
+        pick_compaction_input(
+            &candidates,
+            &next_level,
+            self.options.compaction_pick_by_overlap,
+        )
+        .await
+    }
+
+    // drops the oldest ssts whose data was all written before cutoff, as a
+    // whole and without reading them, and returns how many went. creation
+    // times only grow from the oldest sst to the newest, so what's dropped
+    // is always a run of the oldest, with nothing older left behind that
+    // their records were hiding. ssts with no creation time are kept.
+    pub async fn drop_ssts_before(&mut self, cutoff: SystemTime) -> Result<usize, DBError> {
+        self.check_poisoned()?;
+        self.finish_background(true).await;
+        let mut keep = self.ssts.len();
+        while keep > 0
+            && self.ssts[keep - 1]
+                .meta()
+                .await?
+                .created()
+                .is_some_and(|created| created < cutoff)
+        {
+            keep -= 1;
+        }
+        self.ssts_changed();
+        let dropped: Vec<_> = self.ssts.drain(keep..).collect();
+        if dropped.is_empty() {
+            return Ok(0);
+        }
+        if let Err(err) = self.save_state().await {
+            self.ssts.extend(dropped);
+            return Err(err);
+        }
+        for sst in &dropped {
+            self.retire(sst);
+        }
+        Ok(dropped.len())
+    }
+
+    // empties the database in place. the memtable is flushed first so that
+    // everything is in ssts and the log is empty, then a manifest with no
+    // ssts replaces the old one in one atomic write: a crash before that
+    // leaves the old contents, after it an empty database. the old ssts go
+    // once nothing reads them. the value log and the checkpoints taken by
+    // `flush_all_and_checkpoint` are removed too, so iterators still open
+    // over separated values stop being able to read them.
+    pub async fn truncate(&mut self) -> Result<(), DBError> {
+        self.check_poisoned()?;
+        if self.base.is_some() {
+            return Err(DBError::InvalidInput(
+                "an overlay can't be truncated on its own".to_string(),
+            ));
+        }
+        self.flush().await?;
+        self.finish_background(true).await;
+        let next_sst_id = self.next_sst_id.load(Ordering::SeqCst);
+        let empty = self.manifest_for(&[], next_sst_id)?;
+        self.manifest.commit(&self.dir, empty).await?;
+        self.ssts_changed();
+        for sst in std::mem::take(&mut self.ssts) {
+            self.retire(&sst);
+        }
+        self.memtable.clear();
+        let reset = async {
+            self.log.truncate().await?;
+            if self.vlog.path().exists() {
+                tokio::fs::remove_file(self.vlog.path()).await?;
+            }
+            self.vlog = ValueLog::new(self.dir.join("vlog"));
+            // their sequence numbers are about to be handed out again
+            let checkpoints = self.dir.join("checkpoints");
+            if checkpoints.exists() {
+                tokio::fs::remove_dir_all(checkpoints).await?;
+            }
+            manifest::store_seq(&self.dir, 0).await
+        }
+        .await;
+        self.poison_on_err(reset)?;
+        self.seq_floor = 0;
+        self.next_seq = None;
+        Ok(())
+    }
+
+    // fsyncs the log, making every write so far durable without a flush.
+    // only needed with `Durability::NoSync`, other writes are already synced
+    // or never logged.
+    pub async fn sync_wal(&self) -> Result<(), DBError> {
+        self.check_poisoned()?;
+        self.log.sync().await
+    }
+
+    // waits for background compaction and flushes the memtable, so nothing
+    // is left to replay from the log on the next open
+    pub async fn close(mut self) -> Result<(), DBError> {
+        self.finish_background(true).await;
+        self.flush().await
+    }
+
+    // `close` and then `open` on the same directory with the same options,
+    // for a fresh handle with everything durable in ssts and nothing left
+    // to replay. snapshots taken from the old handle no longer hold back
+    // compaction. an overlay can't be reopened this way.
+    pub async fn reopen(mut self) -> Result<Db, DBError> {
+        self.check_poisoned()?;
+        if self.base.is_some() {
+            return Err(DBError::InvalidInput(
+                "an overlay can't be reopened on its own".to_string(),
+            ));
+        }
+        self.finish_background(true).await;
+        self.flush().await?;
+        self.log.sync().await?;
+        let (dir, options) = (self.dir.clone(), self.options.clone());
+        drop(self);
+        Db::open(dir, options).await
+    }
+
+    // swaps in an empty memtable and log and writes the full ones out on
+    // their own task, after waiting for the oldest flushes while the queue
+    // is full
+    async fn start_flush(&mut self) -> Result<(), DBError> {
+        self.finish_flush(false).await?;
+        while self.flushing.len() >= self.options.max_flushing_memtables.max(1) {
+            self.finish_oldest_flush().await?;
+        }
+        let path = self.next_sst_path(0).await?;
+        let writer = sst_writer(&self.options, self.clock.as_ref(), &path, 0).await?;
+        let id = self.flushing.back().map_or(0, |newest| newest.id + 1);
+        let rotated = self.log.rotate(&flushing_log(&self.log_dir, id)).await;
+        self.poison_on_err(rotated)?;
+        let fresh = self.fresh_memtable();
+        let memtable = Arc::new(std::mem::replace(&mut self.memtable, fresh));
+        let task = {
+            let memtable = memtable.clone();
+            tokio::spawn(async move {
+                let entries = memtable
+                    .iter_sorted()
+                    .expect("the skip list memtable flushes in line");
+                write_entries(writer, entries).await
+            })
+        };
+        event!(debug, keys = memtable.len(), "background flush started");
+        self.flushing.push_back(Flushing { memtable, task, id });
+        Ok(())
+    }
+
+    // puts the ssts of finished background flushes in place, oldest first,
+    // stopping at the first still running unless wait is set, when it
+    // waits for every one
+    async fn finish_flush(&mut self, wait: bool) -> Result<(), DBError> {
+        while let Some(oldest) = self.flushing.front() {
+            if !wait && !oldest.task.is_finished() {
+                break;
+            }
+            self.finish_oldest_flush().await?;
+        }
+        Ok(())
+    }
+
+    // waits for the oldest background flush, then puts its sst in place and
+    // lets its log go. if it failed the db is poisoned, its records are
+    // still in the flushing log for the next open.
+    async fn finish_oldest_flush(&mut self) -> Result<(), DBError> {
+        let Some(Flushing { task, id, .. }) = self.flushing.pop_front() else {
+            return Ok(());
+        };
+        let written = task
+            .await
+            .unwrap_or_else(|err| Err(std::io::Error::other(err).into()));
+        let sst = self.poison_on_err(written)?;
+        self.ssts_changed();
+        self.ssts.insert(0, Arc::new(sst));
+        let saved = self.save_state().await;
+        self.poison_on_err(saved)?;
+        let removed = tokio::fs::remove_file(flushing_log(&self.log_dir, id)).await;
+        self.poison_on_err(removed.map_err(DBError::from))?;
+        if let Some(on_flush) = &self.options.callbacks.on_flush {
+            on_flush(&self.ssts[0]);
+        }
+        Ok(())
+    }
+
+    // the memtable into a new L0 sst, leaving the log alone
+    async fn flush_memtable(&mut self) -> Result<(), DBError> {
+        timer!(started);
+        let path = self.next_sst_path(0).await?;
+        let sst = match self.options.value_separation_threshold {
+            Some(threshold) => {
+                let data = self.separate_values(threshold).await;
+                let data = self.poison_on_err(data)?;
+                Sst::construct_with(
+                    sst_writer(&self.options, self.clock.as_ref(), &path, 0).await?,
+                    data.into_iter(),
+                )
+                .await?
+            }
+            None => {
+                self.write_memtable(sst_writer(&self.options, self.clock.as_ref(), &path, 0).await?)
+                    .await?
+            }
+        };
+        event!(
+            info,
+            keys = self.memtable.len(),
+            bytes = sst.size(),
+            elapsed_us = started.elapsed().as_micros() as u64,
+            "flush"
+        );
+        self.ssts_changed();
+        self.ssts.insert(0, Arc::new(sst));
+        let saved = self.save_state().await;
+        self.poison_on_err(saved)?;
+        self.memtable = self.fresh_memtable();
+        Ok(())
+    }
+
+    // an empty memtable of the kind the options ask for, holding on to what
+    // overwrites replace while a snapshot may still read it
+    fn fresh_memtable(&self) -> Memtable {
+        let mut memtable = new_memtable(&self.options);
+        if self.snapshots.oldest().is_some() {
+            memtable.keep_versions();
+        }
+        memtable
+    }
+
+    // straight from the borrowed entries where the memtable can lend them
+    async fn write_memtable(&self, writer: SstWriter) -> Result<Sst, DBError> {
+        match self.memtable.iter_sorted() {
+            Some(entries) => write_entries(writer, entries).await,
+            None => Sst::construct_with(writer, self.memtable.iter()).await,
+        }
+    }
+
+    // the memtable's records, with values over the threshold moved out to
+    // the value log. a failure here leaves the value log's length unknown.
+    async fn separate_values(&mut self, threshold: usize) -> Result<Vec<Put>, DBError> {
+        let mut data = Vec::with_capacity(self.memtable.len());
+        for mut put in self.memtable.iter() {
+            if let Some(value) = put.value.as_mut().filter(|value| value.len() > threshold) {
+                put.value_ref = Some(self.vlog.append(value.as_bytes()).await?);
+                value.clear();
+            }
+            data.push(put);
+        }
+        // the values have to be durable before anything points at them
+        self.vlog.sync().await?;
+        Ok(data)
+    }
+
+    // merges every sst into a single L1 sst. the inputs are only marked obsolete here;
+    // their files are removed once in-flight scans let go of them. nothing is
+    // older than the merged output, so tombstones can be dropped.
+    pub async fn compact(&mut self) -> Result<(), DBError> {
+        self.check_poisoned()?;
+        if self.ssts.len() < 2 {
+            return Ok(());
+        }
+        self.compact_all(self.options.compaction_skip_disjoint)
+            .await
+    }
+
+    // brings a database written before the manifest, sequence numbers and
+    // checksums up to the current layout, log and ssts alike. the old layout
+    // reads fine as it is, this rewrites it all once through `major_compact`
+    // so `verify` can check it and nothing is bootstrapped or sorted out by
+    // file order anymore.
+    pub async fn migrate(dir: impl AsRef<Path>, options: DbOptions) -> Result<Db, DBError> {
+        let mut db = Db::open(dir, options).await?;
+        db.major_compact().await?;
+        Ok(db)
+    }
+
+    // compacts level by level until every level is within its size (see
+    // `DbOptions::level_base_bytes`) and L0 has fewer than
+    // `compaction_trigger` ssts, returning how many compactions that took.
+    // each moves one sst, and whatever at its level overlaps it, into the
+    // level below, merged with what it overlaps there. all of L0 goes at
+    // once. unlike `compact` only the data over a level's size is rewritten,
+    // though a key may then be kept in several levels until it reaches the
+    // last.
+    pub async fn compact_leveled(&mut self) -> Result<usize, DBError> {
+        self.check_poisoned()?;
+        self.finish_background(true).await;
+        let mut compactions = 0;
+        while let Some(level) = self.level_to_compact().await? {
+            self.compact_level(level).await?;
+            compactions += 1;
+        }
+        Ok(compactions)
+    }
+
+    // the tidy up an operator runs: when any sst may hold versions or
+    // tombstones that no live snapshot needs anymore (see `min_seq_in_use`),
+    // every sst is merged in one compaction that drops them, which covers
+    // whatever compaction was due as well. otherwise it runs the leveled
+    // compactions that are due. returns how many compactions it ran.
+    pub async fn maintain(&mut self) -> Result<usize, DBError> {
+        self.check_poisoned()?;
+        self.finish_background(true).await;
+        if self.holds_garbage().await? {
+            self.compact_all(false).await?;
+            return Ok(1);
+        }
+        self.compact_leveled().await
+    }
+
+    // whether an sst may hold a version compaction would drop: an older
+    // version of a key right behind a newer one in the same sst, a version
+    // hidden by an overlapping newer sst, or a tombstone with nothing left
+    // to hide. ssts without stats are assumed to. only the oldest live
+    // snapshot is tracked, so records newer than it are all still needed.
+    async fn holds_garbage(&self) -> Result<bool, DBError> {
+        let in_use = self.min_seq_in_use();
+        for (idx, sst) in self.ssts.iter().enumerate() {
+            let meta = sst.meta().await?;
+            if meta.min_seq() > in_use {
+                continue;
+            }
+            let in_itself = meta.stats().is_none_or(|stats| {
+                stats.overwrites > 0 || (stats.tombstones > 0 && self.base.is_none())
+            });
+            if in_itself || overlaps_any(sst, &self.ssts[..idx], false).await? {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    // what `compact_leveled` would do first, without doing it: the ssts it
+    // would merge and into which level, with the bytes it would read and a
+    // guess at the bytes it would write. None when no level is due.
+    pub async fn plan_compaction(&self) -> Result<Option<CompactionPlan>, DBError> {
+        self.check_poisoned()?;
+        let Some(level) = self.level_to_compact().await? else {
+            return Ok(None);
+        };
+        let compaction = self.level_compaction(level).await?;
+        let mut input_bytes = 0;
+        let mut output_bytes = 0.0;
+        for sst in &compaction.inputs {
+            input_bytes += sst.size();
+            // the share of records a merge would drop, going by the stats of
+            // each sst alone. versions of a key spread over several ssts
+            // aren't seen, so it errs high.
+            let dropped = sst.stats().await?.map_or(0.0, |stats| {
+                let tombstones = match compaction.keep_tombstones {
+                    true => 0.0,
+                    false => stats.tombstone_ratio(),
+                };
+                stats.overwrite_ratio() + tombstones
+            });
+            output_bytes += sst.size() as f64 * (1.0 - dropped).max(0.0);
+        }
+        Ok(Some(CompactionPlan {
+            level,
+            target_level: level + 1,
+            inputs: compaction
+                .inputs
+                .iter()
+                .map(|sst| sst.data_path().to_path_buf())
+                .collect(),
+            input_bytes,
+            estimated_output_bytes: output_bytes as u64,
+        }))
+    }
+
+    // bytes of ssts at each level, L0 first, for every level up to
+    // `max_levels` or the deepest holding an sst
+    pub async fn level_sizes(&self) -> Result<Vec<u64>, DBError> {
+        let mut sizes = vec![0; self.max_levels()];
+        for sst in &self.ssts {
+            let level = sst.meta().await?.level();
+            if level >= sizes.len() {
+                sizes.resize(level + 1, 0);
+            }
+            sizes[level] += sst.size();
+        }
+        Ok(sizes)
+    }
+
+    // how much `compact_leveled` lets level hold. None for L0, which goes by
+    // sst count, and for the last level, which holds whatever reaches it.
+    fn level_target(&self, level: usize) -> Option<u64> {
+        if level == 0 || level + 1 >= self.max_levels() {
+            return None;
+        }
+        let multiplier = match self.options.level_multiplier {
+            0 => DEFAULT_LEVEL_MULTIPLIER,
+            multiplier => multiplier,
+        };
+        let mut target = self.level_base_bytes();
+        for _ in 1..level {
+            target = target.saturating_mul(multiplier);
+        }
+        Some(target)
+    }
+
+    fn max_levels(&self) -> usize {
+        match self.options.max_levels {
+            0 => DEFAULT_MAX_LEVELS,
+            levels => levels.max(2),
+        }
+    }
+
+    fn level_base_bytes(&self) -> u64 {
+        match self.options.level_base_bytes {
+            0 => DEFAULT_LEVEL_BASE_BYTES,
+            bytes => bytes,
+        }
+    }
+
+    // the shallowest level that's due a compaction
+    async fn level_to_compact(&self) -> Result<Option<usize>, DBError> {
+        let sizes = self.level_sizes().await?;
+        let mut l0 = 0;
+        for sst in &self.ssts {
+            l0 += usize::from(sst.meta().await?.level() == 0);
+        }
+        if l0 >= self.compaction_trigger() {
+            return Ok(Some(0));
+        }
+        Ok((1..sizes.len()).find(|&level| {
+            self.level_target(level)
+                .is_some_and(|target| sizes[level] > target)
+        }))
+    }
+
+    // what one step of `compact_leveled`, from level into the one below,
+    // merges: the ssts newest first, where the outputs go in `ssts`, and
+    // whether tombstones are kept. the outputs come out where the oldest
+    // input was, so whatever was older than all the inputs stays behind
+    // them, and whatever sat among them and overlaps none newer than itself
+    // in front.
+    async fn level_compaction(&self, level: usize) -> Result<LevelCompaction, DBError> {
+        let target = level + 1;
+        let mut inputs = match level {
+            0 => Vec::new(),
+            _ => self
+                .pick_compaction_input(level)
+                .await?
+                .into_iter()
+                .collect(),
+        };
+        let mut levels = Vec::with_capacity(self.ssts.len());
+        for sst in &self.ssts {
+            levels.push(sst.meta().await?.level());
+        }
+        // everything at level overlapping the inputs has to move with them,
+        // or an older version could end up in front of a newer one
+        loop {
+            let mut grew = false;
+            for (sst, _) in self.ssts.iter().zip(&levels).filter(|(_, l)| **l == level) {
+                if !contains(&inputs, sst) && overlaps_any(sst, &inputs, level == 0).await? {
+                    inputs.push(sst.clone());
+                    grew = true;
+                }
+            }
+            if !grew {
+                break;
+            }
+        }
+        let moved = inputs.clone();
+        for (sst, _) in self.ssts.iter().zip(&levels).filter(|(_, l)| **l == target) {
+            if overlaps_any(sst, &moved, false).await? {
+                inputs.push(sst.clone());
+            }
+        }
+        let last = self
+            .ssts
+            .iter()
+            .rposition(|sst| contains(&inputs, sst))
+            .expect("a level due a compaction has an sst in it");
+        // an sst among the inputs that's older than one of them it overlaps
+        // would end up in front of that one's data, so it's merged too
+        let mut idx = last;
+        while let Some(prev) = idx.checked_sub(1) {
+            idx = prev;
+            let sst = &self.ssts[idx];
+            if contains(&inputs, sst) {
+                continue;
+            }
+            let newer: Vec<_> = self.ssts[..idx]
+                .iter()
+                .filter(|sst| contains(&inputs, sst))
+                .cloned()
+                .collect();
+            if overlaps_any(sst, &newer, false).await? {
+                inputs.push(sst.clone());
+                // what it overlaps further back may now have to come too
+                idx = last;
+            }
+        }
+        // newest first, as they are in `ssts`
+        inputs.sort_by_key(|input| self.ssts.iter().position(|sst| Arc::ptr_eq(sst, input)));
+        let position = last + 1 - inputs.len();
+        // older data for the same keys further back still needs hiding
+        let mut keep_tombstones = self.base.is_some();
+        for sst in &self.ssts[last + 1..] {
+            if overlaps_any(sst, &inputs, false).await? {
+                keep_tombstones = true;
+            }
+        }
+        Ok(LevelCompaction {
+            inputs,
+            position,
+            keep_tombstones,
+        })
+    }
+
+    // one step of `compact_leveled`, see `level_compaction`
+    async fn compact_level(&mut self, level: usize) -> Result<(), DBError> {
+        let target = level + 1;
+        let LevelCompaction {
+            inputs,
+            position,
+            keep_tombstones,
+        } = self.level_compaction(level).await?;
+        if self.options.level_dirs {
+            tokio::fs::create_dir_all(level_dir(&self.dir, target)).await?;
+        }
+        timer!(started);
+        event!(
+            info,
+            level,
+            inputs = inputs.len(),
+            bytes = inputs.iter().map(|sst| sst.size()).sum::<u64>(),
+            "leveled compaction started"
+        );
+        let mut handed_out = Vec::new();
+        let merged = {
+            let mut next_path = || {
+                let id = self.next_sst_id.fetch_add(1, Ordering::SeqCst);
+                let path = self.sst_path(&self.dir, target, id);
+                handed_out.push(path.clone());
+                path
+            };
+            let output = MergeOutput::Split {
+                level: target,
+                max_bytes: (self.level_base_bytes() / SSTS_PER_LEVEL_BASE).max(1),
+                next_path: &mut next_path,
+            };
+            let oldest = self.snapshots.oldest();
+            let merge = merge_ssts_into(
+                &inputs,
+                &self.options,
+                self.clock.as_ref(),
+                output,
+                keep_tombstones,
+                oldest,
+                None,
+            );
+            let merge_started = Instant::now();
+            let merged = with_timeout(self.options.compact_timeout, merge).await;
+            if let Some(latency) = &self.latency {
+                latency.compaction.record(merge_started.elapsed());
+            }
+            merged
+        };
+        let merged = match merged {
+            Ok(merged) => merged,
+            Err(err) => {
+                for path in &handed_out {
+                    sst::remove_files(path).await;
+                }
+                return Err(err);
+            }
+        };
+        // everything may have been tombstones with nothing left to hide
+        let mut outputs = Vec::with_capacity(merged.len());
+        for output in merged {
+            match output.meta().await?.min_key() {
+                Some(_) => outputs.push(output),
+                None => output.mark_obsolete(),
+            }
+        }
+        event!(
+            info,
+            ssts = outputs.len(),
+            elapsed_us = started.elapsed().as_micros() as u64,
+            "leveled compaction finished"
+        );
+        self.install_at(inputs, outputs, Some(position)).await
+    }
+
+    // flushes and then compacts even a lone sst, so that afterwards there is
+    // at most one sst holding exactly the live keys, one version each. heavy,
+    // the whole database is rewritten, so it's never run automatically.
+    pub async fn major_compact(&mut self) -> Result<(), DBError> {
+        self.flush().await?;
+        if self.ssts.is_empty() {
+            return Ok(());
+        }
+        self.compact_all(false).await
+    }
+
+    async fn compact_all(&mut self, skip_disjoint: bool) -> Result<(), DBError> {
+        self.finish_background(true).await;
+        let inputs = compaction_inputs(&self.ssts, skip_disjoint).await?;
+        if skip_disjoint && inputs.len() < 2 {
+            return Ok(());
+        }
+        timer!(started);
+        event!(
+            info,
+            inputs = inputs.len(),
+            bytes = inputs.iter().map(|sst| sst.size()).sum::<u64>(),
+            "compaction started"
+        );
+        let path = self.next_sst_path(1).await?;
+        let merge = merge_ssts(
+            &inputs,
+            &self.options,
+            self.clock.as_ref(),
+            &path,
+            self.base.is_some(),
+            self.snapshots.oldest(),
+            None,
+        );
+        let merge_started = Instant::now();
+        let merged = with_timeout(self.options.compact_timeout, merge).await;
+        if let Some(latency) = &self.latency {
+            latency.compaction.record(merge_started.elapsed());
+        }
+        let sst = match merged {
+            Ok(sst) => sst,
+            Err(err) => {
+                // the inputs are untouched, only the partial output goes
+                sst::remove_files(&path).await;
+                return Err(err);
+            }
+        };
+        event!(
+            info,
+            bytes = sst.size(),
+            elapsed_us = started.elapsed().as_micros() as u64,
+            "compaction finished"
+        );
+        self.install(inputs, sst).await
+    }
+
+    // swaps the merged output in for its inputs, going in last. ssts
+    // flushed since the merge started stay in front of it, and ones left out
+    // of the merge for not overlapping any input share no keys with it, so
+    // where they sit doesn't matter.
+    async fn install(&mut self, inputs: Vec<Arc<Sst>>, output: Sst) -> Result<(), DBError> {
+        self.install_at(inputs, vec![output], None).await
+    }
+
+    // `install` for any number of outputs, going in at position among the
+    // ssts that stay rather than last
+    async fn install_at(
+        &mut self,
+        inputs: Vec<Arc<Sst>>,
+        outputs: Vec<Sst>,
+        position: Option<usize>,
+    ) -> Result<(), DBError> {
+        let kept: Vec<_> = outputs.into_iter().map(Arc::new).collect();
+        let before = self.ssts.clone();
+        self.ssts_changed();
+        self.ssts.retain(|sst| !contains(&inputs, sst));
+        let position = position.unwrap_or(self.ssts.len()).min(self.ssts.len());
+        self.ssts.splice(position..position, kept.iter().cloned());
+        if let Err(err) = self.save_state().await {
+            // still on the old manifest, so the old ssts stay live
+            for output in &kept {
+                output.mark_obsolete();
+            }
+            self.ssts = before;
+            return Err(err);
+        }
+        for old in &inputs {
+            self.retire(old);
+        }
+        if let Some(on_compaction) = &self.options.callbacks.on_compaction {
+            for output in &kept {
+                on_compaction(output);
+            }
+        }
+        Ok(())
+    }
+
+    fn compaction_trigger(&self) -> usize {
+        match self.options.compaction_trigger {
+            0 => DEFAULT_COMPACTION_TRIGGER,
+            trigger => trigger,
+        }
+    }
+
+    // starts a compaction on its own task when there are at least
+    // `compaction_trigger` ssts and none is running yet, returning straight
+    // away either way. the result is swapped in by the next write, flush or
+    // compaction once the task is done. must be called within a tokio runtime.
+    pub fn maybe_compact(&self) -> Result<bool, DBError> {
+        self.check_poisoned()?;
+        if self.options.manual_background {
+            return Ok(false);
+        }
+        self.start_compaction()
+    }
+
+    // with `DbOptions::manual_background`, does one piece of the background
+    // work that would otherwise have been started on its own, in line, and
+    // says which: the flush a write crossing `flush_threshold` left waiting,
+    // the one `idle_flush` is due, or else the compaction `maybe_compact`
+    // would start. None when there's nothing to do.
+    pub async fn run_background_once(&mut self) -> Result<Option<BackgroundWork>, DBError> {
+        self.check_poisoned()?;
+        let flush_waiting = self
+            .options
+            .flush_threshold
+            .is_some_and(|threshold| self.memtable.size_bytes() >= threshold);
+        if flush_waiting {
+            self.flush().await?;
+            return Ok(Some(BackgroundWork::Flush));
+        }
+        if self.flush_if_idle().await? {
+            return Ok(Some(BackgroundWork::Flush));
+        }
+        if !self.start_compaction()? {
+            return Ok(None);
+        }
+        self.finish_background(true).await;
+        Ok(Some(BackgroundWork::Compaction))
+    }
+
+    fn start_compaction(&self) -> Result<bool, DBError> {
+        let trigger = self.compaction_trigger();
+        let mut background = self.background.lock().expect("background lock poisoned");
+        if background.is_some() || self.ssts.len() < trigger.max(2) {
+            return Ok(false);
+        }
+        let inputs = self.ssts.clone();
+        let id = self.next_sst_id.fetch_add(1, Ordering::SeqCst);
+        let path = self.sst_path(&self.dir, 1, id);
+        let progress = Arc::new(Progress::default());
+        let task = {
+            let (inputs, options, path) = (inputs.clone(), self.options.clone(), path.clone());
+            let keep_tombstones = self.base.is_some();
+            let oldest = self.snapshots.oldest();
+            let latency = self.latency.clone();
+            let progress = progress.clone();
+            let clock = self.clock.clone();
+            tokio::spawn(async move {
+                let skip_disjoint = options.compaction_skip_disjoint;
+                let inputs = compaction_inputs(&inputs, skip_disjoint).await?;
+                if skip_disjoint && inputs.len() < 2 {
+                    return Ok(None);
+                }
+                progress.started(&inputs);
+                if let Some(dir) = path.parent() {
+                    tokio::fs::create_dir_all(dir).await?;
+                }
+                let merge = merge_ssts(
+                    &inputs,
+                    &options,
+                    clock.as_ref(),
+                    &path,
+                    keep_tombstones,
+                    oldest,
+                    Some(&progress.read),
+                );
+                let started = Instant::now();
+                let merged = with_timeout(options.compact_timeout, merge).await;
+                if let Some(latency) = latency {
+                    latency.compaction.record(started.elapsed());
+                }
+                if merged.is_err() {
+                    sst::remove_files(&path).await;
+                }
+                Ok(Some((inputs, merged?)))
+            })
+        };
+        event!(info, inputs = inputs.len(), "background compaction started");
+        *background = Some(Background {
+            inputs,
+            task,
+            progress,
+        });
+        Ok(true)
+    }
+
+    // how far along the background compaction `maybe_compact` started is,
+    // None when there's none running. compactions run in the foreground
+    // hold `&mut self`, so there's nothing to ask while they run.
+    pub fn compaction_status(&self) -> Option<CompactionProgress> {
+        let background = self.background.lock().expect("background lock poisoned");
+        let progress = background
+            .as_ref()
+            .filter(|job| !job.task.is_finished())?
+            .progress
+            .clone();
+        drop(background);
+        Some(progress.report())
+    }
+
+    // installs the output of a finished background compaction, first waiting
+    // for it if wait is set. a failed one is only logged, the inputs are still
+    // there and `maybe_compact` can try again.
+    async fn finish_background(&mut self, wait: bool) {
+        let job = {
+            let background = self.background.get_mut().expect("background lock poisoned");
+            match background {
+                Some(job) if wait || job.task.is_finished() => background.take(),
+                _ => None,
+            }
+        };
+        let Some(Background { inputs, task, .. }) = job else {
+            return;
+        };
+        let merged = task
+            .await
+            .map_err(|err| DBError::Io(std::io::Error::other(err)))
+            .and_then(|merged| merged);
+        let (merged_inputs, output) = match merged {
+            Ok(Some(merged)) => merged,
+            Ok(None) => return,
+            Err(_err) => {
+                event!(warn, error = %_err, "background compaction failed");
+                return;
+            }
+        };
+        // a foreground compaction always waits for this one, so the inputs are
+        // still the oldest ssts. check anyway rather than lose data.
+        let still_there = self.ssts.len() >= inputs.len()
+            && self.ssts[self.ssts.len() - inputs.len()..]
+                .iter()
+                .zip(&inputs)
+                .all(|(sst, input)| Arc::ptr_eq(sst, input));
+        if !still_there {
+            output.mark_obsolete();
+            return;
+        }
+        if let Err(_err) = self.install(merged_inputs, output).await {
+            event!(warn, error = %_err, "background compaction not installed");
+        }
+    }
+
+    // writes a standalone copy of the database as it is right now into `dir`,
+    // which must not exist or be empty. ssts are immutable so they're hard
+    // linked (copied if linking fails), the memtable is written out as one
+    // more sst and the copy gets its own empty log, so nothing in it is shared
+    // with files the live database will modify.
+    pub async fn checkpoint(&self, dir: impl AsRef<Path>) -> Result<(), DBError> {
+        self.check_poisoned()?;
+        if self.base.is_some() {
+            return Err(DBError::InvalidInput(
+                "an overlay can't be checkpointed on its own".to_string(),
+            ));
+        }
+        let dir = dir.as_ref();
+        if dir.exists()
+            && tokio::fs::read_dir(dir)
+                .await?
+                .next_entry()
+                .await?
+                .is_some()
+        {
+            return Err(DBError::InvalidInput(format!(
+                "checkpoint directory {} is not empty",
+                dir.display()
+            )));
+        }
+        tokio::fs::create_dir_all(dir).await?;
+
+        for sst in &self.ssts {
+            for path in [sst.data_path(), sst.index_path()] {
+                let relative = path
+                    .strip_prefix(&self.dir)
+                    .map_err(std::io::Error::other)?;
+                let target = dir.join(relative);
+                if let Some(parent) = target.parent() {
+                    tokio::fs::create_dir_all(parent).await?;
+                }
+                if tokio::fs::hard_link(path, &target).await.is_err() {
+                    tokio::fs::copy(path, &target).await?;
+                }
+            }
+        }
+
+        // unlike the ssts the value log is still appended to, so it's copied
+        if self.vlog.path().exists() {
+            tokio::fs::copy(self.vlog.path(), dir.join("vlog")).await?;
+        }
+
+        let next_sst_id = self.next_sst_id.load(Ordering::SeqCst);
+        let mut manifest = self.manifest_for(&self.ssts, next_sst_id)?;
+        // oldest first, so the memtable's sst ends up in front
+        let flushing = self.flushing.iter().map(|flushing| &*flushing.memtable);
+        for memtable in flushing.chain([&self.memtable]) {
+            if memtable.is_empty() {
+                continue;
+            }
+            let path = self.sst_path_in(dir, 0, manifest.next_sst_id).await?;
+            let data = memtable.iter();
+            Sst::construct_with(
+                sst_writer(&self.options, self.clock.as_ref(), &path, 0).await?,
+                data,
+            )
+            .await?;
+            let relative = path.strip_prefix(dir).map_err(std::io::Error::other)?;
+            manifest.ssts.insert(0, relative.to_path_buf());
+            manifest.next_sst_id += 1;
+        }
+        manifest.seq = self.next_seq_hint();
+        manifest::store_seq(dir, manifest.seq).await?;
+        Log::open(dir.join("log")).await?;
+        manifest.store(dir).await?;
+        Ok(())
+    }
+
+    // brings everything to rest at a clean sequence number boundary: the
+    // memtable is flushed, any compaction running is waited for, and the
+    // live sst set is checkpointed under `checkpoints/<seq>` in the database
+    // directory. returns seq, the last sequence number written, which
+    // `Db::open_at_seq` opens the database as it was at. nothing written
+    // since the previous call reuses that checkpoint.
+    pub async fn flush_all_and_checkpoint(&mut self) -> Result<u64, DBError> {
+        self.check_poisoned()?;
+        self.flush().await?;
+        self.finish_background(true).await;
+        self.save_state().await?;
+        let seq = self.next_seq().await?.saturating_sub(1);
+        let dir = seq_checkpoint_dir(&self.dir, seq);
+        if !dir.join("manifest").exists() {
+            if dir.exists() {
+                // left half written by a crash
+                tokio::fs::remove_dir_all(&dir).await?;
+            }
+            self.checkpoint(&dir).await?;
+        }
+        Ok(seq)
+    }
+
+    // opens the checkpoint `Db::flush_all_and_checkpoint` took at seq, on its
+    // own and separate from the live database in dir. the checkpoint has its
+    // own log, so `DbOptions::wal_dir` is ignored.
+    pub async fn open_at_seq(
+        dir: impl AsRef<Path>,
+        seq: u64,
+        mut options: DbOptions,
+    ) -> Result<Db, DBError> {
+        options.wal_dir = None;
+        let checkpoint = seq_checkpoint_dir(dir.as_ref(), seq);
+        if !checkpoint.join("manifest").exists() {
+            return Err(DBError::InvalidInput(format!(
+                "no checkpoint at sequence number {}",
+                seq
+            )));
+        }
+        Db::open(checkpoint, options).await
+    }
+
+    // checks every sst and the log for internal consistency. problems are
+    // collected into the report rather than returned as errors.
+    pub async fn verify(&self) -> Result<VerifyReport, DBError> {
+        let mut report = VerifyReport::default();
+        for sst in &self.ssts {
+            let (records, problems) = sst.verify().await;
+            report.ssts_checked += 1;
+            report.records_checked += records;
+            report.problems.extend(problems);
+        }
+
+        // every sst needs both halves on disk, and to be in the manifest
+        for dir in sst_dirs(&self.dir, self.options.level_dirs).await? {
+            let mut entries = tokio::fs::read_dir(&dir).await?;
+            while let Some(entry) = entries.next_entry().await? {
+                let path = entry.path();
+                if sst_id(&path).is_some() && !self.ssts.iter().any(|sst| sst.data_path() == path) {
+                    report
+                        .problems
+                        .push(Problem::new(&path, "sst is not in the manifest"));
+                }
+                let other = match path.extension().and_then(|ext| ext.to_str()) {
+                    Some("data") => path.with_extension("index"),
+                    Some("index") => path.with_extension("data"),
+                    _ => continue,
+                };
+                if !other.exists() {
+                    report.problems.push(Problem::new(
+                        &path,
+                        format!("{} is missing", other.display()),
+                    ));
+                }
+            }
+        }
+
+        // and has to sit in the directory for the level it was written for
+        if self.options.level_dirs {
+            for sst in &self.ssts {
+                let Ok(meta) = sst.meta().await else { continue };
+                if sst.data_path().parent() != Some(&level_dir(&self.dir, meta.level())) {
+                    report.problems.push(Problem::new(
+                        sst.data_path(),
+                        format!("L{} sst outside its level directory", meta.level()),
+                    ));
+                }
+            }
+        }
+
+        // below L0 each level splits the key space between its ssts
+        let mut levels: HashMap<usize, Vec<(&Sst, &SstMeta)>> = HashMap::new();
+        for sst in &self.ssts {
+            let Ok(meta) = sst.meta().await else { continue };
+            if meta.level() > 0 {
+                levels.entry(meta.level()).or_default().push((sst, meta));
+            }
+        }
+        let mut levels: Vec<_> = levels.into_iter().collect();
+        levels.sort_by_key(|(level, _)| *level);
+        for (level, mut ssts) in levels {
+            ssts.sort_by(|(_, a), (_, b)| a.min_key().cmp(&b.min_key()));
+            for (idx, (sst, meta)) in ssts.iter().enumerate() {
+                // sorted by first key, so the ones after that overlap come
+                // straight after
+                for (other, other_meta) in &ssts[idx + 1..] {
+                    if !meta.overlaps(other_meta) {
+                        break;
+                    }
+                    report.problems.push(Problem::new(
+                        other.data_path(),
+                        format!("L{} sst overlaps {}", level, sst.data_path().display()),
+                    ));
+                }
+            }
+        }
+
+        report.problems.extend(self.log.verify().await?);
+        Ok(report)
+    }
+
+    pub fn ssts(&self) -> &[Arc<Sst>] {
+        &self.ssts
+    }
+
+    // the data files point lookups hold open, with `DbOptions::max_open_files`
+    pub fn file_cache(&self) -> Option<&FileCache> {
+        self.files.as_deref()
+    }
+
+    // how long gets, writes, flushes and compactions have taken, with
+    // `DbOptions::record_latency`
+    pub fn latency_histogram(&self) -> Option<&Latencies> {
+        self.latency.as_deref()
+    }
+
+    // full memtables `DbOptions::async_flush` is still writing out
+    pub fn flushing_memtables(&self) -> usize {
+        self.flushing.len()
+    }
+
+    // only available with `skip_list_memtable`. the reader tracks the live
+    // memtable across writes and is emptied, not replaced, by a flush.
+    pub fn memtable_reader(&self) -> Option<MemtableReader> {
+        self.memtable.reader()
+    }
+}
+
+// nothing async can run here, so unflushed records are only pointed out,
+// by a tracing event. they come back from the log on the next open, unless
+// `Durability::None` kept them out of it.
+#[cfg(feature = "tracing")]
+impl Drop for Db {
+    fn drop(&mut self) {
+        let flushing: usize = self
+            .flushing
+            .iter()
+            .map(|flushing| flushing.memtable.len())
+            .sum();
+        if self.memtable.is_empty() && flushing == 0 {
+            return;
+        }
+        let fate = match self.options.durability {
+            Durability::None => "lost where Durability::None kept them out of the log",
+            _ => "replayed from the log on the next open",
+        };
+        event!(
+            warn,
+            dir = %self.dir.display(),
+            records = self.memtable.len() + flushing,
+            "db dropped with unflushed records, {}. close() flushes them",
+            fate
+        );
+    }
+}
@@ -0,0 +1,64 @@
+use std::{
+    error::Error,
+    fmt::{self, Display},
+    path::PathBuf,
+};
+
+#[derive(Debug)]
+pub enum DBError {
+    Io(std::io::Error),
+    Serde(serde_json::Error),
+    InvalidInput(String),
+    Timeout,
+    Poisoned,
+    // the files of an sst the database still refers to are gone
+    MissingSst(PathBuf),
+    // a log has a corrupt record at this byte offset with intact ones after
+    // it, so replay can't just stop there
+    CorruptLog(PathBuf, u64),
+}
+
+impl Error for DBError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            DBError::Io(err) => Some(err),
+            DBError::Serde(err) => Some(err),
+            DBError::InvalidInput(_)
+            | DBError::Timeout
+            | DBError::Poisoned
+            | DBError::MissingSst(_)
+            | DBError::CorruptLog(..) => None,
+        }
+    }
+}
+
+impl Display for DBError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DBError::Io(err) => write!(f, "IO error: {}", err),
+            DBError::Serde(err) => write!(f, "Serde error: {}", err),
+            DBError::InvalidInput(msg) => write!(f, "Invalid input: {}", msg),
+            DBError::Timeout => write!(f, "Operation timed out"),
+            DBError::Poisoned => write!(f, "Database poisoned by an earlier failed write"),
+            DBError::MissingSst(path) => write!(f, "Missing SST: {}", path.display()),
+            DBError::CorruptLog(path, offset) => write!(
+                f,
+                "Corrupt log record in {} at byte {}",
+                path.display(),
+                offset
+            ),
+        }
+    }
+}
+
+impl From<std::io::Error> for DBError {
+    fn from(err: std::io::Error) -> DBError {
+        DBError::Io(err)
+    }
+}
+
+impl From<serde_json::Error> for DBError {
+    fn from(value: serde_json::Error) -> Self {
+        DBError::Serde(value)
+    }
+}
@@ -0,0 +1,74 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use tokio::fs::File;
+
+pub(crate) type SharedFile = Arc<tokio::sync::Mutex<File>>;
+
+// keeps up to `capacity` sst data files open for point lookups, closing the
+// least recently used one to make room. a lookup still reading a file that
+// was just evicted holds it open until it's done, so for a moment there can
+// be one more open than the limit per lookup in flight.
+#[derive(Debug)]
+pub struct FileCache {
+    capacity: usize,
+    // least recently used first
+    files: Mutex<Vec<(PathBuf, SharedFile)>>,
+}
+
+impl FileCache {
+    pub fn new(capacity: usize) -> FileCache {
+        FileCache {
+            capacity: capacity.max(1),
+            files: Mutex::new(Vec::new()),
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    // how many files the cache holds open
+    pub fn len(&self) -> usize {
+        self.files.lock().expect("file cache lock poisoned").len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub(crate) async fn get(&self, path: &Path) -> std::io::Result<SharedFile> {
+        if let Some(file) = self.touch(path) {
+            return Ok(file);
+        }
+        let file = Arc::new(tokio::sync::Mutex::new(File::open(path).await?));
+        let mut files = self.files.lock().expect("file cache lock poisoned");
+        // someone else may have opened it meanwhile, theirs is as good
+        files.retain(|(cached, _)| cached != path);
+        if files.len() >= self.capacity {
+            files.remove(0);
+        }
+        files.push((path.to_path_buf(), file.clone()));
+        Ok(file)
+    }
+
+    // closes path if it's open, for an sst whose files are going away
+    pub(crate) fn forget(&self, path: &Path) {
+        self.files
+            .lock()
+            .expect("file cache lock poisoned")
+            .retain(|(cached, _)| cached != path);
+    }
+
+    // the cached file for path, made the most recently used
+    fn touch(&self, path: &Path) -> Option<SharedFile> {
+        let mut files = self.files.lock().expect("file cache lock poisoned");
+        let idx = files.iter().position(|(cached, _)| cached == path)?;
+        let entry = files.remove(idx);
+        let file = entry.1.clone();
+        files.push(entry);
+        Some(file)
+    }
+}
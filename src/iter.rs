@@ -0,0 +1,402 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use futures::{stream, Stream};
+use tokio::time::Instant;
+
+use crate::{error::DBError, log::Put, options::MergeOperator, sst::SstIter, vlog::resolve_value};
+
+pub enum Source {
+    Memtable(std::vec::IntoIter<Put>),
+    Sst(Box<SstIter>),
+    // the live keys of an overlay's base, older than anything in the overlay
+    Base(Box<DbIter>),
+}
+
+impl Source {
+    async fn next(&mut self) -> Result<Option<Put>, DBError> {
+        match self {
+            Source::Memtable(iter) => Ok(iter.next()),
+            Source::Sst(iter) => iter.next().await,
+            Source::Base(iter) => Ok(Box::pin(iter.next()).await?.map(|(key, value)| Put {
+                key,
+                value: Some(value),
+                seq: 0,
+                flags: 0,
+                value_ref: None,
+                merge: false,
+            })),
+        }
+    }
+}
+
+// k-way merge over sorted sources. when several sources hold the same key the
+// record with the highest sequence number wins, falling back to source order
+// (newest first) on a tie. tombstones are passed through so compaction can
+// decide whether to keep them.
+pub struct MergeIter {
+    sources: Vec<Source>,
+    heads: Vec<Option<Put>>,
+    primed: bool,
+    // yield older versions too, newest first, instead of only the winner
+    all_versions: bool,
+    // the key and sequence number last yielded, kept to check the order
+    // with `DbOptions::paranoid_checks`
+    last: Option<(String, u64)>,
+    check_order: bool,
+    // records between yields to the runtime, 0 never yields
+    yield_every: usize,
+    since_yield: usize,
+}
+
+impl MergeIter {
+    pub fn new(sources: Vec<Source>) -> MergeIter {
+        MergeIter {
+            heads: Vec::with_capacity(sources.len()),
+            sources,
+            primed: false,
+            all_versions: false,
+            last: None,
+            check_order: false,
+            yield_every: 0,
+            since_yield: 0,
+        }
+    }
+
+    pub fn all_versions(sources: Vec<Source>) -> MergeIter {
+        MergeIter {
+            all_versions: true,
+            ..MergeIter::new(sources)
+        }
+    }
+
+    // fails the merge with `InvalidInput` as soon as it would yield a record
+    // out of order, which only a source that isn't sorted can cause
+    pub(crate) fn checked(mut self, check_order: bool) -> MergeIter {
+        self.check_order = check_order;
+        self
+    }
+
+    // reads that complete from a buffer never give the runtime a chance to
+    // run anything else, so a long merge hands it one every so many records
+    pub(crate) fn yielding(mut self, every: usize) -> MergeIter {
+        self.yield_every = every;
+        self
+    }
+
+    fn check_follows(&mut self, put: &Put) -> Result<(), DBError> {
+        if let Some((key, seq)) = &self.last {
+            let in_order = match put.key.as_str().cmp(key) {
+                std::cmp::Ordering::Greater => true,
+                std::cmp::Ordering::Equal => self.all_versions && put.seq < *seq,
+                std::cmp::Ordering::Less => false,
+            };
+            if !in_order {
+                return Err(DBError::InvalidInput(format!(
+                    "merge out of order: {:?} (seq {}) after {:?} (seq {})",
+                    put.key, put.seq, key, seq
+                )));
+            }
+        }
+        match &mut self.last {
+            Some((key, seq)) => {
+                key.clear();
+                key.push_str(&put.key);
+                *seq = put.seq;
+            }
+            None => self.last = Some((put.key.clone(), put.seq)),
+        }
+        Ok(())
+    }
+
+    pub async fn next(&mut self) -> Result<Option<Put>, DBError> {
+        if !self.primed {
+            for source in self.sources.iter_mut() {
+                self.heads.push(source.next().await?);
+            }
+            self.primed = true;
+        }
+
+        let mut winner: Option<usize> = None;
+        for (idx, head) in self.heads.iter().enumerate() {
+            let Some(put) = head else { continue };
+            let better = match winner.and_then(|w| self.heads[w].as_ref()) {
+                None => true,
+                Some(best) => put.key < best.key || (put.key == best.key && put.seq > best.seq),
+            };
+            if better {
+                winner = Some(idx);
+            }
+        }
+        let Some(winner) = winner else {
+            return Ok(None);
+        };
+
+        if self.yield_every > 0 {
+            self.since_yield += 1;
+            if self.since_yield >= self.yield_every {
+                self.since_yield = 0;
+                tokio::task::yield_now().await;
+            }
+        }
+        let put = self.heads[winner].take().unwrap();
+        if self.check_order {
+            self.check_follows(&put)?;
+        }
+        self.heads[winner] = self.sources[winner].next().await?;
+        // drop the older versions of the same key, or with all_versions only
+        // copies of this same record
+        for idx in 0..self.heads.len() {
+            while matches!(&self.heads[idx], Some(head) if head.key == put.key
+                && (!self.all_versions || head.seq == put.seq))
+            {
+                self.heads[idx] = self.sources[idx].next().await?;
+            }
+        }
+        Ok(Some(put))
+    }
+}
+
+// the value a key's versions, newest first from a merge operand, add up to:
+// the operands folded onto the first version that isn't one, or onto
+// nothing if they run out first
+pub(crate) async fn fold_operands(
+    operator: &dyn MergeOperator,
+    vlog: &Path,
+    key: &str,
+    versions: impl IntoIterator<Item = Put>,
+) -> Result<Option<String>, DBError> {
+    let mut operands = Vec::new();
+    let mut value = None;
+    for put in versions {
+        let merge = put.merge;
+        let resolved = resolve_value(vlog, put).await?;
+        if !merge {
+            value = resolved;
+            break;
+        }
+        operands.extend(resolved);
+    }
+    for operand in operands.iter().rev() {
+        value = Some(operator.merge(key, value.as_deref(), operand));
+    }
+    Ok(value)
+}
+
+pub struct DbIter {
+    merge: MergeIter,
+    end: Option<String>,
+    deadline: Option<Instant>,
+    vlog: PathBuf,
+    done: bool,
+    // newer records are skipped, the merge then yields every version
+    snapshot: Option<u64>,
+    // the key whose visible version under the snapshot was already seen
+    decided: Option<String>,
+    remaining: Option<usize>,
+    keys_only: bool,
+    // hand back tombstones rather than stepping over them
+    tombstones: bool,
+    // read up front for a reverse scan, largest key first
+    reversed: Option<std::vec::IntoIter<Put>>,
+    // folds merge operands, the merge then yields every version
+    operator: Option<Arc<dyn MergeOperator>>,
+    // read past the versions of a folded key
+    peeked: Option<Put>,
+}
+
+impl DbIter {
+    pub(crate) fn new(
+        merge: MergeIter,
+        end: Option<String>,
+        deadline: Option<Instant>,
+        vlog: PathBuf,
+    ) -> DbIter {
+        DbIter {
+            merge,
+            end,
+            deadline,
+            vlog,
+            done: false,
+            snapshot: None,
+            decided: None,
+            remaining: None,
+            keys_only: false,
+            tombstones: false,
+            reversed: None,
+            operator: None,
+            peeked: None,
+        }
+    }
+
+    // the merge has to be over all versions for the snapshot to find the
+    // ones old enough
+    pub(crate) fn with_snapshot(mut self, snapshot: Option<u64>) -> DbIter {
+        self.snapshot = snapshot;
+        self
+    }
+
+    pub(crate) fn with_merge_operator(
+        mut self,
+        operator: Option<Arc<dyn MergeOperator>>,
+    ) -> DbIter {
+        self.operator = operator;
+        self
+    }
+
+    pub(crate) fn with_limit(mut self, limit: Option<usize>) -> DbIter {
+        self.remaining = limit;
+        self
+    }
+
+    pub(crate) fn keys_only(mut self, keys_only: bool) -> DbIter {
+        self.keys_only = keys_only;
+        self
+    }
+
+    // for `next_record`, the newest record of a deleted key is its tombstone
+    pub(crate) fn with_tombstones(mut self) -> DbIter {
+        self.tombstones = true;
+        self
+    }
+
+    // reads the rest of the iteration and hands it back largest key first
+    pub(crate) async fn into_reversed(mut self) -> Result<DbIter, DBError> {
+        let mut live = Vec::new();
+        while let Some(put) = self.next_live().await? {
+            live.push(put);
+        }
+        live.reverse();
+        self.reversed = Some(live.into_iter());
+        Ok(self)
+    }
+
+    async fn next_merged(&mut self) -> Result<Option<Put>, DBError> {
+        if let Some(put) = self.peeked.take() {
+            return Ok(Some(put));
+        }
+        let Some(deadline) = self.deadline else {
+            return self.merge.next().await;
+        };
+        // timeout_at only notices the deadline while the read is pending,
+        // buffered reads that complete straight away have to be caught here
+        let next = if Instant::now() < deadline {
+            tokio::time::timeout_at(deadline, self.merge.next())
+                .await
+                .ok()
+        } else {
+            None
+        };
+        match next {
+            Some(next) => next,
+            None => {
+                self.done = true;
+                Err(DBError::Timeout)
+            }
+        }
+    }
+
+    async fn next_live(&mut self) -> Result<Option<Put>, DBError> {
+        if self.remaining == Some(0) {
+            return Ok(None);
+        }
+        let next = match &mut self.reversed {
+            Some(reversed) => reversed.next(),
+            None => self.next_forward().await?,
+        };
+        if let (Some(remaining), Some(_)) = (&mut self.remaining, &next) {
+            *remaining -= 1;
+        }
+        Ok(next)
+    }
+
+    async fn next_forward(&mut self) -> Result<Option<Put>, DBError> {
+        while !self.done {
+            match self.next_merged().await? {
+                Some(put) if self.end.as_ref().is_some_and(|end| put.key >= *end) => {
+                    self.done = true;
+                }
+                Some(put) if !self.visible(&put) => {}
+                Some(put) if put.merge && self.operator.is_some() => {
+                    return self.fold(put).await.map(Some)
+                }
+                Some(put) if put.value.is_some() || self.tombstones => return Ok(Some(put)),
+                // deleted, skip to the next live key
+                Some(_) => {}
+                None => self.done = true,
+            }
+        }
+        Ok(None)
+    }
+
+    // whether put is the version of its key the snapshot sees, versions
+    // arrive newest first
+    fn visible(&mut self, put: &Put) -> bool {
+        if self.snapshot.is_none() && self.operator.is_none() {
+            return true;
+        }
+        if self.snapshot.is_some_and(|snapshot| put.seq > snapshot)
+            || self.decided.as_ref() == Some(&put.key)
+        {
+            return false;
+        }
+        self.decided = Some(put.key.clone());
+        true
+    }
+
+    // the operand newest and the older versions of its key after it, as one
+    // record with the folded value
+    async fn fold(&mut self, newest: Put) -> Result<Put, DBError> {
+        let key = newest.key.clone();
+        let seq = newest.seq;
+        let mut versions = vec![newest];
+        while let Some(put) = self.next_merged().await? {
+            if put.key != key {
+                self.peeked = Some(put);
+                break;
+            }
+            versions.push(put);
+        }
+        let operator = self.operator.clone().expect("folding without an operator");
+        Ok(Put {
+            value: fold_operands(operator.as_ref(), &self.vlog, &key, versions).await?,
+            key,
+            seq,
+            flags: 0,
+            value_ref: None,
+            merge: false,
+        })
+    }
+
+    pub async fn next(&mut self) -> Result<Option<(String, String)>, DBError> {
+        let Some(put) = self.next_live().await? else {
+            return Ok(None);
+        };
+        if self.keys_only {
+            return Ok(Some((put.key, String::new())));
+        }
+        let key = put.key.clone();
+        Ok(resolve_value(&self.vlog, put)
+            .await?
+            .map(|value| (key, value)))
+    }
+
+    // like `next`, without reading separated values back from the value log
+    pub(crate) async fn next_key(&mut self) -> Result<Option<String>, DBError> {
+        Ok(self.next_live().await?.map(|put| put.key))
+    }
+
+    // the live record itself, value left as stored
+    pub(crate) async fn next_record(&mut self) -> Result<Option<Put>, DBError> {
+        self.next_live().await
+    }
+
+    // the same iteration as a `Stream`, for use with `StreamExt` combinators
+    pub fn into_stream(self) -> impl Stream<Item = Result<(String, String), DBError>> {
+        stream::try_unfold(self, |mut iter| async move {
+            Ok(iter.next().await?.map(|entry| (entry, iter)))
+        })
+    }
+}
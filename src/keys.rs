@@ -0,0 +1,20 @@
+// keys compare as strings, byte by byte, so numbers only sort numerically
+// when they're all written out to the same width. these give u64 keys
+// (ids, timestamps) a fixed 16 digit lowercase hex form, usable on their own
+// or after a fixed prefix like "user:".
+
+pub fn encode_u64_key(n: u64) -> String {
+    format!("{:016x}", n)
+}
+
+// None for anything `encode_u64_key` wouldn't have produced
+pub fn decode_u64_key(key: &str) -> Option<u64> {
+    let is_encoded = key.len() == 16
+        && key
+            .bytes()
+            .all(|byte| byte.is_ascii_digit() || (b'a'..=b'f').contains(&byte));
+    if !is_encoded {
+        return None;
+    }
+    u64::from_str_radix(key, 16).ok()
+}
@@ -0,0 +1,119 @@
+use std::{
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+
+// each power of two of nanoseconds is split into this many buckets, so a
+// recorded latency is off by at most 1/16th of itself
+const SUB_BUCKET_BITS: u32 = 4;
+const SUB_BUCKETS: u64 = 1 << SUB_BUCKET_BITS;
+// values below this each get a bucket to themselves
+const LINEAR: u64 = SUB_BUCKETS * 2;
+const BUCKETS: usize = (LINEAR + (63 - SUB_BUCKET_BITS as u64) * SUB_BUCKETS) as usize;
+
+fn bucket(nanos: u64) -> usize {
+    if nanos < LINEAR {
+        return nanos as usize;
+    }
+    let exponent = 63 - nanos.leading_zeros();
+    let shift = exponent - SUB_BUCKET_BITS;
+    let mantissa = (nanos >> shift) & (SUB_BUCKETS - 1);
+    (LINEAR + (shift as u64 - 1) * SUB_BUCKETS + mantissa) as usize
+}
+
+// the largest value that lands in bucket idx
+fn bucket_max(idx: usize) -> u64 {
+    let idx = idx as u64;
+    if idx < LINEAR {
+        return idx;
+    }
+    let shift = (idx - LINEAR) / SUB_BUCKETS + 1;
+    let mantissa = (idx - LINEAR) % SUB_BUCKETS;
+    let min = (SUB_BUCKETS + mantissa) << shift;
+    min + ((1 << shift) - 1)
+}
+
+// counts of latencies in log-linear buckets, in the manner of hdrhistogram.
+// recording is a couple of atomic adds, so it's safe from `&self` and cheap
+// enough for every call.
+#[derive(Debug)]
+pub struct Histogram {
+    buckets: Vec<AtomicU64>,
+    count: AtomicU64,
+    total_nanos: AtomicU64,
+}
+
+impl Default for Histogram {
+    fn default() -> Self {
+        Histogram {
+            buckets: (0..BUCKETS).map(|_| AtomicU64::new(0)).collect(),
+            count: AtomicU64::new(0),
+            total_nanos: AtomicU64::new(0),
+        }
+    }
+}
+
+impl Histogram {
+    pub fn record(&self, latency: Duration) {
+        let nanos = u64::try_from(latency.as_nanos()).unwrap_or(u64::MAX);
+        self.buckets[bucket(nanos)].fetch_add(1, Ordering::Relaxed);
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.total_nanos.fetch_add(nanos, Ordering::Relaxed);
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count.load(Ordering::Relaxed)
+    }
+
+    pub fn mean(&self) -> Duration {
+        match self.count() {
+            0 => Duration::ZERO,
+            count => Duration::from_nanos(self.total_nanos.load(Ordering::Relaxed) / count),
+        }
+    }
+
+    // the latency percentile of the recorded ones were at or under, rounded
+    // up to the top of its bucket. zero with nothing recorded.
+    pub fn percentile(&self, percentile: f64) -> Duration {
+        let count = self.count();
+        if count == 0 {
+            return Duration::ZERO;
+        }
+        let rank = ((percentile.clamp(0.0, 100.0) / 100.0 * count as f64).ceil() as u64).max(1);
+        let mut seen = 0;
+        for (idx, bucket) in self.buckets.iter().enumerate() {
+            seen += bucket.load(Ordering::Relaxed);
+            if seen >= rank {
+                return Duration::from_nanos(bucket_max(idx));
+            }
+        }
+        // recorded into concurrently while we counted
+        Duration::from_nanos(bucket_max(BUCKETS - 1))
+    }
+
+    pub fn p50(&self) -> Duration {
+        self.percentile(50.0)
+    }
+
+    pub fn p95(&self) -> Duration {
+        self.percentile(95.0)
+    }
+
+    pub fn p99(&self) -> Duration {
+        self.percentile(99.0)
+    }
+}
+
+// what `DbOptions::record_latency` collects, one histogram per kind of
+// operation
+#[derive(Debug, Default)]
+pub struct Latencies {
+    pub get: Histogram,
+    // puts and deletes, including a flush the write waits for
+    pub put: Histogram,
+    // flushes run in line, by `Db::flush` or a write crossing
+    // `DbOptions::flush_threshold`. async flushes aren't counted.
+    pub flush: Histogram,
+    // the merge of each compaction, foreground or background
+    pub compaction: Histogram,
+}
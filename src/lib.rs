@@ -0,0 +1,81 @@
+use async_trait::async_trait;
+
+mod batch;
+mod bloom;
+mod changes;
+mod clock;
+mod db;
+mod error;
+mod files;
+mod iter;
+mod keys;
+mod latency;
+mod log;
+mod manifest;
+mod memtable;
+mod options;
+mod rate_limit;
+mod recovery;
+mod sharded;
+mod snapshot;
+mod sst;
+mod trace;
+mod verify;
+mod vlog;
+
+pub use batch::WriteBatch;
+pub use bloom::KeyHasher;
+pub use changes::Change;
+pub use clock::{Clock, MockClock, MonotonicClock, SystemClock};
+pub use db::{pick_compaction_input, BackgroundWork, CompactionPlan, CompactionProgress, Db};
+pub use error::DBError;
+pub use files::FileCache;
+pub use iter::DbIter;
+pub use keys::{decode_u64_key, encode_u64_key};
+pub use latency::{Histogram, Latencies};
+pub use log::{CorruptionPolicy, Durability, Log, LogReader, Put, RecordIter};
+pub use manifest::{Manifest, VersionEdit};
+pub use memtable::{Entry, Memtable, MemtableReader};
+pub use options::{
+    Callbacks, DbOptions, MergeOperator, ReplicationSink, ScanOptions, Seek, SstCallback,
+    WriteCallback, WriteOptions,
+};
+pub use rate_limit::RateLimiter;
+pub use recovery::RecoveryReport;
+pub use sharded::{ShardedDb, ShardedIter};
+pub use snapshot::Snapshot;
+pub use sst::{Newest, Sst, SstIter, SstMeta, SstStats, SstWriter};
+pub use verify::{Problem, VerifyReport};
+pub use vlog::{ValueLog, ValueRef};
+
+// what a single source knows about a key. `Deleted` means the source holds a
+// tombstone, so older sources must not be consulted.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Lookup {
+    Present(String),
+    Deleted,
+    Absent,
+}
+
+impl Lookup {
+    pub fn into_value(self) -> Option<String> {
+        match self {
+            Lookup::Present(value) => Some(value),
+            Lookup::Deleted | Lookup::Absent => None,
+        }
+    }
+}
+
+impl From<Option<String>> for Lookup {
+    fn from(value: Option<String>) -> Self {
+        match value {
+            Some(value) => Lookup::Present(value),
+            None => Lookup::Deleted,
+        }
+    }
+}
+
+#[async_trait]
+pub trait Queryable {
+    async fn get(&self, key: &str) -> Result<Lookup, DBError>;
+}
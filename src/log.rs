@@ -0,0 +1,518 @@
+use std::path::{Path, PathBuf};
+
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use tokio::{
+    fs::{File, OpenOptions},
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+};
+
+use crate::{
+    error::DBError, memtable::Memtable, verify::Problem, vlog::ValueRef, Lookup, Queryable,
+};
+
+pub struct Log {
+    path: PathBuf,
+    log: File,
+    // records are serialized here, kept between appends so a steady stream
+    // of writes doesn't allocate a buffer each
+    scratch: Vec<u8>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct Put {
+    pub key: String,
+    // None marks a delete
+    pub value: Option<String>,
+    // records written before sequence numbers existed read back as 0
+    #[serde(default)]
+    pub seq: u64,
+    // small caller-defined per-key metadata, left out of the record when unset
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub flags: u8,
+    // set when the value was moved out to the value log, `value` is then
+    // left empty
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub value_ref: Option<ValueRef>,
+    // an operand for `DbOptions::merge_operator` rather than a value, folded
+    // onto the versions before it when read
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub merge: bool,
+}
+
+// when a write to the log is made durable
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Durability {
+    // fsync before the write returns
+    #[default]
+    Sync,
+    // written to the file but left to the os to persist, until a sync or a
+    // flush. a crash may lose the latest of them.
+    NoSync,
+    // not written to the log at all, so a write touches no disk. records
+    // live only in the memtable until a flush, and whatever hasn't been
+    // flushed is gone once the db is dropped or crashes.
+    None,
+}
+
+// a `Put` serialized from borrowed fields, written out the same way
+#[derive(Serialize)]
+pub(crate) struct PutRef<'a> {
+    pub key: &'a str,
+    pub value: Option<&'a str>,
+    pub seq: u64,
+    #[serde(skip_serializing_if = "is_zero")]
+    pub flags: u8,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub value_ref: Option<ValueRef>,
+    #[serde(skip_serializing_if = "is_false")]
+    pub merge: bool,
+}
+
+impl<'a> From<&'a Put> for PutRef<'a> {
+    fn from(put: &'a Put) -> Self {
+        PutRef {
+            key: &put.key,
+            value: put.value.as_deref(),
+            seq: put.seq,
+            flags: put.flags,
+            value_ref: put.value_ref,
+            merge: put.merge,
+        }
+    }
+}
+
+// what replaying the log does with a record that doesn't parse or match its
+// checksum. a crash mid-append leaves a torn record at the tail, anywhere
+// else it's damage.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum CorruptionPolicy {
+    // replay up to the bad record and ignore the rest of the log
+    #[default]
+    StopAtFirstError,
+    // leave the bad record out and carry on after it
+    SkipCorrupt,
+    Fail,
+}
+
+fn is_zero(flags: &u8) -> bool {
+    *flags == 0
+}
+
+fn is_false(merge: &bool) -> bool {
+    !*merge
+}
+
+// every record in the log ends in a checksum field over the record without
+// it. `Put` ignores the field, so it parses like any other.
+const CRC_FIELD: &[u8] = b",\"crc\":";
+
+// whether a record's checksum matches it, records written before they had
+// one have nothing to check
+fn checksum_matches(record: &[u8]) -> bool {
+    let Some(body) = record.strip_suffix(b"}") else {
+        return true;
+    };
+    let Some(at) = body
+        .windows(CRC_FIELD.len())
+        .rposition(|window| window == CRC_FIELD)
+    else {
+        return true;
+    };
+    let digits = &body[at + CRC_FIELD.len()..];
+    if digits.is_empty() || !digits.iter().all(u8::is_ascii_digit) {
+        return true;
+    }
+    let Some(crc) = std::str::from_utf8(digits)
+        .ok()
+        .and_then(|digits| digits.parse::<u32>().ok())
+    else {
+        return false;
+    };
+    let mut hasher = crc32fast::Hasher::new();
+    hasher.update(&body[..at]);
+    hasher.update(b"}");
+    hasher.finalize() == crc
+}
+
+impl Log {
+    pub async fn open(path: impl AsRef<Path>) -> Result<Log, DBError> {
+        let log = OpenOptions::new()
+            .append(true)
+            .create(true)
+            .open(&path)
+            .await?;
+        Ok(Log {
+            path: path.as_ref().to_path_buf(),
+            log,
+            scratch: Vec::new(),
+        })
+    }
+
+    // moves the records so far to `to` and carries on in a fresh, empty log
+    // at the same path
+    pub async fn rotate(&mut self, to: &Path) -> Result<(), DBError> {
+        self.log.sync_all().await?;
+        tokio::fs::rename(&self.path, to).await?;
+        self.log = Log::open(&self.path).await?.log;
+        Ok(())
+    }
+
+    pub async fn append(&mut self, put: &Put) -> Result<(), DBError> {
+        self.append_batch(std::slice::from_ref(put)).await
+    }
+
+    // one write and one fsync for the lot
+    pub async fn append_batch(&mut self, records: &[Put]) -> Result<(), DBError> {
+        self.append_batch_with(records, Durability::Sync).await
+    }
+
+    pub async fn append_batch_with(
+        &mut self,
+        records: &[Put],
+        durability: Durability,
+    ) -> Result<(), DBError> {
+        self.append_with(records.iter().map(PutRef::from), durability)
+            .await
+    }
+
+    // straight from borrowed records, one write for the lot
+    pub(crate) async fn append_with<'a>(
+        &mut self,
+        records: impl Iterator<Item = PutRef<'a>>,
+        durability: Durability,
+    ) -> Result<(), DBError> {
+        if durability == Durability::None {
+            return Ok(());
+        }
+        self.scratch.clear();
+        for put in records {
+            let start = self.scratch.len();
+            serde_json::to_writer(&mut self.scratch, &put)?;
+            let crc = crc32fast::hash(&self.scratch[start..]);
+            // in place of the closing brace, which it puts back
+            self.scratch.pop();
+            self.scratch.extend_from_slice(CRC_FIELD);
+            self.scratch.extend_from_slice(crc.to_string().as_bytes());
+            self.scratch.extend_from_slice(b"}\n");
+        }
+        self.log.write_all(&self.scratch).await?;
+        // tokio hands a write to a blocking thread and returns before it's
+        // done, so without waiting a fresh handle on the file, as `get` and
+        // `reader` open, could still miss it
+        match durability {
+            Durability::Sync => self.log.sync_all().await?,
+            _ => self.log.flush().await?,
+        }
+        Ok(())
+    }
+
+    // makes everything appended so far durable
+    pub async fn sync(&self) -> Result<(), DBError> {
+        self.log.sync_all().await?;
+        Ok(())
+    }
+
+    pub async fn hydrate(&self, policy: CorruptionPolicy) -> Result<Memtable, DBError> {
+        self.hydrate_into(Memtable::default(), policy).await
+    }
+
+    // replays the log on top of a memtable of the caller's choosing
+    pub async fn hydrate_into(
+        &self,
+        mut memtable: Memtable,
+        policy: CorruptionPolicy,
+    ) -> Result<Memtable, DBError> {
+        let mut records = RecordIter::new([self.path.clone()], policy);
+        while let Some(put) = records.next_valid().await? {
+            memtable.insert(put);
+        }
+        Ok(memtable)
+    }
+
+    // the records in the log one at a time, oldest first
+    pub async fn reader(&self, policy: CorruptionPolicy) -> Result<LogReader, DBError> {
+        LogReader::open(&self.path, policy).await
+    }
+
+    // records that fail to parse or don't match their checksum, by line
+    // number
+    pub async fn verify(&self) -> Result<Vec<Problem>, DBError> {
+        let reader = File::open(&self.path).await?;
+        let mut lines = BufReader::new(reader).lines();
+        let mut problems = Vec::new();
+        let mut line_no = 0;
+        while let Some(line) = lines.next_line().await? {
+            line_no += 1;
+            if let Err(err) = serde_json::from_str::<Put>(&line) {
+                problems.push(Problem::new(
+                    &self.path,
+                    format!("bad record on line {}: {}", line_no, err),
+                ));
+            } else if !checksum_matches(line.as_bytes()) {
+                problems.push(Problem::new(
+                    &self.path,
+                    format!("bad checksum on line {}", line_no),
+                ));
+            }
+        }
+        Ok(problems)
+    }
+
+    // size of the log file in bytes
+    pub async fn size(&self) -> Result<u64, DBError> {
+        Ok(self.log.metadata().await?.len())
+    }
+
+    // everything in the log has made it into an sst, start over
+    pub async fn truncate(&mut self) -> Result<(), DBError> {
+        self.truncate_to(0).await
+    }
+
+    // drops everything past `len` bytes
+    pub async fn truncate_to(&mut self, len: u64) -> Result<(), DBError> {
+        self.log.set_len(len).await?;
+        self.log.sync_all().await?;
+        Ok(())
+    }
+}
+
+pub struct LogReader {
+    path: PathBuf,
+    reader: BufReader<File>,
+    policy: CorruptionPolicy,
+    // end of the last record read back intact
+    offset: u64,
+    // where the last record read starts
+    start: u64,
+    stopped: bool,
+    // the record reading stopped at was the last thing in the file
+    torn: bool,
+    skipped: usize,
+}
+
+impl LogReader {
+    async fn open(path: &Path, policy: CorruptionPolicy) -> Result<LogReader, DBError> {
+        let reader = File::open(path).await?;
+        Ok(LogReader {
+            path: path.to_path_buf(),
+            reader: BufReader::new(reader),
+            policy,
+            offset: 0,
+            start: 0,
+            stopped: false,
+            torn: false,
+            skipped: 0,
+        })
+    }
+
+    // the next intact record. one that doesn't match its checksum is as
+    // corrupt as one that won't parse, and goes the way the policy says.
+    pub async fn next(&mut self) -> Result<Option<Put>, DBError> {
+        while let Some((put, valid)) = self.next_checked().await? {
+            if valid {
+                return Ok(Some(put));
+            }
+            self.torn = self.reader.fill_buf().await?.is_empty();
+            self.corrupt(DBError::CorruptLog(self.path.clone(), self.start))?;
+            if self.stopped {
+                self.offset = self.start;
+            }
+        }
+        Ok(None)
+    }
+
+    // the next record that parses and whether it matches its checksum. the
+    // policy only sees records that don't parse, or that do but are missing
+    // their newline at the end of the file: an append writes the two
+    // together, so that one is as torn as any, and the next append would
+    // join its line.
+    pub async fn next_checked(&mut self) -> Result<Option<(Put, bool)>, DBError> {
+        let mut line = Vec::new();
+        while !self.stopped {
+            line.clear();
+            let read = self.reader.read_until(b'\n', &mut line).await?;
+            if read == 0 {
+                return Ok(None);
+            }
+            let record = line.strip_suffix(b"\n").unwrap_or(&line);
+            match serde_json::from_slice(record) {
+                Ok(_) if !line.ends_with(b"\n") => {
+                    self.torn = true;
+                    self.corrupt(DBError::CorruptLog(self.path.clone(), self.offset))?
+                }
+                Ok(put) => {
+                    self.start = self.offset;
+                    self.offset += read as u64;
+                    return Ok(Some((put, checksum_matches(record))));
+                }
+                Err(err) => {
+                    self.torn = !line.ends_with(b"\n") || self.reader.fill_buf().await?.is_empty();
+                    self.corrupt(err.into())?
+                }
+            }
+        }
+        Ok(None)
+    }
+
+    fn corrupt(&mut self, err: DBError) -> Result<(), DBError> {
+        match self.policy {
+            CorruptionPolicy::StopAtFirstError => self.stopped = true,
+            CorruptionPolicy::SkipCorrupt => self.skipped += 1,
+            CorruptionPolicy::Fail => return Err(err),
+        }
+        Ok(())
+    }
+
+    // where the intact records end if reading stopped at a corrupt one
+    pub fn stopped_at(&self) -> Option<u64> {
+        self.stopped.then_some(self.offset)
+    }
+
+    // whether reading stopped at a record torn off the end of the file, as a
+    // crash part way through an append leaves, or at a damaged last record.
+    // anywhere else a corrupt record is damage, with intact records after
+    // it.
+    pub fn stopped_at_tail(&self) -> bool {
+        self.stopped && self.torn
+    }
+
+    // corrupt records passed over so far
+    pub fn skipped(&self) -> usize {
+        self.skipped
+    }
+}
+
+// the records of several log segments one after another, oldest segment
+// first, as if they were one log: the logs rotated away by async flushes and
+// the one after them, say. `next` hands each record back with whether it
+// matches its checksum, `next_valid` applies the policy to those that don't
+// as well. under `CorruptionPolicy::StopAtFirstError` a bad record ends the
+// lot, later segments included, since what they hold may depend on what was
+// lost.
+pub struct RecordIter {
+    segments: Vec<PathBuf>,
+    policy: CorruptionPolicy,
+    // the segment being read and its index
+    current: Option<(usize, LogReader)>,
+    next_segment: usize,
+    stopped: Option<(usize, u64)>,
+    // reading stopped at the end of its segment
+    torn: bool,
+    skipped: usize,
+    // the segment and offset of the record last handed back
+    last: Option<(usize, u64)>,
+}
+
+impl RecordIter {
+    pub fn new(
+        segments: impl IntoIterator<Item = PathBuf>,
+        policy: CorruptionPolicy,
+    ) -> RecordIter {
+        RecordIter {
+            segments: segments.into_iter().collect(),
+            policy,
+            current: None,
+            next_segment: 0,
+            stopped: None,
+            torn: false,
+            skipped: 0,
+            last: None,
+        }
+    }
+
+    // the next record that parses and whether it matches its checksum
+    pub async fn next(&mut self) -> Result<Option<(Put, bool)>, DBError> {
+        self.advance(false).await
+    }
+
+    // the next intact record, see `LogReader::next`
+    pub async fn next_valid(&mut self) -> Result<Option<Put>, DBError> {
+        Ok(self.advance(true).await?.map(|(put, _)| put))
+    }
+
+    async fn advance(&mut self, valid: bool) -> Result<Option<(Put, bool)>, DBError> {
+        loop {
+            let (idx, reader) = match &mut self.current {
+                Some((idx, reader)) => (*idx, reader),
+                None => {
+                    if self.stopped.is_some() || self.next_segment >= self.segments.len() {
+                        return Ok(None);
+                    }
+                    let idx = self.next_segment;
+                    self.next_segment += 1;
+                    let reader = LogReader::open(&self.segments[idx], self.policy).await?;
+                    let (_, reader) = self.current.insert((idx, reader));
+                    (idx, reader)
+                }
+            };
+            let next = match valid {
+                true => reader.next().await?.map(|put| (put, true)),
+                false => reader.next_checked().await?,
+            };
+            if let Some(next) = next {
+                self.last = Some((idx, reader.start));
+                return Ok(Some(next));
+            }
+            self.skipped += reader.skipped();
+            self.stopped = reader.stopped_at().map(|offset| (idx, offset));
+            self.torn = reader.stopped_at_tail();
+            self.current = None;
+        }
+    }
+
+    // the segment the record last handed back is in, and where it starts
+    pub fn position(&self) -> Option<(&Path, u64)> {
+        self.last
+            .map(|(idx, offset)| (self.segments[idx].as_path(), offset))
+    }
+
+    // the segment a corrupt record stopped reading in, and where its intact
+    // records end. None while reading goes on, or when it reached the end.
+    pub fn stopped_at(&self) -> Option<(&Path, u64)> {
+        self.stopped
+            .map(|(idx, offset)| (self.segments[idx].as_path(), offset))
+    }
+
+    // whether reading stopped at a torn or damaged last record of the last
+    // segment, with nothing after it that's lost by stopping there
+    pub fn stopped_at_tail(&self) -> bool {
+        self.torn
+            && self
+                .stopped
+                .is_some_and(|(idx, _)| idx + 1 == self.segments.len())
+    }
+
+    // corrupt records passed over so far, in every segment
+    pub fn skipped(&self) -> usize {
+        self.skipped
+            + self
+                .current
+                .as_ref()
+                .map_or(0, |(_, reader)| reader.skipped())
+    }
+}
+
+#[async_trait]
+impl Queryable for Log {
+    async fn get(&self, key: &str) -> Result<Lookup, DBError> {
+        let reader = File::open(&self.path).await?;
+        let reader = BufReader::new(reader);
+        let mut lines = reader.lines();
+        let mut result = Lookup::Absent;
+        let mut unparsed = None;
+        while let Some(line) = lines.next_line().await? {
+            if let Some(err) = unparsed.take() {
+                return Err(err);
+            }
+            match serde_json::from_str::<Put>(&line) {
+                Ok(put) if put.key == key => result = put.value.into(),
+                Ok(_) => {}
+                Err(err) => unparsed = Some(DBError::from(err)),
+            }
+        }
+        // only a last line can be an append another handle is still part way
+        // through, that record isn't there yet
+        Ok(result)
+    }
+}
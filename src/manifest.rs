@@ -0,0 +1,233 @@
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+use tokio::{
+    fs::{File, OpenOptions},
+    io::AsyncWriteExt,
+};
+
+use crate::error::DBError;
+
+const MANIFEST: &str = "manifest";
+const EDITS: &str = "manifest.log";
+const SEQ: &str = "seq";
+// edits appended before they're folded into a fresh snapshot
+const EDIT_LIMIT: u64 = 256;
+
+// the live sst set. its presence marks a bootstrapped database directory, so
+// it's always the last file written when one is created. on disk it's a
+// snapshot in `manifest` plus the edits appended to `manifest.log` since.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct Manifest {
+    pub next_sst_id: u64,
+    // relative to the database directory and without extension, newest first
+    pub ssts: Vec<PathBuf>,
+    // id of the last edit applied, 0 for none
+    #[serde(default)]
+    pub last_edit: u64,
+    // the last edit in the snapshot this was loaded from
+    #[serde(skip)]
+    pub(crate) snapshot_edit: u64,
+    // the next sequence number when this version was written, a copy of
+    // what's in the seq file for when that's lost
+    #[serde(default)]
+    pub seq: u64,
+}
+
+// one step from a version of the sst set to the next, written as a single
+// line so that it's either wholly there or not at all
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct VersionEdit {
+    pub id: u64,
+    pub next_sst_id: u64,
+    pub removed: Vec<PathBuf>,
+    // each sst added with its place in the new list, in increasing order
+    pub added: Vec<(usize, PathBuf)>,
+    #[serde(default)]
+    pub seq: u64,
+}
+
+impl VersionEdit {
+    // the edit taking from to to. the ssts both have keep their relative
+    // order, as every change to the set does.
+    pub fn between(from: &Manifest, to: &Manifest) -> VersionEdit {
+        VersionEdit {
+            id: from.last_edit + 1,
+            next_sst_id: to.next_sst_id,
+            removed: from
+                .ssts
+                .iter()
+                .filter(|sst| !to.ssts.contains(sst))
+                .cloned()
+                .collect(),
+            added: to
+                .ssts
+                .iter()
+                .enumerate()
+                .filter(|(_, sst)| !from.ssts.contains(sst))
+                .map(|(idx, sst)| (idx, sst.clone()))
+                .collect(),
+            seq: to.seq,
+        }
+    }
+}
+
+// via a temp file and a rename, so a crash leaves either the old contents or
+// the new ones
+pub(crate) async fn write_atomic(path: &Path, data: &[u8]) -> Result<(), DBError> {
+    let tmp = path.with_extension("tmp");
+    let mut file = File::create(&tmp).await?;
+    file.write_all(data).await?;
+    file.sync_all().await?;
+    tokio::fs::rename(&tmp, path).await?;
+    Ok(())
+}
+
+async fn read_optional(path: &Path) -> Result<Option<Vec<u8>>, DBError> {
+    match tokio::fs::read(path).await {
+        Ok(data) => Ok(Some(data)),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(err.into()),
+    }
+}
+
+impl Manifest {
+    pub fn path(dir: &Path) -> PathBuf {
+        dir.join(MANIFEST)
+    }
+
+    pub fn edits_path(dir: &Path) -> PathBuf {
+        dir.join(EDITS)
+    }
+
+    // the snapshot with the edits logged since replayed onto it. None for a
+    // directory that was never bootstrapped.
+    pub async fn load(dir: &Path) -> Result<Option<Manifest>, DBError> {
+        let Some(data) = read_optional(&Manifest::path(dir)).await? else {
+            return Ok(None);
+        };
+        let mut manifest: Manifest = serde_json::from_slice(&data)?;
+        manifest.snapshot_edit = manifest.last_edit;
+        let edits = read_optional(&Manifest::edits_path(dir))
+            .await?
+            .unwrap_or_default();
+        let mut lines = edits.split(|byte| *byte == b'\n').peekable();
+        let mut intact = 0;
+        while let Some(line) = lines.next() {
+            if line.is_empty() {
+                intact += 1;
+                continue;
+            }
+            let edit: VersionEdit = match serde_json::from_slice(line) {
+                Ok(edit) => edit,
+                // an append cut short by a crash, it never took effect. it's
+                // cut off so the next append starts on a line of its own.
+                Err(_) if lines.peek().is_none() => {
+                    let file = OpenOptions::new()
+                        .write(true)
+                        .open(Manifest::edits_path(dir))
+                        .await?;
+                    file.set_len(intact as u64).await?;
+                    file.sync_all().await?;
+                    break;
+                }
+                Err(err) => return Err(err.into()),
+            };
+            intact += line.len() + 1;
+            // already in the snapshot when one was written and the log not
+            // yet cleared
+            if edit.id > manifest.last_edit {
+                manifest.apply(&edit);
+            }
+        }
+        Ok(Some(manifest))
+    }
+
+    pub fn apply(&mut self, edit: &VersionEdit) {
+        self.ssts.retain(|sst| !edit.removed.contains(sst));
+        for (idx, sst) in &edit.added {
+            self.ssts.insert((*idx).min(self.ssts.len()), sst.clone());
+        }
+        self.next_sst_id = edit.next_sst_id;
+        self.last_edit = edit.id;
+        self.seq = edit.seq;
+    }
+
+    // writes a complete snapshot and clears the edit log, which the snapshot
+    // now covers. a crash in between leaves edits the snapshot already holds,
+    // and loading skips those.
+    pub async fn store(&self, dir: &Path) -> Result<(), DBError> {
+        write_atomic(&Manifest::path(dir), &serde_json::to_vec(self)?).await?;
+        match tokio::fs::remove_file(Manifest::edits_path(dir)).await {
+            Err(err) if err.kind() != std::io::ErrorKind::NotFound => Err(err.into()),
+            _ => Ok(()),
+        }
+    }
+
+    // moves the manifest in dir on to next in one atomic step, by appending
+    // the edit between them. every so often the edits are folded into a new
+    // snapshot instead.
+    pub async fn commit(&mut self, dir: &Path, next: Manifest) -> Result<(), DBError> {
+        let edit = VersionEdit::between(self, &next);
+        let unchanged = edit.removed.is_empty()
+            && edit.added.is_empty()
+            && edit.next_sst_id == self.next_sst_id
+            && edit.seq == self.seq;
+        if unchanged {
+            return Ok(());
+        }
+        let mut updated = self.clone();
+        updated.apply(&edit);
+        if edit.id - self.snapshot_edit > EDIT_LIMIT {
+            updated.store(dir).await?;
+            updated.snapshot_edit = updated.last_edit;
+        } else {
+            append_edit(&Manifest::edits_path(dir), &edit).await?;
+        }
+        *self = updated;
+        Ok(())
+    }
+}
+
+// an append that fails part way is cut back off, so the next one doesn't
+// land after half a line
+async fn append_edit(path: &Path, edit: &VersionEdit) -> Result<(), DBError> {
+    let mut line = serde_json::to_vec(edit)?;
+    line.push(b'\n');
+    let mut file = OpenOptions::new()
+        .append(true)
+        .create(true)
+        .open(path)
+        .await?;
+    let len = file.metadata().await?.len();
+    let written = async {
+        file.write_all(&line).await?;
+        file.sync_all().await
+    }
+    .await;
+    if let Err(err) = written {
+        let _ = file.set_len(len).await;
+        return Err(err.into());
+    }
+    Ok(())
+}
+
+pub fn seq_path(dir: &Path) -> PathBuf {
+    dir.join(SEQ)
+}
+
+// the next sequence number as of the last flush or compaction. it's a floor:
+// records written since then can only have pushed it higher.
+pub async fn load_seq(dir: &Path) -> Result<Option<u64>, DBError> {
+    let Some(data) = read_optional(&seq_path(dir)).await? else {
+        return Ok(None);
+    };
+    let seq = String::from_utf8_lossy(&data);
+    seq.trim().parse().map(Some).map_err(|_| {
+        DBError::InvalidInput(format!("bad sequence number {:?} in seq file", seq.trim()))
+    })
+}
+
+pub async fn store_seq(dir: &Path, seq: u64) -> Result<(), DBError> {
+    write_atomic(&seq_path(dir), seq.to_string().as_bytes()).await
+}
@@ -0,0 +1,315 @@
+use std::{collections::BTreeMap, ops::Bound, sync::Arc};
+
+use async_trait::async_trait;
+use crossbeam_skiplist::SkipMap;
+
+use crate::{error::DBError, log::Put, vlog::ValueRef, Lookup, Queryable};
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Entry {
+    pub seq: u64,
+    // None is a tombstone
+    pub value: Option<String>,
+    pub flags: u8,
+    // a merge operand, see `Put::merge`
+    pub merge: bool,
+    // where the value is when it went straight to the value log, see
+    // `Put::value_ref`
+    pub value_ref: Option<ValueRef>,
+}
+
+impl Entry {
+    pub fn to_put(&self, key: &str) -> Put {
+        Put {
+            key: key.to_string(),
+            value: self.value.clone(),
+            seq: self.seq,
+            flags: self.flags,
+            value_ref: self.value_ref,
+            merge: self.merge,
+        }
+    }
+}
+
+fn bounds<'a>(start: &'a str, end: Option<&'a str>) -> (Bound<&'a str>, Bound<&'a str>) {
+    let end = match end {
+        Some(end) => Bound::Excluded(end),
+        None => Bound::Unbounded,
+    };
+    (Bound::Included(start), end)
+}
+
+enum Table {
+    BTree(BTreeMap<String, Entry>),
+    // every version of each key, newest first
+    Versions(BTreeMap<String, Vec<Entry>>),
+    // inserts go through a shared reference, so readers holding a
+    // `MemtableReader` never wait on the writer
+    SkipList(Arc<SkipMap<String, Entry>>),
+}
+
+pub struct Memtable {
+    memtable: Table,
+    max_seq: u64,
+    bytes: usize,
+}
+
+// what a record costs the memtable, roughly
+fn record_bytes(key_len: usize, entry: &Entry) -> usize {
+    key_len + entry.value.as_ref().map_or(0, String::len) + std::mem::size_of::<Entry>()
+}
+
+impl Default for Memtable {
+    fn default() -> Self {
+        Memtable::new(BTreeMap::new())
+    }
+}
+
+impl Memtable {
+    pub fn new(memtable: BTreeMap<String, Entry>) -> Self {
+        let max_seq = memtable.values().map(|entry| entry.seq).max().unwrap_or(0);
+        let bytes = memtable
+            .iter()
+            .map(|(key, entry)| record_bytes(key.len(), entry))
+            .sum();
+        Memtable {
+            memtable: Table::BTree(memtable),
+            max_seq,
+            bytes,
+        }
+    }
+
+    // overwrites keep the versions they replace, so they reach the sst on
+    // flush rather than being gone as soon as the key is written again
+    pub fn versioned() -> Self {
+        Memtable {
+            memtable: Table::Versions(BTreeMap::new()),
+            max_seq: 0,
+            bytes: 0,
+        }
+    }
+
+    // from now on overwrites keep the versions they replace, as in a
+    // `versioned` memtable. the skip list holds one version whatever.
+    pub fn keep_versions(&mut self) {
+        if let Table::BTree(map) = &mut self.memtable {
+            let versions = std::mem::take(map)
+                .into_iter()
+                .map(|(key, entry)| (key, vec![entry]))
+                .collect();
+            self.memtable = Table::Versions(versions);
+        }
+    }
+
+    pub fn skip_list() -> Self {
+        Memtable {
+            memtable: Table::SkipList(Arc::new(SkipMap::new())),
+            max_seq: 0,
+            bytes: 0,
+        }
+    }
+
+    // a handle that reads the skip list concurrently with writes, None for
+    // the btree memtable
+    pub fn reader(&self) -> Option<MemtableReader> {
+        match &self.memtable {
+            Table::BTree(_) | Table::Versions(_) => None,
+            Table::SkipList(map) => Some(MemtableReader { map: map.clone() }),
+        }
+    }
+
+    pub fn insert(&mut self, put: Put) {
+        // an operand only means something on top of what it follows
+        if put.merge {
+            self.keep_versions();
+        }
+        self.max_seq = self.max_seq.max(put.seq);
+        let entry = Entry {
+            seq: put.seq,
+            value: put.value,
+            flags: put.flags,
+            merge: put.merge,
+            value_ref: put.value_ref,
+        };
+        // the key moves into the table
+        let key_len = put.key.len();
+        self.bytes += record_bytes(key_len, &entry);
+        let replaced = match &mut self.memtable {
+            Table::BTree(map) => map.insert(put.key, entry),
+            Table::Versions(map) => {
+                map.entry(put.key).or_default().insert(0, entry);
+                None
+            }
+            Table::SkipList(map) => {
+                let replaced = map.get(&put.key).map(|old| old.value().clone());
+                map.insert(put.key, entry);
+                replaced
+            }
+        };
+        if let Some(old) = replaced {
+            self.bytes -= record_bytes(key_len, &old);
+        }
+    }
+
+    pub fn put(&mut self, key: String, value: String, seq: u64) {
+        self.insert(Put {
+            key,
+            value: Some(value),
+            seq,
+            flags: 0,
+            value_ref: None,
+            merge: false,
+        });
+    }
+
+    pub fn delete(&mut self, key: String, seq: u64) {
+        self.insert(Put {
+            key,
+            value: None,
+            seq,
+            flags: 0,
+            value_ref: None,
+            merge: false,
+        });
+    }
+
+    // records, counting every version a versioned memtable holds
+    pub fn len(&self) -> usize {
+        match &self.memtable {
+            Table::BTree(map) => map.len(),
+            Table::Versions(map) => map.values().map(Vec::len).sum(),
+            Table::SkipList(map) => map.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        match &self.memtable {
+            Table::BTree(map) => map.is_empty(),
+            Table::Versions(map) => map.is_empty(),
+            Table::SkipList(map) => map.is_empty(),
+        }
+    }
+
+    // approximate memory held by the records
+    pub fn size_bytes(&self) -> usize {
+        self.bytes
+    }
+
+    // highest sequence number inserted since the last clear, 0 when empty
+    pub fn max_seq(&self) -> u64 {
+        self.max_seq
+    }
+
+    // sorted by key, ready to be written out as an sst
+    pub fn iter(&self) -> Box<dyn Iterator<Item = Put> + Send + '_> {
+        match &self.memtable {
+            Table::BTree(map) => Box::new(map.iter().map(|(key, entry)| entry.to_put(key))),
+            Table::Versions(map) => Box::new(
+                map.iter()
+                    .flat_map(|(key, versions)| versions.iter().map(|entry| entry.to_put(key))),
+            ),
+            Table::SkipList(map) => Box::new(map.iter().map(|e| e.value().to_put(e.key()))),
+        }
+    }
+
+    // borrows the records in key order, tombstones included. None for the
+    // skip list memtable, whose entries can't be lent out past the guard
+    // that keeps them alive.
+    pub fn iter_sorted(&self) -> Option<Box<dyn Iterator<Item = (&str, &Entry)> + Send + '_>> {
+        match &self.memtable {
+            Table::BTree(map) => Some(Box::new(
+                map.iter().map(|(key, entry)| (key.as_str(), entry)),
+            )),
+            Table::Versions(map) => {
+                Some(Box::new(map.iter().flat_map(|(key, versions)| {
+                    versions.iter().map(|entry| (key.as_str(), entry))
+                })))
+            }
+            Table::SkipList(_) => None,
+        }
+    }
+
+    // copies out [start, end) so a scan doesn't keep the memtable borrowed
+    pub fn range(&self, start: &str, end: Option<&str>) -> Vec<Put> {
+        match &self.memtable {
+            Table::BTree(map) => map
+                .range::<str, _>(bounds(start, end))
+                .map(|(key, entry)| entry.to_put(key))
+                .collect(),
+            Table::Versions(map) => map
+                .range::<str, _>(bounds(start, end))
+                .flat_map(|(key, versions)| versions.iter().map(|entry| entry.to_put(key)))
+                .collect(),
+            Table::SkipList(map) => skip_list_range(map, start, end),
+        }
+    }
+
+    pub fn get_record(&self, key: &str) -> Option<Put> {
+        match &self.memtable {
+            Table::BTree(map) => map.get(key).map(|entry| entry.to_put(key)),
+            Table::Versions(map) => map.get(key)?.first().map(|entry| entry.to_put(key)),
+            Table::SkipList(map) => map.get(key).map(|e| e.value().to_put(key)),
+        }
+    }
+
+    // every version held of key, newest first
+    pub fn get_versions(&self, key: &str) -> Vec<Put> {
+        match &self.memtable {
+            Table::Versions(map) => map.get(key).map_or_else(Vec::new, |versions| {
+                versions.iter().map(|entry| entry.to_put(key)).collect()
+            }),
+            _ => self.get_record(key).into_iter().collect(),
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.bytes = 0;
+        self.max_seq = 0;
+        match &mut self.memtable {
+            Table::BTree(map) => map.clear(),
+            Table::Versions(map) => map.clear(),
+            Table::SkipList(map) => map.clear(),
+        }
+    }
+}
+
+fn skip_list_range(map: &SkipMap<String, Entry>, start: &str, end: Option<&str>) -> Vec<Put> {
+    map.range::<str, _>(bounds(start, end))
+        .map(|e| e.value().to_put(e.key()))
+        .collect()
+}
+
+// shares a skip list memtable with the db that writes to it. every record
+// is seen either as it was before an insert or after, never half written.
+#[derive(Clone)]
+pub struct MemtableReader {
+    map: Arc<SkipMap<String, Entry>>,
+}
+
+impl MemtableReader {
+    pub fn get_record(&self, key: &str) -> Option<Put> {
+        self.map.get(key).map(|e| e.value().to_put(key))
+    }
+
+    pub fn range(&self, start: &str, end: Option<&str>) -> Vec<Put> {
+        skip_list_range(&self.map, start, end)
+    }
+
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+}
+
+#[async_trait]
+impl Queryable for Memtable {
+    async fn get(&self, key: &str) -> Result<Lookup, DBError> {
+        Ok(match self.get_record(key) {
+            Some(put) => put.value.into(),
+            None => Lookup::Absent,
+        })
+    }
+}
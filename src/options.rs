@@ -0,0 +1,250 @@
+use std::{fmt, path::PathBuf, sync::Arc, time::Duration};
+
+use async_trait::async_trait;
+
+use crate::{
+    bloom::KeyHasher,
+    clock::Clock,
+    error::DBError,
+    log::{CorruptionPolicy, Durability, Put},
+    rate_limit::RateLimiter,
+    sst::Sst,
+};
+
+pub type WriteCallback = Arc<dyn Fn(&Put) + Send + Sync>;
+pub type SstCallback = Arc<dyn Fn(&Sst) + Send + Sync>;
+
+// called once the event has happened and the db's own state is up to date.
+// they run inline on the calling task, so they should be quick.
+#[derive(Clone, Default)]
+pub struct Callbacks {
+    // a put or delete is in the log, with the record as written
+    pub on_write: Option<WriteCallback>,
+    // with the new L0 sst
+    pub on_flush: Option<SstCallback>,
+    // with the merged output sst
+    pub on_compaction: Option<SstCallback>,
+}
+
+impl fmt::Debug for Callbacks {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Callbacks")
+            .field("on_write", &self.on_write.is_some())
+            .field("on_flush", &self.on_flush.is_some())
+            .field("on_compaction", &self.on_compaction.is_some())
+            .finish()
+    }
+}
+
+// handed every write once it's as durable as `DbOptions::durability` makes
+// it, before the write returns, so it can be streamed to a follower in
+// order. an error fails the write, which is taken back out again and never
+// becomes visible.
+#[async_trait]
+pub trait ReplicationSink: fmt::Debug + Send + Sync {
+    async fn replicate(&self, records: &[Put]) -> Result<(), DBError>;
+}
+
+// folds a `Db::merge` operand onto the value before it, existing is None
+// when the key had none or was deleted. gets and scans call it as they read,
+// oldest operand first.
+pub trait MergeOperator: fmt::Debug + Send + Sync {
+    fn merge(&self, key: &str, existing: Option<&str>, operand: &str) -> String;
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct DbOptions {
+    // probe every sst concurrently on a point lookup instead of newest to
+    // oldest. the record with the highest sequence number still wins.
+    pub parallel_reads: bool,
+    // defer reading each sst's index and bloom filter until it is first
+    // queried, rather than loading all of them in `Db::open`
+    pub lazy_sst_open: bool,
+    // how many ssts `Db::open` reads in at once, 0 means 8
+    pub open_parallelism: usize,
+    // keep each level's ssts in its own subdirectory (`L0`, `L1`, ...)
+    // instead of all of them directly in the database directory
+    pub level_dirs: bool,
+    // back the memtable with a concurrent skip list so that a
+    // `MemtableReader` can read it while the db is writing
+    pub skip_list_memtable: bool,
+    // an overwrite in the memtable keeps the version it replaces, so every
+    // version is flushed and `versions_to_keep` can see them. off, a key
+    // holds one record in the memtable and overwrites free the old one.
+    // not for the skip list memtable, which always holds one.
+    pub memtable_versions: bool,
+    // values longer than this many bytes are written to a separate value
+    // log at flush, with ssts holding only a pointer to them. compaction then
+    // copies the pointer rather than the value. None keeps values inline.
+    pub value_separation_threshold: Option<usize>,
+    // give each sst a second bloom filter over the first this many bytes of
+    // its keys, letting `Db::scan_prefix` skip ssts without the prefix
+    pub prefix_bloom_len: Option<usize>,
+    // new ssts store their sparse index prefix compressed, each key as the
+    // length it shares with the key before plus the rest
+    pub compressed_index: bool,
+    // the hash new ssts build their bloom filters with, xxh64 by default.
+    // ssts already written keep using the one they were built with.
+    pub bloom_hasher: KeyHasher,
+    // how many of the newest versions of each key compaction holds on to,
+    // readable through `Db::get_versions`. 0 and 1 both keep just the latest.
+    // versions overwritten while still in the memtable are only kept with
+    // `memtable_versions`.
+    pub versions_to_keep: usize,
+    // a write that takes the memtable to this many bytes or more flushes it.
+    // None leaves flushing to `Db::flush` and `Db::flush_if_idle`.
+    pub flush_threshold: Option<usize>,
+    // crossing `flush_threshold` swaps in an empty memtable and writes the
+    // full one out on its own task, so the write returns straight away.
+    // reads still see its records until the sst is in place. off, the write
+    // waits for the flush. the skip list memtable and value separation
+    // always flush in line.
+    pub async_flush: bool,
+    // how many full memtables `async_flush` lets wait to be written at once,
+    // 0 means 1. filling one more waits for the oldest flush to finish
+    // first. they're written at the same time, but their ssts go in oldest
+    // first, each newer than the ones before it, and reads look at the
+    // memtables newest first.
+    pub max_flushing_memtables: usize,
+    // nothing is started in the background: `Db::maybe_compact` does
+    // nothing and a write crossing `flush_threshold` with `async_flush`
+    // leaves the flush waiting. `Db::run_background_once` then does that
+    // work a step at a time, so tests can see the tree at every step.
+    pub manual_background: bool,
+    // bytes of memtable recovery may build up from the log before flushing
+    // it to an sst, so a huge log can't exhaust memory on open. None means
+    // no limit.
+    pub memtable_budget: Option<usize>,
+    // keep the log here rather than in the database directory, say on a
+    // faster device. every open has to pass the same one, or writes still
+    // waiting in the old log are missed. the ssts, manifest and value log
+    // stay in the database directory.
+    pub wal_dir: Option<PathBuf>,
+    // whether each write is fsynced to the log before it returns, or logged
+    // at all
+    pub durability: Durability,
+    // what replaying the log on open does with a record that won't parse or
+    // doesn't match its checksum. stopping only goes as far as a torn tail,
+    // which is cut off, a corrupt record with more after it fails the open
+    // with `DBError::CorruptLog` and leaves the log as it is.
+    pub log_corruption: CorruptionPolicy,
+    // how many ssts it takes for `Db::maybe_compact` to start a compaction,
+    // 0 means 4
+    pub compaction_trigger: usize,
+    // the shape `Db::compact_leveled` keeps the ssts in: L1 holds up to
+    // `level_base_bytes`, each level below `level_multiplier` times the one
+    // above, and the last of `max_levels` (counting L0) as much as it takes.
+    // it writes ssts of about a quarter of `level_base_bytes`. 0 means 7
+    // levels, 64 MiB and 10 respectively.
+    pub max_levels: usize,
+    pub level_base_bytes: u64,
+    pub level_multiplier: u64,
+    // every flush goes on to `Db::drop_shadowed_ssts`, dropping the ssts
+    // the new one and those before it hide entirely. it reads each sst whose
+    // key range they cover.
+    pub auto_drop_shadowed: bool,
+    // bytes read at a time from each input sst during compaction, 0 keeps
+    // the default of 8 KiB
+    pub compaction_read_ahead: usize,
+    // `Db::pick_compaction_input` favours the sst overlapping the least of
+    // the next level over the largest one
+    pub compaction_pick_by_overlap: bool,
+    // compaction only merges ssts whose key range overlaps another's, and
+    // leaves the rest as they are rather than rewriting them. their old
+    // versions and tombstones stay until they overlap something.
+    // `Db::major_compact` still rewrites everything.
+    pub compaction_skip_disjoint: bool,
+    // scans and compactions let other tasks on the runtime run after every
+    // this many records they merge, 0 means 1024
+    pub yield_every: usize,
+    // caps the bytes compactions read and write a second, foreground or
+    // background, so they leave the disk to reads and writes. the limiter
+    // can be shared between databases to cap them together. None doesn't
+    // throttle them.
+    pub compaction_rate_limit: Option<Arc<RateLimiter>>,
+    // bytes buffered while writing an sst before they go to the file, 0
+    // keeps the default of 64 KiB
+    pub sst_write_buffer: usize,
+    // a point lookup that had to read this many ssts or more to find its key
+    // keeps the record it found in a small cache, checked before the ssts,
+    // so reading the key again is fast. None turns read repair off.
+    pub read_repair: Option<usize>,
+    // how many sst data files point lookups keep open between reads, closing
+    // the least recently used past that. None opens the file afresh for
+    // every lookup. scans and compaction always open their own.
+    pub max_open_files: Option<usize>,
+    // reads carry on past ssts whose files have disappeared, as if they were
+    // empty, instead of failing with `DBError::MissingSst`
+    pub skip_missing: bool,
+    // check that the records every scan and compaction merges come out in
+    // key order, failing with `DBError::InvalidInput` at the first that
+    // doesn't rather than returning or writing them. costs a key copy per
+    // record. ssts always refuse to be written out of order.
+    pub paranoid_checks: bool,
+    // time gets, writes, flushes and compactions into the histograms
+    // `Db::latency_histogram` returns
+    pub record_latency: bool,
+    // changes each `Db::subscribe` stream holds while its reader catches
+    // up, 0 means 1024
+    pub change_feed_capacity: usize,
+    pub callbacks: Callbacks,
+    pub replication: Option<Arc<dyn ReplicationSink>>,
+    // what `Db::merge` operands mean. compaction keeps operands as they
+    // are, along with the version under them, so reads fold them every time.
+    // None makes `Db::merge` fail with `InvalidInput`.
+    pub merge_operator: Option<Arc<dyn MergeOperator>>,
+    // the system clock when None
+    pub clock: Option<Arc<dyn Clock>>,
+    // `Db::flush_if_idle` flushes once there have been no writes for this
+    // long, `Db::spawn_idle_flush` keeps checking. None turns idle flushing
+    // off.
+    pub idle_flush: Option<Duration>,
+    // limits on how long a call may run before failing with
+    // `DBError::Timeout`. the scan limit covers the whole iteration, from
+    // `Db::scan` to the last `next`. None means no limit.
+    pub get_timeout: Option<Duration>,
+    pub scan_timeout: Option<Duration>,
+    pub compact_timeout: Option<Duration>,
+}
+
+// what `Db::scan_options` reads and how. the defaults scan every live key
+// from start, in key order.
+#[derive(Clone, Debug, Default)]
+pub struct ScanOptions {
+    pub start: String,
+    pub start_exclusive: bool,
+    // None runs to the end of the keyspace
+    pub end: Option<String>,
+    pub end_inclusive: bool,
+    // largest key first. there's no reverse iteration underneath, so the
+    // whole range is read before the first key comes back.
+    pub reverse: bool,
+    pub limit: Option<usize>,
+    // values come back empty and separated ones aren't read from the value
+    // log
+    pub keys_only: bool,
+    // only records with this sequence number or lower are seen. versions
+    // compacted away, or overwritten in the memtable without
+    // `DbOptions::memtable_versions`, are gone, so a key changed since may be
+    // missed rather than shown as it was. a `Db::snapshot` held meanwhile
+    // keeps them from both.
+    pub snapshot: Option<u64>,
+}
+
+// how `Db::write_with_options` makes one write durable
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct WriteOptions {
+    // true fsyncs the log before the write returns, false writes it to the
+    // log without waiting for the disk. None does what
+    // `DbOptions::durability` says.
+    pub sync: Option<bool>,
+}
+
+// which way `Db::get_closest` looks from its key
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Seek {
+    // the largest live key at or before it
+    AtOrBefore,
+    // the smallest live key at or after it
+    AtOrAfter,
+}
@@ -0,0 +1,74 @@
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    time::Duration,
+};
+
+use tokio::time::Instant;
+
+// a token bucket over bytes. it holds up to a tenth of a second's worth, so
+// a burst after a quiet spell is short. taking more than there is puts the
+// bucket in debt, and the taker sleeps until it's paid off. waits are real
+// time, not `DbOptions::clock`'s, since they have to actually pass.
+#[derive(Debug)]
+pub struct RateLimiter {
+    bytes_per_sec: u64,
+    bucket: Mutex<Bucket>,
+    taken: AtomicU64,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    // negative while in debt
+    tokens: f64,
+    refilled: Instant,
+}
+
+impl RateLimiter {
+    // a rate of 0 is taken as 1 byte a second
+    pub fn new(bytes_per_sec: u64) -> RateLimiter {
+        let bytes_per_sec = bytes_per_sec.max(1);
+        RateLimiter {
+            bytes_per_sec,
+            bucket: Mutex::new(Bucket {
+                tokens: RateLimiter::capacity(bytes_per_sec),
+                refilled: Instant::now(),
+            }),
+            taken: AtomicU64::new(0),
+        }
+    }
+
+    fn capacity(bytes_per_sec: u64) -> f64 {
+        bytes_per_sec as f64 / 10.0
+    }
+
+    pub fn bytes_per_sec(&self) -> u64 {
+        self.bytes_per_sec
+    }
+
+    // bytes taken since it was made
+    pub fn taken(&self) -> u64 {
+        self.taken.load(Ordering::Relaxed)
+    }
+
+    // returns once bytes can go without going over the rate
+    pub async fn take(&self, bytes: u64) {
+        self.taken.fetch_add(bytes, Ordering::Relaxed);
+        let wait = {
+            let mut bucket = self.bucket.lock().expect("rate limiter lock poisoned");
+            let now = Instant::now();
+            let refill =
+                now.duration_since(bucket.refilled).as_secs_f64() * self.bytes_per_sec as f64;
+            bucket.tokens = (bucket.tokens + refill).min(RateLimiter::capacity(self.bytes_per_sec));
+            bucket.refilled = now;
+            bucket.tokens -= bytes as f64;
+            if bucket.tokens >= 0.0 {
+                return;
+            }
+            Duration::from_secs_f64(-bucket.tokens / self.bytes_per_sec as f64)
+        };
+        tokio::time::sleep(wait).await;
+    }
+}
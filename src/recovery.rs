@@ -0,0 +1,39 @@
+use std::path::PathBuf;
+
+// what `Db::open_with_report` did to bring the database back
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct RecoveryReport {
+    // there was no manifest yet, so the ssts were found by listing the
+    // directory and adopted
+    pub bootstrapped: bool,
+    // live ssts, from the manifest or found while bootstrapping
+    pub ssts_opened: usize,
+    // left behind by sst writes a crash cut short
+    pub temp_files_removed: Vec<PathBuf>,
+    // ssts no longer or not yet in the manifest, without extension
+    pub orphans_removed: Vec<PathBuf>,
+    pub records_replayed: usize,
+    // log records that didn't parse and were passed over, with
+    // `CorruptionPolicy::SkipCorrupt`
+    pub corrupt_records_skipped: usize,
+    // cut off the end of the log after the last intact record
+    pub log_bytes_truncated: u64,
+    // the seq file was missing or unreadable and was written again from the
+    // manifest's copy of the sequence number
+    pub seq_file_rebuilt: bool,
+    // written while replaying because the memtable went over
+    // `DbOptions::memtable_budget`
+    pub ssts_flushed: usize,
+}
+
+impl RecoveryReport {
+    // whether the database was closed cleanly, with nothing to clean up or
+    // throw away
+    pub fn is_clean(&self) -> bool {
+        self.temp_files_removed.is_empty()
+            && self.orphans_removed.is_empty()
+            && self.corrupt_records_skipped == 0
+            && self.log_bytes_truncated == 0
+            && !self.seq_file_rebuilt
+    }
+}
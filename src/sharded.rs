@@ -0,0 +1,198 @@
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+use tokio::sync::RwLock;
+
+use crate::{
+    bloom::KeyHasher, db::Db, error::DBError, iter::DbIter, manifest::write_atomic,
+    options::DbOptions,
+};
+
+// records how many shards the directory was split into and the hash that
+// split it, keys would be looked for in the wrong shard under any other
+const SHARDS: &str = "shards";
+
+// what `ShardedDb::open` splits keys with. it's written down with the shard
+// count, so changing it here doesn't move the keys of a directory already
+// split.
+const SHARD_HASHER: KeyHasher = KeyHasher::XxHash64;
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+struct Layout {
+    shards: usize,
+    hasher: KeyHasher,
+}
+
+impl Layout {
+    // a bare count is from before the hash was recorded, when it was
+    // always fnv-1a
+    fn parse(data: &str) -> Result<Layout, DBError> {
+        if let Ok(shards) = data.trim().parse() {
+            return Ok(Layout {
+                shards,
+                hasher: KeyHasher::Fnv1a,
+            });
+        }
+        Ok(serde_json::from_str(data)?)
+    }
+}
+
+// keys spread by hash over independent databases in subdirectories of one
+// directory, each with its own log, memtable and ssts. every shard has its
+// own lock, so writes to different shards go ahead together.
+pub struct ShardedDb {
+    shards: Vec<RwLock<Db>>,
+    hasher: KeyHasher,
+}
+
+fn shard_name(idx: usize) -> String {
+    format!("shard-{:03}", idx)
+}
+
+impl ShardedDb {
+    // every shard is opened with options. the count can't change once the
+    // directory holds data, opening with another fails with `InvalidInput`.
+    // keys are split by xxh64.
+    pub async fn open(
+        dir: impl AsRef<Path>,
+        shards: usize,
+        options: DbOptions,
+    ) -> Result<ShardedDb, DBError> {
+        ShardedDb::open_with_hasher(dir, shards, SHARD_HASHER, options).await
+    }
+
+    // `open` splitting keys by hasher, which can't change once the
+    // directory holds data either
+    pub async fn open_with_hasher(
+        dir: impl AsRef<Path>,
+        shards: usize,
+        hasher: KeyHasher,
+        options: DbOptions,
+    ) -> Result<ShardedDb, DBError> {
+        let dir = dir.as_ref();
+        if shards == 0 {
+            return Err(DBError::InvalidInput(
+                "a sharded db needs at least one shard".to_string(),
+            ));
+        }
+        tokio::fs::create_dir_all(dir).await?;
+        let layout_path = dir.join(SHARDS);
+        match tokio::fs::read_to_string(&layout_path).await {
+            Ok(data) => {
+                let layout = Layout::parse(&data)?;
+                if layout.shards != shards {
+                    return Err(DBError::InvalidInput(format!(
+                        "{} was split into {} shards, not {}",
+                        dir.display(),
+                        layout.shards,
+                        shards
+                    )));
+                }
+                if layout.hasher != hasher {
+                    return Err(DBError::InvalidInput(format!(
+                        "{} was split by {:?}, not {:?}",
+                        dir.display(),
+                        layout.hasher,
+                        hasher
+                    )));
+                }
+            }
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+                let layout = serde_json::to_vec(&Layout { shards, hasher })?;
+                write_atomic(&layout_path, &layout).await?;
+            }
+            Err(err) => return Err(err.into()),
+        }
+        let mut opened = Vec::with_capacity(shards);
+        for idx in 0..shards {
+            let mut options = options.clone();
+            // each shard's log apart from the others'
+            options.wal_dir = options.wal_dir.map(|wal_dir| wal_dir.join(shard_name(idx)));
+            if let Some(wal_dir) = &options.wal_dir {
+                tokio::fs::create_dir_all(wal_dir).await?;
+            }
+            let db = Db::open(dir.join(shard_name(idx)), options).await?;
+            opened.push(RwLock::new(db));
+        }
+        Ok(ShardedDb {
+            shards: opened,
+            hasher,
+        })
+    }
+
+    pub fn shard_count(&self) -> usize {
+        self.shards.len()
+    }
+
+    // which shard key lives in
+    pub fn shard_of(&self, key: &str) -> usize {
+        (self.hasher.hash(key.as_bytes()) % self.shards.len() as u64) as usize
+    }
+
+    fn shard(&self, key: &str) -> &RwLock<Db> {
+        &self.shards[self.shard_of(key)]
+    }
+
+    pub async fn put(&self, key: &str, value: &str) -> Result<(), DBError> {
+        self.shard(key).write().await.put(key, value).await
+    }
+
+    pub async fn delete(&self, key: &str) -> Result<(), DBError> {
+        self.shard(key).write().await.delete(key).await
+    }
+
+    pub async fn get(&self, key: &str) -> Result<Option<String>, DBError> {
+        self.shard(key).read().await.get(key).await
+    }
+
+    // live keys in [start, end) across every shard, in key order. each
+    // shard's scan starts here, so they all see their shard as it is now,
+    // though not at one moment across shards.
+    pub async fn scan(&self, start: &str, end: Option<&str>) -> Result<ShardedIter, DBError> {
+        let mut iters = Vec::with_capacity(self.shards.len());
+        let mut heads = Vec::with_capacity(self.shards.len());
+        for shard in &self.shards {
+            let mut iter = shard.read().await.scan(start, end).await?;
+            heads.push(iter.next().await?);
+            iters.push(iter);
+        }
+        Ok(ShardedIter { iters, heads })
+    }
+
+    pub async fn flush(&self) -> Result<(), DBError> {
+        for shard in &self.shards {
+            shard.write().await.flush().await?;
+        }
+        Ok(())
+    }
+
+    // the shard at idx, for whatever `ShardedDb` doesn't pass through
+    pub fn shard_at(&self, idx: usize) -> &RwLock<Db> {
+        &self.shards[idx]
+    }
+}
+
+// a scan of every shard. a key is in exactly one shard, so merging is just
+// taking the smallest of their next keys each time.
+pub struct ShardedIter {
+    iters: Vec<DbIter>,
+    // each shard's next entry, None once it's done
+    heads: Vec<Option<(String, String)>>,
+}
+
+impl ShardedIter {
+    pub async fn next(&mut self) -> Result<Option<(String, String)>, DBError> {
+        let smallest = self
+            .heads
+            .iter()
+            .enumerate()
+            .filter_map(|(idx, head)| head.as_ref().map(|(key, _)| (idx, key)))
+            .min_by(|(_, a), (_, b)| a.cmp(b))
+            .map(|(idx, _)| idx);
+        let Some(idx) = smallest else {
+            return Ok(None);
+        };
+        let next = self.iters[idx].next().await?;
+        Ok(std::mem::replace(&mut self.heads[idx], next))
+    }
+}
@@ -0,0 +1,67 @@
+use std::{
+    collections::BTreeMap,
+    fmt,
+    sync::{Arc, Mutex},
+};
+
+// how many snapshots are alive at each sequence number
+type Live = Arc<Mutex<BTreeMap<u64, usize>>>;
+
+// a point in the database's history that compaction keeps readable for as
+// long as this is alive: the versions a read at `seq` sees aren't dropped.
+// read through it by passing `seq` as `ScanOptions::snapshot`.
+pub struct Snapshot {
+    seq: u64,
+    live: Live,
+}
+
+impl Snapshot {
+    pub fn seq(&self) -> u64 {
+        self.seq
+    }
+}
+
+impl fmt::Debug for Snapshot {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Snapshot").field("seq", &self.seq).finish()
+    }
+}
+
+impl Drop for Snapshot {
+    fn drop(&mut self) {
+        let mut live = self.live.lock().expect("snapshot lock poisoned");
+        if let Some(count) = live.get_mut(&self.seq) {
+            *count -= 1;
+            if *count == 0 {
+                live.remove(&self.seq);
+            }
+        }
+    }
+}
+
+// the snapshots a db has handed out and not yet had dropped
+#[derive(Default)]
+pub(crate) struct Snapshots {
+    live: Live,
+}
+
+impl Snapshots {
+    pub(crate) fn take(&self, seq: u64) -> Snapshot {
+        *self
+            .live
+            .lock()
+            .expect("snapshot lock poisoned")
+            .entry(seq)
+            .or_default() += 1;
+        Snapshot {
+            seq,
+            live: self.live.clone(),
+        }
+    }
+
+    // the oldest snapshot still alive
+    pub(crate) fn oldest(&self) -> Option<u64> {
+        let live = self.live.lock().expect("snapshot lock poisoned");
+        live.keys().next().copied()
+    }
+}
@@ -0,0 +1,1120 @@
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc,
+    },
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use tokio::{
+    fs::{File, OpenOptions},
+    io::{
+        AsyncBufRead, AsyncBufReadExt, AsyncReadExt, AsyncSeekExt, AsyncWriteExt, BufReader,
+        BufWriter, Lines, Take,
+    },
+    sync::OnceCell,
+};
+
+use crate::{
+    bloom::{BloomFilter, KeyHasher},
+    error::DBError,
+    files::FileCache,
+    iter::{MergeIter, Source},
+    log::{Put, PutRef},
+    memtable::Entry,
+    trace::event,
+    verify::Problem,
+    Lookup, Queryable,
+};
+
+const INDEX_SPLIT: usize = 16;
+// `Sst::estimate_range_len` reads ranges starting this many blocks or fewer
+const ESTIMATE_READ_BLOCKS: usize = 2;
+// tokio's BufReader default
+pub const DEFAULT_READ_AHEAD: usize = 8 * 1024;
+pub const DEFAULT_WRITE_BUFFER: usize = 64 * 1024;
+
+#[derive(Serialize, Deserialize)]
+pub struct IndexEntry {
+    key: String,
+    position: u64,
+}
+
+// an index entry stored as how many bytes of key it shares with the entry
+// before it, and the rest of the key
+#[derive(Serialize, Deserialize)]
+struct CompressedEntry {
+    shared: usize,
+    suffix: String,
+    position: u64,
+}
+
+fn compress_index(index: &[IndexEntry]) -> Vec<CompressedEntry> {
+    let mut previous = "";
+    let mut compressed = Vec::with_capacity(index.len());
+    for entry in index {
+        let mut shared = previous
+            .bytes()
+            .zip(entry.key.bytes())
+            .take_while(|(a, b)| a == b)
+            .count();
+        while !entry.key.is_char_boundary(shared) {
+            shared -= 1;
+        }
+        compressed.push(CompressedEntry {
+            shared,
+            suffix: entry.key[shared..].to_string(),
+            position: entry.position,
+        });
+        previous = &entry.key;
+    }
+    compressed
+}
+
+fn decompress_index(compressed: Vec<CompressedEntry>) -> Result<Vec<IndexEntry>, DBError> {
+    let mut index: Vec<IndexEntry> = Vec::with_capacity(compressed.len());
+    for entry in compressed {
+        let previous = index.last().map_or("", |last| last.key.as_str());
+        let Some(prefix) = previous.get(..entry.shared) else {
+            return Err(DBError::InvalidInput(format!(
+                "compressed index entry shares {} bytes of {:?}",
+                entry.shared, previous
+            )));
+        };
+        index.push(IndexEntry {
+            key: format!("{}{}", prefix, entry.suffix),
+            position: entry.position,
+        });
+    }
+    Ok(index)
+}
+
+// everything about an sst that lives in its .index file
+#[derive(Serialize, Deserialize)]
+pub struct SstMeta {
+    // empty on disk when the index is stored compressed, readers that don't
+    // know about compression then scan from the start of the data file
+    #[serde(default)]
+    index: Vec<IndexEntry>,
+    // only on disk, moved into `index` on load
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    compressed_index: Option<Vec<CompressedEntry>>,
+    bloom: BloomFilter,
+    min_seq: u64,
+    max_seq: u64,
+    #[serde(default)]
+    level: usize,
+    // crc32 of the whole data file
+    #[serde(default)]
+    checksum: Option<u32>,
+    #[serde(default)]
+    prefix_bloom: Option<PrefixBloom>,
+    // not recorded by older ssts
+    #[serde(default)]
+    max_key: Option<String>,
+    // ms since the unix epoch, see `SstMeta::created`
+    #[serde(default)]
+    created_ms: Option<u64>,
+    // not recorded by older ssts
+    #[serde(default)]
+    stats: Option<SstStats>,
+}
+
+// counts over an sst's records, taken as it's written
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct SstStats {
+    pub entries: u64,
+    pub key_bytes: u64,
+    // separated values count as empty, only their pointer is here
+    pub value_bytes: u64,
+    pub tombstones: u64,
+    // records that are an older version of the key before them, a hint of
+    // how much a compaction dropping old versions would save
+    pub overwrites: u64,
+}
+
+impl SstStats {
+    pub fn avg_key_size(&self) -> f64 {
+        ratio(self.key_bytes, self.entries)
+    }
+
+    pub fn avg_value_size(&self) -> f64 {
+        ratio(self.value_bytes, self.entries - self.tombstones)
+    }
+
+    pub fn tombstone_ratio(&self) -> f64 {
+        ratio(self.tombstones, self.entries)
+    }
+
+    pub fn overwrite_ratio(&self) -> f64 {
+        ratio(self.overwrites, self.entries)
+    }
+}
+
+// 0 when there's nothing to divide by
+fn ratio(part: u64, whole: u64) -> f64 {
+    match whole {
+        0 => 0.0,
+        whole => part as f64 / whole as f64,
+    }
+}
+
+// a bloom filter over the first `len` bytes of every key, keys shorter than
+// that go in whole
+#[derive(Serialize, Deserialize)]
+struct PrefixBloom {
+    len: usize,
+    filter: BloomFilter,
+}
+
+fn key_prefix(key: &str, len: usize) -> &[u8] {
+    &key.as_bytes()[..len.min(key.len())]
+}
+
+pub struct Sst {
+    data_path: PathBuf,
+    index_path: PathBuf,
+    // loaded on open, or on first use for lazily opened ssts
+    meta: OnceCell<SstMeta>,
+    size: u64,
+    // set once a compaction has replaced this sst, the files go away when
+    // the last reference is dropped
+    obsolete: AtomicBool,
+}
+
+// whether put can come right after the record for (key, seq) in an sst
+fn follows(key: &str, seq: u64, next_key: &str, next_seq: u64) -> bool {
+    next_key > key || (next_key == key && next_seq < seq)
+}
+
+// streams sorted records into a new sst
+pub struct SstWriter {
+    data_path: PathBuf,
+    index_path: PathBuf,
+    data_file: BufWriter<File>,
+    index: Vec<IndexEntry>,
+    hashes: Vec<u64>,
+    min_seq: u64,
+    max_seq: u64,
+    last_key: String,
+    last_seq: u64,
+    level: usize,
+    prefix_len: Option<usize>,
+    prefix_hashes: Vec<u64>,
+    key_hasher: KeyHasher,
+    created: SystemTime,
+    compress_index: bool,
+    hasher: crc32fast::Hasher,
+    position: u64,
+    count: usize,
+    stats: SstStats,
+}
+
+// which of the two ssts `Sst::merge_into` is handed holds the newer data,
+// deciding between records of a key that carry the same sequence number
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Newest {
+    A,
+    B,
+}
+
+// reads an sst front to back starting at a given key, and up to an end key
+// when it has one, holding a reference to the sst so it can't be deleted
+// underneath us
+pub struct SstIter {
+    _sst: Arc<Sst>,
+    lines: Lines<BufReader<Take<File>>>,
+    start: String,
+    end: Option<String>,
+    // bytes of the data file read so far are added here, see `counting`
+    read: Option<Arc<AtomicU64>>,
+}
+
+fn paths(path: &Path) -> (PathBuf, PathBuf) {
+    (path.with_extension("data"), path.with_extension("index"))
+}
+
+// where a file is written before it's renamed into place, so a file under
+// its final name is always complete
+fn temp_path(path: &Path) -> PathBuf {
+    let mut temp = path.as_os_str().to_owned();
+    temp.push(".tmp");
+    PathBuf::from(temp)
+}
+
+pub(crate) fn is_temp(path: &Path) -> bool {
+    path.extension().is_some_and(|ext| ext == "tmp")
+}
+
+// cleans up after an sst that was never finished
+pub(crate) async fn remove_files(path: &Path) {
+    let (data_path, index_path) = paths(path);
+    for path in [data_path, index_path] {
+        let _ = tokio::fs::remove_file(temp_path(&path)).await;
+        let _ = tokio::fs::remove_file(path).await;
+    }
+}
+
+impl SstWriter {
+    pub async fn create(path: impl AsRef<Path>) -> Result<SstWriter, DBError> {
+        SstWriter::create_with_buffer(path, DEFAULT_WRITE_BUFFER).await
+    }
+
+    // records are written out `capacity` bytes at a time rather than one
+    // write per record
+    pub async fn create_with_buffer(
+        path: impl AsRef<Path>,
+        capacity: usize,
+    ) -> Result<SstWriter, DBError> {
+        let (data_path, index_path) = paths(path.as_ref());
+        let data_file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(temp_path(&data_path))
+            .await?;
+        Ok(SstWriter {
+            data_path,
+            index_path,
+            data_file: BufWriter::with_capacity(capacity, data_file),
+            index: Vec::new(),
+            hashes: Vec::new(),
+            min_seq: u64::MAX,
+            max_seq: 0,
+            last_key: String::new(),
+            last_seq: 0,
+            level: 0,
+            prefix_len: None,
+            prefix_hashes: Vec::new(),
+            key_hasher: KeyHasher::default(),
+            created: SystemTime::now(),
+            compress_index: false,
+            hasher: crc32fast::Hasher::new(),
+            position: 0,
+            count: 0,
+            stats: SstStats::default(),
+        })
+    }
+
+    // the level this sst is written for, 0 unless set
+    pub fn with_level(mut self, level: usize) -> SstWriter {
+        self.level = level;
+        self
+    }
+
+    // the hash the bloom filters are built with, xxh64 unless set
+    pub fn with_hasher(mut self, hasher: KeyHasher) -> SstWriter {
+        self.key_hasher = hasher;
+        self
+    }
+
+    // recorded as the sst's creation time, now unless set
+    pub fn with_created(mut self, created: SystemTime) -> SstWriter {
+        self.created = created;
+        self
+    }
+
+    // store the index prefix compressed, which is smaller when neighbouring
+    // index keys start the same way
+    pub fn with_compressed_index(mut self) -> SstWriter {
+        self.compress_index = true;
+        self
+    }
+
+    // also build a prefix bloom filter over the first len bytes of each key
+    pub fn with_prefix_bloom(mut self, len: usize) -> SstWriter {
+        self.prefix_len = Some(len);
+        self
+    }
+
+    // records must arrive in increasing key order, anything else would leave
+    // the sparse index pointing at the wrong blocks. several versions of a key
+    // go newest first.
+    pub async fn add(&mut self, put: &Put) -> Result<(), DBError> {
+        self.add_ref(PutRef::from(put)).await
+    }
+
+    // `add` straight from a memtable entry, without copying it into a `Put`
+    pub async fn add_entry(&mut self, key: &str, entry: &Entry) -> Result<(), DBError> {
+        self.add_ref(PutRef {
+            key,
+            value: entry.value.as_deref(),
+            seq: entry.seq,
+            flags: entry.flags,
+            value_ref: entry.value_ref,
+            merge: entry.merge,
+        })
+        .await
+    }
+
+    async fn add_ref(&mut self, put: PutRef<'_>) -> Result<(), DBError> {
+        if self.count > 0 && !follows(&self.last_key, self.last_seq, put.key, put.seq) {
+            return Err(DBError::InvalidInput(format!(
+                "sst keys out of order: {:?} (seq {}) after {:?} (seq {})",
+                put.key, put.seq, self.last_key, self.last_seq
+            )));
+        }
+        self.stats.entries += 1;
+        self.stats.key_bytes += put.key.len() as u64;
+        match put.value {
+            Some(value) => self.stats.value_bytes += value.len() as u64,
+            None => self.stats.tombstones += 1,
+        }
+        if self.count > 0 && self.last_key == put.key {
+            self.stats.overwrites += 1;
+        }
+        self.last_key.clear();
+        self.last_key.push_str(put.key);
+        self.last_seq = put.seq;
+        if self.count.is_multiple_of(INDEX_SPLIT) {
+            self.index.push(IndexEntry {
+                key: put.key.to_string(),
+                position: self.position,
+            });
+        }
+        self.hashes.push(self.key_hasher.hash(put.key.as_bytes()));
+        if let Some(len) = self.prefix_len {
+            // sorted keys put equal prefixes next to each other
+            let hash = self.key_hasher.hash(key_prefix(put.key, len));
+            if self.prefix_hashes.last() != Some(&hash) {
+                self.prefix_hashes.push(hash);
+            }
+        }
+        self.min_seq = self.min_seq.min(put.seq);
+        self.max_seq = self.max_seq.max(put.seq);
+        let mut serialized = serde_json::to_string(&put)?;
+        serialized.push('\n');
+        self.data_file.write_all(serialized.as_bytes()).await?;
+        self.hasher.update(serialized.as_bytes());
+        self.position += serialized.len() as u64;
+        self.count += 1;
+        Ok(())
+    }
+
+    // bytes of data written so far
+    pub fn size(&self) -> u64 {
+        self.position
+    }
+
+    // records added so far
+    pub fn len(&self) -> usize {
+        self.count
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    // removes whatever was written so far
+    pub async fn abandon(self) {
+        drop(self.data_file);
+        let _ = tokio::fs::remove_file(temp_path(&self.data_path)).await;
+    }
+
+    // both files are written and synced under temporary names first. the
+    // data file is renamed into place last, and it's what marks the sst as
+    // there.
+    pub async fn finish(mut self) -> Result<Sst, DBError> {
+        self.data_file.flush().await?;
+        self.data_file.get_ref().sync_all().await?;
+        let mut index_file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(temp_path(&self.index_path))
+            .await?;
+        let mut meta = SstMeta {
+            index: self.index,
+            compressed_index: None,
+            bloom: BloomFilter::from_hashes(&self.hashes, self.key_hasher),
+            min_seq: self.min_seq.min(self.max_seq),
+            max_seq: self.max_seq,
+            level: self.level,
+            checksum: Some(self.hasher.finalize()),
+            prefix_bloom: self.prefix_len.map(|len| PrefixBloom {
+                len,
+                filter: BloomFilter::from_hashes(&self.prefix_hashes, self.key_hasher),
+            }),
+            max_key: (self.count > 0).then(|| self.last_key.clone()),
+            created_ms: Some(
+                self.created
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap_or_default()
+                    .as_millis() as u64,
+            ),
+            stats: Some(self.stats),
+        };
+        let index_data = if self.compress_index {
+            meta.compressed_index = Some(compress_index(&meta.index));
+            let index = std::mem::take(&mut meta.index);
+            let serialized = serde_json::to_string(&meta);
+            meta.index = index;
+            meta.compressed_index = None;
+            serialized?
+        } else {
+            serde_json::to_string(&meta)?
+        };
+        index_file.write_all(index_data.as_bytes()).await?;
+        index_file.write_all(b"\n").await?;
+        index_file.sync_all().await?;
+        tokio::fs::rename(temp_path(&self.index_path), &self.index_path).await?;
+        tokio::fs::rename(temp_path(&self.data_path), &self.data_path).await?;
+        event!(
+            debug,
+            path = %self.data_path.display(),
+            keys = self.count,
+            bytes = self.position,
+            "sst written"
+        );
+        Ok(Sst {
+            data_path: self.data_path,
+            index_path: self.index_path,
+            meta: OnceCell::new_with(Some(meta)),
+            size: self.position,
+            obsolete: AtomicBool::new(false),
+        })
+    }
+}
+
+impl Sst {
+    pub async fn construct(
+        path: impl AsRef<Path>,
+        data: impl Iterator<Item = Put>,
+    ) -> Result<Sst, DBError> {
+        Sst::construct_with(SstWriter::create(path).await?, data).await
+    }
+
+    // `construct` through a writer that's already been configured
+    pub async fn construct_with(
+        mut writer: SstWriter,
+        data: impl Iterator<Item = Put>,
+    ) -> Result<Sst, DBError> {
+        for put in data {
+            if let Err(err) = writer.add(&put).await {
+                writer.abandon().await;
+                return Err(err);
+            }
+        }
+        writer.finish().await
+    }
+
+    // like `construct`, but accepts unsorted input and keeps the last record
+    // seen for a duplicated key
+    pub async fn construct_lenient(
+        path: impl AsRef<Path>,
+        data: impl Iterator<Item = Put>,
+    ) -> Result<Sst, DBError> {
+        let mut sorted = BTreeMap::new();
+        for put in data {
+            sorted.insert(put.key.clone(), put);
+        }
+        Sst::construct(path, sorted.into_values()).await
+    }
+
+    // streams a and b into one sst at path, holding the newest record of
+    // every key in either. that's the one with the higher sequence number,
+    // or the one in the newest sst when they're equal. tombstones are kept,
+    // since older ssts may still hold what they delete. the output takes the
+    // higher level of the two and the newest sst's bloom hasher.
+    pub async fn merge_into(
+        a: &Arc<Sst>,
+        b: &Arc<Sst>,
+        path: impl AsRef<Path>,
+        newest: Newest,
+    ) -> Result<Sst, DBError> {
+        let (newer, older) = match newest {
+            Newest::A => (a, b),
+            Newest::B => (b, a),
+        };
+        let (newer_meta, older_meta) = (newer.meta().await?, older.meta().await?);
+        let mut writer = SstWriter::create(path)
+            .await?
+            .with_level(newer_meta.level().max(older_meta.level()))
+            .with_hasher(newer_meta.bloom_hasher());
+        if let Some(created) = newer_meta.created().max(older_meta.created()) {
+            writer = writer.with_created(created);
+        }
+        // the first source wins a tie
+        let mut merge = MergeIter::new(vec![
+            Source::Sst(Box::new(newer.iter().await?)),
+            Source::Sst(Box::new(older.iter().await?)),
+        ]);
+        loop {
+            let added = match merge.next().await {
+                Ok(Some(put)) => writer.add(&put).await,
+                Ok(None) => break,
+                Err(err) => Err(err),
+            };
+            if let Err(err) = added {
+                writer.abandon().await;
+                return Err(err);
+            }
+        }
+        writer.finish().await
+    }
+
+    pub async fn open(path: impl AsRef<Path>) -> Result<Sst, DBError> {
+        let sst = Sst::open_lazy(path).await?;
+        sst.meta().await?;
+        Ok(sst)
+    }
+
+    // only looks at the data file; the index and bloom filter are read the
+    // first time the sst is queried
+    pub async fn open_lazy(path: impl AsRef<Path>) -> Result<Sst, DBError> {
+        let (data_path, index_path) = paths(path.as_ref());
+        let size = tokio::fs::metadata(&data_path).await?.len();
+        Ok(Sst {
+            data_path,
+            index_path,
+            meta: OnceCell::new(),
+            size,
+            obsolete: AtomicBool::new(false),
+        })
+    }
+
+    pub async fn meta(&self) -> Result<&SstMeta, DBError> {
+        self.meta
+            .get_or_try_init(|| async {
+                let index_data = tokio::fs::read_to_string(&self.index_path)
+                    .await
+                    .map_err(|err| self.missing(err))?;
+                let mut meta: SstMeta = serde_json::from_str(&index_data)?;
+                if let Some(compressed) = meta.compressed_index.take() {
+                    meta.index = decompress_index(compressed)?;
+                }
+                event!(
+                    debug,
+                    path = %self.data_path.display(),
+                    index_entries = meta.index.len(),
+                    bytes = self.size,
+                    "sst opened"
+                );
+                Ok(meta)
+            })
+            .await
+    }
+
+    // whether the index and bloom filter have been read yet
+    pub fn is_loaded(&self) -> bool {
+        self.meta.initialized()
+    }
+
+    // the bloom filter's answer for key: false means it's definitely not
+    // here, true that it may be. always true for a lazily opened sst whose
+    // filter hasn't been read yet.
+    pub fn bloom_may_contain(&self, key: &str) -> bool {
+        self.meta.get().is_none_or(|meta| meta.may_contain(key))
+    }
+
+    // see `SstStats`, loading the index first for a lazily opened sst
+    pub async fn stats(&self) -> Result<Option<&SstStats>, DBError> {
+        Ok(self.meta().await?.stats())
+    }
+
+    pub fn data_path(&self) -> &Path {
+        &self.data_path
+    }
+
+    pub fn index_path(&self) -> &Path {
+        &self.index_path
+    }
+
+    // size of the data file in bytes
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    pub fn mark_obsolete(&self) {
+        self.obsolete.store(true, Ordering::SeqCst);
+    }
+
+    async fn lines_from(
+        &self,
+        position: u64,
+        read_ahead: usize,
+    ) -> Result<Lines<BufReader<File>>, DBError> {
+        let file = self.open_at(position).await?;
+        Ok(BufReader::with_capacity(read_ahead, file).lines())
+    }
+
+    async fn open_at(&self, position: u64) -> Result<File, DBError> {
+        let mut file = File::open(&self.data_path)
+            .await
+            .map_err(|err| self.missing(err))?;
+        file.seek(std::io::SeekFrom::Start(position)).await?;
+        Ok(file)
+    }
+
+    // files vanishing from under an open sst get their own error
+    fn missing(&self, err: std::io::Error) -> DBError {
+        match err.kind() {
+            std::io::ErrorKind::NotFound => DBError::MissingSst(self.data_path.clone()),
+            _ => DBError::Io(err),
+        }
+    }
+
+    pub async fn iter(self: &Arc<Self>) -> Result<SstIter, DBError> {
+        self.iter_from("").await
+    }
+
+    pub async fn iter_from(self: &Arc<Self>, start: &str) -> Result<SstIter, DBError> {
+        self.iter_with_read_ahead(start, DEFAULT_READ_AHEAD).await
+    }
+
+    // reads the data file in chunks of read_ahead bytes, bigger chunks mean
+    // fewer reads for long sequential passes like compaction
+    pub async fn iter_with_read_ahead(
+        self: &Arc<Self>,
+        start: &str,
+        read_ahead: usize,
+    ) -> Result<SstIter, DBError> {
+        self.range_with_read_ahead(start, None, read_ahead).await
+    }
+
+    // the records in [start, end), in order. only the blocks that can hold
+    // them are read, from the one the index finds start in up to the first
+    // that starts at end or later.
+    pub async fn range_iter(self: &Arc<Self>, start: &str, end: &str) -> Result<SstIter, DBError> {
+        self.range_with_read_ahead(start, Some(end), DEFAULT_READ_AHEAD)
+            .await
+    }
+
+    async fn range_with_read_ahead(
+        self: &Arc<Self>,
+        start: &str,
+        end: Option<&str>,
+        read_ahead: usize,
+    ) -> Result<SstIter, DBError> {
+        let meta = self.meta().await?;
+        let position = meta.seek_position(start);
+        let stop = end.and_then(|end| meta.block_at_or_after(end));
+        let len = stop.map_or(u64::MAX, |stop| stop.saturating_sub(position));
+        let file = self.open_at(position).await?.take(len);
+        Ok(SstIter {
+            _sst: self.clone(),
+            lines: BufReader::with_capacity(read_ahead.max(1), file).lines(),
+            start: start.to_string(),
+            end: end.map(String::from),
+            read: None,
+        })
+    }
+
+    // roughly how many records lie in [start, end), tombstones and older
+    // versions included. ranges starting no more than a couple of indexed
+    // blocks are counted exactly by reading them, longer ones are worked
+    // out from the index alone, a block's worth of records per block.
+    pub async fn estimate_range_len(
+        self: &Arc<Self>,
+        start: &str,
+        end: Option<&str>,
+    ) -> Result<u64, DBError> {
+        let meta = self.meta().await?;
+        let before_end = |key: &str| end.is_none_or(|end| key < end);
+        let disjoint = meta.min_key().is_none_or(|min| !before_end(min))
+            || meta.max_key().is_some_and(|max| max < start);
+        if disjoint {
+            return Ok(0);
+        }
+        let first = meta
+            .index
+            .partition_point(|entry| entry.key.as_str() < start);
+        let last = meta
+            .index
+            .partition_point(|entry| before_end(entry.key.as_str()));
+        let blocks = last.saturating_sub(first);
+        if blocks > ESTIMATE_READ_BLOCKS {
+            return Ok((blocks * INDEX_SPLIT) as u64);
+        }
+        let mut iter = self
+            .range_with_read_ahead(start, end, DEFAULT_READ_AHEAD)
+            .await?;
+        let mut count = 0;
+        while iter.next().await?.is_some() {
+            count += 1;
+        }
+        Ok(count)
+    }
+
+    // the stored record for key, tombstones included, so callers comparing
+    // several ssts can order them by sequence number
+    pub async fn get_record(&self, key: &str) -> Result<Option<Put>, DBError> {
+        self.get_record_in(key, None).await
+    }
+
+    // every version of key held here, newest first. there's more than one
+    // only in ssts compacted with `DbOptions::versions_to_keep`.
+    pub async fn get_versions(&self, key: &str) -> Result<Vec<Put>, DBError> {
+        self.get_versions_in(key, None).await
+    }
+
+    // the same lookups, reading the data file through files when given
+    // rather than opening it afresh
+    pub(crate) async fn get_record_in(
+        &self,
+        key: &str,
+        files: Option<&FileCache>,
+    ) -> Result<Option<Put>, DBError> {
+        Ok(self.versions(key, 1, files).await?.pop())
+    }
+
+    pub(crate) async fn get_versions_in(
+        &self,
+        key: &str,
+        files: Option<&FileCache>,
+    ) -> Result<Vec<Put>, DBError> {
+        self.versions(key, usize::MAX, files).await
+    }
+
+    async fn versions(
+        &self,
+        key: &str,
+        limit: usize,
+        files: Option<&FileCache>,
+    ) -> Result<Vec<Put>, DBError> {
+        let meta = self.meta().await?;
+        if !meta.in_range(key) || !meta.bloom.may_contain(key) {
+            return Ok(Vec::new());
+        }
+        let position = meta.seek_position(key);
+        let Some(files) = files else {
+            let lines = self.lines_from(position, DEFAULT_READ_AHEAD).await?;
+            return read_versions(lines, key, limit).await;
+        };
+        let file = files
+            .get(&self.data_path)
+            .await
+            .map_err(|err| self.missing(err))?;
+        let mut file = file.lock().await;
+        file.seek(std::io::SeekFrom::Start(position)).await?;
+        let lines = BufReader::with_capacity(DEFAULT_READ_AHEAD, &mut *file).lines();
+        read_versions(lines, key, limit).await
+    }
+}
+
+// up to limit records for key from lines positioned at or before it
+async fn read_versions<R: AsyncBufRead + Unpin>(
+    mut lines: Lines<R>,
+    key: &str,
+    limit: usize,
+) -> Result<Vec<Put>, DBError> {
+    let mut versions = Vec::new();
+    while let Some(line) = lines.next_line().await? {
+        let put: Put = serde_json::from_str(&line)?;
+        if put.key.as_str() > key {
+            break;
+        }
+        if put.key == key {
+            versions.push(put);
+            if versions.len() == limit {
+                break;
+            }
+        }
+    }
+    Ok(versions)
+}
+
+impl Sst {
+    // recomputes the data file checksum in one streaming pass and compares it
+    // with the one recorded when the sst was written. ssts written before
+    // checksums were recorded have nothing to compare against and pass.
+    pub async fn verify_file(&self) -> Result<bool, DBError> {
+        let Some(expected) = self.meta().await?.checksum else {
+            return Ok(true);
+        };
+        let mut file = File::open(&self.data_path).await?;
+        let mut hasher = crc32fast::Hasher::new();
+        let mut buf = vec![0; 64 * 1024];
+        loop {
+            let read = file.read(&mut buf).await?;
+            if read == 0 {
+                break;
+            }
+            hasher.update(&buf[..read]);
+        }
+        Ok(hasher.finalize() == expected)
+    }
+
+    // walks the whole sst checking that every record parses, keys are
+    // strictly increasing, index entries point at the records they name and
+    // the bloom filter and sequence bounds cover every record. returns the
+    // number of records read and anything that looked wrong.
+    pub async fn verify(&self) -> (usize, Vec<Problem>) {
+        let mut problems = Vec::new();
+        let meta = match self.meta().await {
+            Ok(meta) => meta,
+            Err(err) => {
+                problems.push(Problem::new(
+                    &self.index_path,
+                    format!("unreadable index: {}", err),
+                ));
+                return (0, problems);
+            }
+        };
+        for pair in meta.index.windows(2) {
+            if pair[0].key > pair[1].key || pair[0].position >= pair[1].position {
+                problems.push(Problem::new(
+                    &self.index_path,
+                    format!("index not sorted at {:?}", pair[1].key),
+                ));
+            }
+        }
+
+        match self.verify_file().await {
+            Ok(true) => {}
+            Ok(false) => problems.push(Problem::new(&self.data_path, "checksum mismatch")),
+            Err(err) => problems.push(Problem::new(
+                &self.data_path,
+                format!("checksum unreadable: {}", err),
+            )),
+        }
+
+        let file = match File::open(&self.data_path).await {
+            Ok(file) => file,
+            Err(err) => {
+                problems.push(Problem::new(
+                    &self.data_path,
+                    format!("unreadable data: {}", err),
+                ));
+                return (0, problems);
+            }
+        };
+        let mut reader = BufReader::new(file);
+        let mut line = String::new();
+        let mut position = 0u64;
+        let mut records = 0;
+        let mut last: Option<(String, u64)> = None;
+        let mut index = meta.index.iter().peekable();
+        loop {
+            line.clear();
+            let read = match reader.read_line(&mut line).await {
+                Ok(read) => read,
+                Err(err) => {
+                    problems.push(Problem::new(
+                        &self.data_path,
+                        format!("read failed at {}: {}", position, err),
+                    ));
+                    break;
+                }
+            };
+            if read == 0 {
+                break;
+            }
+            let put: Put = match serde_json::from_str(line.trim_end()) {
+                Ok(put) => put,
+                Err(err) => {
+                    problems.push(Problem::new(
+                        &self.data_path,
+                        format!("bad record at {}: {}", position, err),
+                    ));
+                    position += read as u64;
+                    continue;
+                }
+            };
+            records += 1;
+
+            while let Some(entry) = index.next_if(|entry| entry.position < position) {
+                problems.push(Problem::new(
+                    &self.index_path,
+                    format!(
+                        "index entry {:?} points inside a record at {}",
+                        entry.key, entry.position
+                    ),
+                ));
+            }
+            if let Some(entry) = index.next_if(|entry| entry.position == position) {
+                if entry.key != put.key {
+                    problems.push(Problem::new(
+                        &self.index_path,
+                        format!("index entry {:?} points at record {:?}", entry.key, put.key),
+                    ));
+                }
+            }
+            if last
+                .as_ref()
+                .is_some_and(|(key, seq)| !follows(key, *seq, &put.key, put.seq))
+            {
+                problems.push(Problem::new(
+                    &self.data_path,
+                    format!("key {:?} out of order", put.key),
+                ));
+            }
+            if !meta.bloom.may_contain(&put.key) {
+                problems.push(Problem::new(
+                    &self.index_path,
+                    format!("bloom filter misses {:?}", put.key),
+                ));
+            }
+            if put.seq < meta.min_seq || put.seq > meta.max_seq {
+                problems.push(Problem::new(
+                    &self.data_path,
+                    format!(
+                        "seq {} of {:?} outside {}..={}",
+                        put.seq, put.key, meta.min_seq, meta.max_seq
+                    ),
+                ));
+            }
+            position += read as u64;
+            last = Some((put.key, put.seq));
+        }
+        for entry in index {
+            problems.push(Problem::new(
+                &self.index_path,
+                format!(
+                    "index entry {:?} points past the end at {}",
+                    entry.key, entry.position
+                ),
+            ));
+        }
+        (records, problems)
+    }
+}
+
+impl SstMeta {
+    pub fn min_seq(&self) -> u64 {
+        self.min_seq
+    }
+
+    pub fn max_seq(&self) -> u64 {
+        self.max_seq
+    }
+
+    pub fn level(&self) -> usize {
+        self.level
+    }
+
+    pub fn checksum(&self) -> Option<u32> {
+        self.checksum
+    }
+
+    // when the newest data in the sst was written out, so nothing in it is
+    // any younger. None for ssts from before this was recorded.
+    pub fn created(&self) -> Option<SystemTime> {
+        self.created_ms
+            .map(|ms| UNIX_EPOCH + std::time::Duration::from_millis(ms))
+    }
+
+    pub fn bloom_hasher(&self) -> KeyHasher {
+        self.bloom.hasher()
+    }
+
+    // None for ssts from before these were recorded
+    pub fn stats(&self) -> Option<&SstStats> {
+        self.stats.as_ref()
+    }
+
+    // false when the bloom filter rules key out
+    pub fn may_contain(&self, key: &str) -> bool {
+        self.bloom.may_contain(key)
+    }
+
+    // false when no key here can start with prefix. prefixes shorter than
+    // the filtered length can't be checked and always pass.
+    pub fn may_contain_prefix(&self, prefix: &str) -> bool {
+        match &self.prefix_bloom {
+            Some(bloom) if prefix.len() >= bloom.len => bloom
+                .filter
+                .may_contain_bytes(key_prefix(prefix, bloom.len)),
+            _ => true,
+        }
+    }
+
+    // None for an empty sst
+    pub fn min_key(&self) -> Option<&str> {
+        self.index.first().map(|entry| entry.key.as_str())
+    }
+
+    // None for an empty sst, or one written before the last key was kept
+    pub fn max_key(&self) -> Option<&str> {
+        self.max_key.as_deref()
+    }
+
+    // whether the key ranges intersect. without a recorded last key an sst is
+    // taken to reach to the end of the key space.
+    pub fn overlaps(&self, other: &SstMeta) -> bool {
+        let (Some(min), Some(other_min)) = (self.min_key(), other.min_key()) else {
+            return false;
+        };
+        self.max_key().is_none_or(|max| max >= other_min)
+            && other.max_key().is_none_or(|max| max >= min)
+    }
+
+    // the first key of every indexed block, in order
+    pub fn block_keys(&self) -> impl Iterator<Item = &str> {
+        self.index.iter().map(|entry| entry.key.as_str())
+    }
+
+    // whether key falls between the first and last keys, the only place
+    // a lookup can find it
+    fn in_range(&self, key: &str) -> bool {
+        self.min_key().is_some_and(|min| min <= key) && self.max_key().is_none_or(|max| key <= max)
+    }
+
+    // the last block starting before key, which is where the newest record
+    // for key would have to start. a block starting at key itself may hold an
+    // older version, with the newest at the end of the block before. a key
+    // before the first block starts at the top, one past the last indexed key
+    // in the last block, which runs to the end of the file.
+    fn seek_position(&self, key: &str) -> u64 {
+        match self.index.partition_point(|entry| entry.key.as_str() < key) {
+            0 => 0,
+            idx => self.index[idx - 1].position,
+        }
+    }
+
+    // where the first block starting at key or later begins, None when
+    // there's no such block and the records before key run to the end
+    fn block_at_or_after(&self, key: &str) -> Option<u64> {
+        let idx = self.index.partition_point(|entry| entry.key.as_str() < key);
+        self.index.get(idx).map(|entry| entry.position)
+    }
+}
+
+impl Drop for Sst {
+    fn drop(&mut self) {
+        if self.obsolete.load(Ordering::SeqCst) {
+            let _ = std::fs::remove_file(&self.data_path);
+            let _ = std::fs::remove_file(&self.index_path);
+        }
+    }
+}
+
+impl SstIter {
+    // adds the bytes of the data file read from here on to read, shared
+    // with whoever is watching how far along it is
+    pub fn counting(mut self, read: Arc<AtomicU64>) -> SstIter {
+        self.read = Some(read);
+        self
+    }
+
+    pub async fn next(&mut self) -> Result<Option<Put>, DBError> {
+        while let Some(line) = self.lines.next_line().await? {
+            if let Some(read) = &self.read {
+                // and the newline
+                read.fetch_add(line.len() as u64 + 1, Ordering::Relaxed);
+            }
+            let put: Put = serde_json::from_str(&line)?;
+            if self.end.as_ref().is_some_and(|end| &put.key >= end) {
+                return Ok(None);
+            }
+            if put.key.as_str() >= self.start.as_str() {
+                return Ok(Some(put));
+            }
+        }
+        Ok(None)
+    }
+}
+
+#[async_trait]
+impl Queryable for Sst {
+    async fn get(&self, key: &str) -> Result<Lookup, DBError> {
+        Ok(match self.get_record(key).await? {
+            Some(put) => put.value.into(),
+            None => Lookup::Absent,
+        })
+    }
+}
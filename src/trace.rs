@@ -0,0 +1,20 @@
+// thin wrappers over `tracing` so call sites compile away entirely when the
+// `tracing` feature is off
+
+macro_rules! event {
+    ($level:ident, $($arg:tt)+) => {
+        #[cfg(feature = "tracing")]
+        tracing::$level!($($arg)+);
+    };
+}
+
+// starts a timer that only exists when tracing is enabled
+macro_rules! timer {
+    ($name:ident) => {
+        #[cfg(feature = "tracing")]
+        let $name = std::time::Instant::now();
+    };
+}
+
+pub(crate) use event;
+pub(crate) use timer;
@@ -0,0 +1,36 @@
+use std::{fmt, path::PathBuf};
+
+// one thing `Db::verify` found wrong, and the file it was found in
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Problem {
+    pub path: PathBuf,
+    pub description: String,
+}
+
+impl Problem {
+    pub fn new(path: impl Into<PathBuf>, description: impl Into<String>) -> Problem {
+        Problem {
+            path: path.into(),
+            description: description.into(),
+        }
+    }
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.path.display(), self.description)
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct VerifyReport {
+    pub ssts_checked: usize,
+    pub records_checked: usize,
+    pub problems: Vec<Problem>,
+}
+
+impl VerifyReport {
+    pub fn is_clean(&self) -> bool {
+        self.problems.is_empty()
+    }
+}
@@ -0,0 +1,95 @@
+use std::{
+    io::SeekFrom,
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+use tokio::{
+    fs::{File, OpenOptions},
+    io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt},
+};
+
+use crate::{error::DBError, log::Put};
+
+// where a separated value sits in the value log
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ValueRef {
+    pub offset: u64,
+    pub len: u64,
+}
+
+// large values, appended at flush time so ssts only carry a `ValueRef` to
+// them, and the bytes `Db::put_raw` writes. nothing is ever removed, space held by overwritten or deleted values
+// isn't reclaimed.
+pub struct ValueLog {
+    path: PathBuf,
+    // opened on the first append, a db that never separates a value doesn't
+    // get a value log file
+    file: Option<File>,
+    len: u64,
+}
+
+impl ValueLog {
+    pub fn new(path: impl Into<PathBuf>) -> ValueLog {
+        ValueLog {
+            path: path.into(),
+            file: None,
+            len: 0,
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub async fn append(&mut self, value: &[u8]) -> Result<ValueRef, DBError> {
+        if self.file.is_none() {
+            let file = OpenOptions::new()
+                .append(true)
+                .create(true)
+                .open(&self.path)
+                .await?;
+            self.len = file.metadata().await?.len();
+            self.file = Some(file);
+        }
+        let file = self.file.as_mut().expect("opened above");
+        file.write_all(value).await?;
+        let value_ref = ValueRef {
+            offset: self.len,
+            len: value.len() as u64,
+        };
+        self.len += value_ref.len;
+        Ok(value_ref)
+    }
+
+    // values appended since the last sync aren't durable yet
+    pub async fn sync(&mut self) -> Result<(), DBError> {
+        if let Some(file) = &mut self.file {
+            file.sync_all().await?;
+        }
+        Ok(())
+    }
+}
+
+pub async fn read_value(path: &Path, value_ref: ValueRef) -> Result<String, DBError> {
+    String::from_utf8(read_raw(path, value_ref).await?)
+        .map_err(|_| DBError::InvalidInput(format!("value at {} is not utf-8", value_ref.offset)))
+}
+
+// the bytes as they sit in the value log, unchecked
+pub async fn read_raw(path: &Path, value_ref: ValueRef) -> Result<Vec<u8>, DBError> {
+    let mut file = File::open(path).await?;
+    file.seek(SeekFrom::Start(value_ref.offset)).await?;
+    let mut value = vec![0; value_ref.len as usize];
+    file.read_exact(&mut value).await?;
+    Ok(value)
+}
+
+// the live value of a record, read back from the value log if it was moved
+// out there
+pub async fn resolve_value(vlog: &Path, put: Put) -> Result<Option<String>, DBError> {
+    match (put.value, put.value_ref) {
+        (Some(_), Some(value_ref)) => Ok(Some(read_value(vlog, value_ref).await?)),
+        (value, _) => Ok(value),
+    }
+}
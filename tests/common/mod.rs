@@ -0,0 +1,8 @@
+use std::path::PathBuf;
+
+// a fresh directory under the system temp dir for one test
+pub fn tmp(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("lsm-test-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    dir
+}
@@ -0,0 +1,933 @@
+mod common;
+
+use common::tmp;
+use lsm_based_db_in_rust::*;
+
+#[tokio::test]
+async fn scan_survives_compaction() {
+    let dir = tmp("scan_survives_compaction");
+    let mut db = Db::new(&dir).await.unwrap();
+    for i in 0..500 {
+        db.put(&format!("k{:04}", i), &format!("v{}", i))
+            .await
+            .unwrap();
+    }
+    db.flush().await.unwrap();
+    for i in 0..500u32 {
+        if i.is_multiple_of(2) {
+            db.put(&format!("k{:04}", i), "new").await.unwrap();
+        }
+    }
+    db.flush().await.unwrap();
+    let old: Vec<_> = db
+        .ssts()
+        .iter()
+        .map(|s| s.data_path().to_path_buf())
+        .collect();
+    let mut scan = db.scan("", None).await.unwrap();
+    let first = scan.next().await.unwrap().unwrap();
+    assert_eq!(first, ("k0000".into(), "new".into()));
+    db.compact().await.unwrap();
+    assert!(old.iter().all(|p| p.exists()));
+    let mut n = 1;
+    while let Some((k, v)) = scan.next().await.unwrap() {
+        let i: usize = k[1..].parse().unwrap();
+        assert_eq!(
+            v,
+            if i.is_multiple_of(2) {
+                "new".to_string()
+            } else {
+                format!("v{}", i)
+            }
+        );
+        n += 1;
+    }
+    assert_eq!(n, 500);
+    drop(scan);
+    assert!(old.iter().all(|p| !p.exists()));
+    assert_eq!(db.get("k0001").await.unwrap(), Some("v1".into()));
+    assert_eq!(db.get("k0002").await.unwrap(), Some("new".into()));
+    assert_eq!(db.get("k0499").await.unwrap(), Some("v499".into()));
+    assert_eq!(db.get("k0500").await.unwrap(), None);
+    drop(db);
+    let db = Db::new(&dir).await.unwrap();
+    assert_eq!(db.ssts().len(), 1);
+    assert_eq!(db.get("k0498").await.unwrap(), Some("new".into()));
+}
+
+#[tokio::test]
+async fn read_ahead() {
+    let dir = tmp("read_ahead");
+    let mut db = Db::open(
+        &dir,
+        DbOptions {
+            compaction_read_ahead: 1 << 20,
+            ..Default::default()
+        },
+    )
+    .await
+    .unwrap();
+    for i in 0..2 {
+        for j in 0..500 {
+            db.put(&format!("k{j:04}"), &format!("{i}")).await.unwrap();
+        }
+        db.flush().await.unwrap();
+    }
+    db.compact().await.unwrap();
+    assert_eq!(db.ssts().len(), 1);
+    assert_eq!(db.get("k0100").await.unwrap().unwrap(), "1");
+}
+
+#[tokio::test]
+async fn major() {
+    let dir = tmp("major");
+    let mut db = Db::new(&dir).await.unwrap();
+    for r in 0..5 {
+        for k in 0..50 {
+            if (k + r) % 7 == 0 {
+                db.delete(&format!("k{k:02}")).await.unwrap();
+            } else {
+                db.put(&format!("k{k:02}"), &format!("{r}")).await.unwrap();
+            }
+        }
+        db.flush().await.unwrap();
+    }
+    db.put("k00", "x").await.unwrap();
+    let mut expected = vec![];
+    let mut it = db.scan("", None).await.unwrap();
+    while let Some(kv) = it.next().await.unwrap() {
+        expected.push(kv);
+    }
+    db.major_compact().await.unwrap();
+    assert_eq!(db.ssts().len(), 1);
+    let mut it = db.ssts()[0].iter().await.unwrap();
+    let mut raw = vec![];
+    while let Some(p) = it.next().await.unwrap() {
+        raw.push((p.key, p.value.unwrap()));
+    }
+    assert_eq!(raw, expected);
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn maybe_compact() {
+    let dir = tmp("maybe_compact");
+    let mut db = Db::open(
+        &dir,
+        DbOptions {
+            compaction_trigger: 3,
+            ..Default::default()
+        },
+    )
+    .await
+    .unwrap();
+    let mut started = 0;
+    for round in 0..12 {
+        for i in 0..300 {
+            db.put(&format!("k{i:04}"), &format!("{round}"))
+                .await
+                .unwrap();
+        }
+        if round % 4 == 0 {
+            db.delete("k0001").await.unwrap();
+        }
+        db.flush().await.unwrap();
+        let t = std::time::Instant::now();
+        for _ in 0..5 {
+            if db.maybe_compact().unwrap() {
+                started += 1;
+            }
+        }
+        assert!(t.elapsed() < std::time::Duration::from_millis(50));
+        assert!(db.ssts().len() <= 12);
+    }
+    assert!((1..=12).contains(&started));
+    tokio::time::sleep(std::time::Duration::from_millis(300)).await;
+    db.put("z", "1").await.unwrap();
+    assert!(db.ssts().len() < 12, "{}", db.ssts().len());
+    assert_eq!(db.get("k0005").await.unwrap().unwrap(), "11");
+    assert_eq!(db.get("k0001").await.unwrap().unwrap(), "11");
+    assert!(
+        db.verify().await.unwrap().is_clean(),
+        "{:?}",
+        db.verify().await.unwrap().problems
+    );
+    db.compact().await.unwrap();
+    assert_eq!(db.ssts().len(), 1);
+    drop(db);
+    let db = Db::new(&dir).await.unwrap();
+    assert_eq!(db.get("k0299").await.unwrap().unwrap(), "11");
+    assert!(db.verify().await.unwrap().is_clean());
+}
+
+async fn picker_sst(
+    dir: &std::path::Path,
+    name: &str,
+    level: usize,
+    keys: std::ops::Range<u32>,
+    prefix: &str,
+) -> std::sync::Arc<Sst> {
+    let w = SstWriter::create(dir.join(name))
+        .await
+        .unwrap()
+        .with_level(level);
+    let data = keys.map(|i| Put {
+        key: format!("{prefix}{i:05}"),
+        value: Some("v".repeat(20)),
+        seq: 1,
+        flags: 0,
+        value_ref: None,
+        merge: false,
+    });
+    std::sync::Arc::new(Sst::construct_with(w, data).await.unwrap())
+}
+
+#[tokio::test]
+async fn picker() {
+    let dir = tmp("picker");
+    std::fs::create_dir_all(&dir).unwrap();
+    let big = picker_sst(&dir, "a", 1, 0..1000, "a").await;
+    let small = picker_sst(&dir, "b", 1, 0..500, "x").await;
+    let next = [
+        picker_sst(&dir, "c", 2, 0..5000, "a").await,
+        picker_sst(&dir, "d", 2, 0..10, "z").await,
+    ];
+    assert!(big
+        .meta()
+        .await
+        .unwrap()
+        .overlaps(next[0].meta().await.unwrap()));
+    assert!(!small
+        .meta()
+        .await
+        .unwrap()
+        .overlaps(next[0].meta().await.unwrap()));
+    let cands = [big.clone(), small.clone()];
+    let by_size = pick_compaction_input(&cands, &next, false)
+        .await
+        .unwrap()
+        .unwrap();
+    let by_overlap = pick_compaction_input(&cands, &next, true)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(by_size.data_path(), big.data_path());
+    assert_eq!(by_overlap.data_path(), small.data_path());
+    assert!(pick_compaction_input(&[], &next, true)
+        .await
+        .unwrap()
+        .is_none());
+    let mut db = Db::open(
+        tmp("picker-db"),
+        DbOptions {
+            compaction_pick_by_overlap: true,
+            ..Default::default()
+        },
+    )
+    .await
+    .unwrap();
+    db.put("a", "1").await.unwrap();
+    db.flush().await.unwrap();
+    assert!(db.pick_compaction_input(0).await.unwrap().is_some());
+    assert!(db.pick_compaction_input(1).await.unwrap().is_none());
+}
+
+#[tokio::test]
+async fn space_amp() {
+    let dir = tmp("space_amp");
+    let mut db = Db::new(&dir).await.unwrap();
+    assert_eq!(db.space_amplification().await.unwrap(), 1.0);
+    for round in 0..5 {
+        for i in 0..200 {
+            db.put(&format!("k{i:03}"), &format!("value-{round}-{i}"))
+                .await
+                .unwrap();
+        }
+        db.flush().await.unwrap();
+    }
+    for i in 0..50 {
+        db.delete(&format!("k{i:03}")).await.unwrap();
+    }
+    let amp = db.space_amplification().await.unwrap();
+    assert!(amp > 4.0, "{amp}");
+    db.major_compact().await.unwrap();
+    let amp = db.space_amplification().await.unwrap();
+    assert!((amp - 1.0).abs() < 1e-9, "{amp}");
+}
+
+#[tokio::test]
+async fn temp_files() {
+    let dir = tmp("temp_files");
+    let mut db = Db::new(&dir).await.unwrap();
+    db.put("a", "1").await.unwrap();
+    db.flush().await.unwrap();
+    db.put("b", "2").await.unwrap();
+    db.flush().await.unwrap();
+    drop(db);
+    assert!(std::fs::read_dir(&dir).unwrap().all(|e| !e
+        .unwrap()
+        .path()
+        .to_string_lossy()
+        .ends_with(".tmp")));
+    std::fs::write(
+        dir.join("00000009.data.tmp"),
+        "{\"key\":\"a\",\"value\":\"half",
+    )
+    .unwrap();
+    std::fs::write(dir.join("00000009.index.tmp"), "{").unwrap();
+    let db = Db::new(&dir).await.unwrap();
+    assert!(!dir.join("00000009.data.tmp").exists() && !dir.join("00000009.index.tmp").exists());
+    assert_eq!(db.get("a").await.unwrap().unwrap(), "1");
+    assert!(db.verify().await.unwrap().problems.is_empty());
+    drop(db);
+    std::fs::write(dir.join("00000009.data.tmp"), "junk").unwrap();
+    std::fs::remove_file(dir.join("manifest")).unwrap();
+    let mut db = Db::new(&dir).await.unwrap();
+    assert_eq!(db.ssts().len(), 2);
+    db.compact().await.unwrap();
+    assert_eq!(db.get("b").await.unwrap().unwrap(), "2");
+    assert!(db.verify().await.unwrap().problems.is_empty());
+}
+
+#[tokio::test]
+async fn read_repair() {
+    let dir = tmp("read_repair");
+    let opts = DbOptions {
+        read_repair: Some(2),
+        compaction_trigger: 100,
+        ..Default::default()
+    };
+    let mut db = Db::open(&dir, opts.clone()).await.unwrap();
+    db.put("deep", "old").await.unwrap();
+    db.flush().await.unwrap();
+    for i in 0..5 {
+        db.put(&format!("k{}", i), "x").await.unwrap();
+        db.flush().await.unwrap();
+    }
+    assert_eq!(db.get("deep").await.unwrap().unwrap(), "old");
+    // the deepest sst's files are gone, the cache still answers
+    let last = db.ssts().last().unwrap().clone();
+    let data = last.data_path().to_path_buf();
+    let bytes = std::fs::read(&data).unwrap();
+    std::fs::remove_file(&data).unwrap();
+    for _ in 0..3 {
+        assert_eq!(db.get("deep").await.unwrap().unwrap(), "old");
+    }
+    std::fs::write(&data, bytes).unwrap();
+    db.put("deep", "new").await.unwrap();
+    assert_eq!(db.get("deep").await.unwrap().unwrap(), "new");
+    db.flush().await.unwrap();
+    assert_eq!(db.get("deep").await.unwrap().unwrap(), "new");
+    db.delete("deep").await.unwrap();
+    db.flush().await.unwrap();
+    assert_eq!(db.get("deep").await.unwrap(), None);
+    assert_eq!(db.get("k0").await.unwrap().unwrap(), "x");
+}
+
+#[tokio::test]
+async fn compaction_debt() {
+    let dir = tmp("compaction_debt");
+    let opts = DbOptions {
+        compaction_trigger: 4,
+        ..Default::default()
+    };
+    let mut db = Db::open(&dir, opts).await.unwrap();
+    assert_eq!(db.compaction_debt().await.unwrap(), 0);
+    db.put("a", "1").await.unwrap();
+    db.flush().await.unwrap();
+    db.put("b", "1").await.unwrap();
+    db.flush().await.unwrap();
+    // disjoint
+    assert_eq!(db.compaction_debt().await.unwrap(), 0);
+    db.put("a", "2").await.unwrap();
+    db.flush().await.unwrap();
+    let small = db.compaction_debt().await.unwrap();
+    assert!(small > 0);
+    db.put("c", "2").await.unwrap();
+    db.flush().await.unwrap();
+    let total: u64 = db.ssts().iter().map(|s| s.size()).sum();
+    assert_eq!(db.compaction_debt().await.unwrap(), total);
+    assert!(db.maybe_compact().unwrap());
+    db.flush_all_and_checkpoint().await.unwrap();
+    assert_eq!(db.ssts().len(), 1);
+    assert_eq!(db.compaction_debt().await.unwrap(), 0);
+}
+
+#[tokio::test]
+async fn skip_disjoint() {
+    let dir = tmp("skip_disjoint");
+    let opts = DbOptions {
+        compaction_skip_disjoint: true,
+        compaction_trigger: 3,
+        ..Default::default()
+    };
+    let mut db = Db::open(&dir, opts.clone()).await.unwrap();
+    db.put("x", "1").await.unwrap();
+    db.put("z", "1").await.unwrap();
+    db.flush().await.unwrap();
+    let disjoint = db.ssts()[0].data_path().to_path_buf();
+    let bytes = std::fs::read(&disjoint).unwrap();
+    db.put("a", "1").await.unwrap();
+    db.put("c", "1").await.unwrap();
+    db.flush().await.unwrap();
+    db.put("b", "2").await.unwrap();
+    db.delete("c").await.unwrap();
+    db.flush().await.unwrap();
+    db.compact().await.unwrap();
+    assert_eq!(db.ssts().len(), 2);
+    assert!(db.ssts().iter().any(|s| s.data_path() == disjoint));
+    assert_eq!(std::fs::read(&disjoint).unwrap(), bytes);
+    assert_eq!(db.get("c").await.unwrap(), None);
+    assert_eq!(db.get("x").await.unwrap().unwrap(), "1");
+    // nothing left overlapping, nothing to do
+    db.compact().await.unwrap();
+    assert_eq!(db.ssts().len(), 2);
+    // the background compaction skips it as well
+    db.put("y", "2").await.unwrap();
+    db.flush().await.unwrap();
+    assert!(db.maybe_compact().unwrap());
+    db.flush_all_and_checkpoint().await.unwrap();
+    assert_eq!(db.ssts().len(), 2);
+    assert!(!db.ssts().iter().any(|s| s.data_path() == disjoint));
+    assert_eq!(db.get("y").await.unwrap().unwrap(), "2");
+    assert_eq!(db.get("a").await.unwrap().unwrap(), "1");
+    drop(db);
+    let db = Db::open(&dir, opts).await.unwrap();
+    assert_eq!(db.get("z").await.unwrap().unwrap(), "1");
+    let mut db = db;
+    db.major_compact().await.unwrap();
+    assert_eq!(db.ssts().len(), 1);
+}
+
+#[tokio::test]
+async fn restartable_compaction() {
+    let dir = tmp("restartable_compaction");
+    let mut db = Db::new(&dir).await.unwrap();
+    for i in 0..3 {
+        db.put("k", &i.to_string()).await.unwrap();
+        db.put(&format!("x{}", i), "1").await.unwrap();
+        db.flush().await.unwrap();
+    }
+    let inputs: Vec<_> = db
+        .ssts()
+        .iter()
+        .map(|s| s.data_path().with_extension(""))
+        .collect();
+    let saved: Vec<_> = inputs
+        .iter()
+        .flat_map(|p| {
+            ["data", "index"].map(|e| {
+                let f = p.with_extension(e);
+                (f.clone(), std::fs::read(&f).unwrap())
+            })
+        })
+        .collect();
+    db.compact().await.unwrap();
+    let version = Manifest::load(&dir).await.unwrap().unwrap();
+    assert_eq!(version.ssts.len(), 1);
+    assert!(
+        std::fs::read_to_string(dir.join("manifest.log"))
+            .unwrap()
+            .lines()
+            .count()
+            >= 4
+    );
+    drop(db);
+    // the crash: edit written, inputs never deleted, and half of another edit
+    for (f, bytes) in &saved {
+        std::fs::write(f, bytes).unwrap();
+    }
+    let mut f = std::fs::OpenOptions::new()
+        .append(true)
+        .open(dir.join("manifest.log"))
+        .unwrap();
+    std::io::Write::write_all(&mut f, b"{\"id\":99,\"next_sst").unwrap();
+    drop(f);
+    let (db, report) = Db::open_with_report(&dir, Default::default())
+        .await
+        .unwrap();
+    assert_eq!(report.orphans_removed.len(), 3);
+    assert_eq!(db.ssts().len(), 1);
+    assert_eq!(db.get("k").await.unwrap().unwrap(), "2");
+    for p in &inputs {
+        assert!(!p.with_extension("data").exists());
+    }
+    drop(db);
+    assert!(std::fs::read_to_string(dir.join("manifest.log"))
+        .unwrap()
+        .ends_with('\n'));
+    let mut db = Db::new(&dir).await.unwrap();
+    // enough edits to fold the log into a snapshot
+    for i in 0..300 {
+        db.put(&format!("y{:03}", i), "1").await.unwrap();
+        db.flush().await.unwrap();
+        if i % 50 == 49 {
+            db.compact().await.unwrap();
+        }
+    }
+    let ssts: Vec<_> = db
+        .ssts()
+        .iter()
+        .map(|s| s.data_path().to_path_buf())
+        .collect();
+    drop(db);
+    let lines = std::fs::read_to_string(dir.join("manifest.log"))
+        .unwrap_or_default()
+        .lines()
+        .count();
+    assert!(lines < 256, "{}", lines);
+    let db = Db::new(&dir).await.unwrap();
+    let reopened: Vec<_> = db
+        .ssts()
+        .iter()
+        .map(|s| s.data_path().to_path_buf())
+        .collect();
+    assert_eq!(reopened, ssts);
+    assert_eq!(db.get("y123").await.unwrap().unwrap(), "1");
+    assert_eq!(db.get("k").await.unwrap().unwrap(), "2");
+}
+
+#[tokio::test]
+async fn yielding() {
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+    use std::time::{Duration, Instant};
+    let dir = tmp("yielding");
+    let mut db = Db::open(
+        &dir,
+        DbOptions {
+            durability: Durability::NoSync,
+            yield_every: 64,
+            ..Default::default()
+        },
+    )
+    .await
+    .unwrap();
+    for round in 0..4 {
+        for i in 0..5000 {
+            db.put(&format!("k{:05}", i), &format!("{}", round))
+                .await
+                .unwrap();
+        }
+        db.flush().await.unwrap();
+    }
+    let stop = Arc::new(AtomicBool::new(false));
+    let ticker = {
+        let stop = stop.clone();
+        tokio::spawn(async move {
+            let mut worst = Duration::ZERO;
+            let mut ticks = 0;
+            let mut last = Instant::now();
+            while !stop.load(Ordering::SeqCst) {
+                tokio::time::sleep(Duration::from_millis(1)).await;
+                worst = worst.max(last.elapsed());
+                last = Instant::now();
+                ticks += 1;
+            }
+            (worst, ticks)
+        })
+    };
+    tokio::task::yield_now().await;
+    let started = Instant::now();
+    db.compact().await.unwrap();
+    let mut iter = db.scan("", None).await.unwrap();
+    let mut n = 0;
+    while iter.next().await.unwrap().is_some() {
+        n += 1;
+    }
+    let took = started.elapsed();
+    stop.store(true, Ordering::SeqCst);
+    let (worst, ticks) = ticker.await.unwrap();
+    assert_eq!(n, 5000);
+    assert!(ticks > 0);
+    assert!(
+        worst < Duration::from_millis(100),
+        "{:?} over {:?}",
+        worst,
+        took
+    );
+    assert_eq!(db.get("k00042").await.unwrap().unwrap(), "3");
+}
+
+#[tokio::test]
+async fn leveled() {
+    use std::collections::BTreeMap;
+    let dir = tmp("leveled");
+    let opts = DbOptions {
+        durability: Durability::NoSync,
+        max_levels: 4,
+        level_base_bytes: 8 * 1024,
+        level_multiplier: 4,
+        level_dirs: true,
+        ..Default::default()
+    };
+    let mut db = Db::open(&dir, opts.clone()).await.unwrap();
+    let mut model = BTreeMap::new();
+    let mut x: u64 = 12345;
+    let mut steps = 0;
+    for round in 0..60 {
+        for _ in 0..100 {
+            x = x
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            let k = format!("k{:05}", (x >> 33) % 4000);
+            if (x >> 20).is_multiple_of(11) {
+                db.delete(&k).await.unwrap();
+                model.remove(&k);
+            } else {
+                let v = format!("{}-{}", round, "v".repeat(20));
+                db.put(&k, &v).await.unwrap();
+                model.insert(k, v);
+            }
+        }
+        db.flush().await.unwrap();
+        steps += db.compact_leveled().await.unwrap();
+    }
+    assert!(steps > 10);
+    let sizes = db.level_sizes().await.unwrap();
+    assert_eq!(sizes.len(), 4);
+    // every flush was compacted out of L0, and the last level holds the most
+    assert_eq!(sizes[0], 0, "{:?}", sizes);
+    assert!(sizes[3] > sizes[2], "{:?}", sizes);
+    assert!(sizes[1] <= 8 * 1024, "{:?}", sizes);
+    assert!(sizes[2] <= 32 * 1024, "{:?}", sizes);
+    let ratio = sizes[2] as f64 / sizes[1] as f64;
+    assert!((2.0..8.0).contains(&ratio), "{:?}", sizes);
+    let model = &model;
+    let check = |db: Db| async move {
+        for i in 0..4000 {
+            let k = format!("k{:05}", i);
+            assert_eq!(db.get(&k).await.unwrap(), model.get(&k).cloned(), "{}", k);
+        }
+        let mut iter = db.scan("", None).await.unwrap();
+        let mut n = 0;
+        while iter.next().await.unwrap().is_some() {
+            n += 1;
+        }
+        assert_eq!(n, model.len());
+        db
+    };
+    let db = check(db).await;
+    drop(db);
+    let db = Db::open(&dir, opts).await.unwrap();
+    check(db).await;
+}
+
+#[tokio::test]
+async fn rate_limit() {
+    use std::sync::Arc;
+    let dir = tmp("rate_limit");
+    let limiter = Arc::new(RateLimiter::new(400_000));
+    let options = DbOptions {
+        compaction_rate_limit: Some(limiter.clone()),
+        ..DbOptions::default()
+    };
+    let mut db = Db::open(&dir, options).await.unwrap();
+    let value = "x".repeat(1000);
+    for round in 0..2 {
+        for i in 0..100 {
+            db.put(&format!("k{:04}{}", i, round), &value)
+                .await
+                .unwrap();
+        }
+        db.flush().await.unwrap();
+    }
+    let started = std::time::Instant::now();
+    db.compact().await.unwrap();
+    let elapsed = started.elapsed().as_secs_f64();
+    let taken = limiter.taken();
+    assert!(taken > 350_000, "{}", taken);
+    let rate = taken as f64 / elapsed;
+    assert!(rate <= 400_000.0 * 1.15, "{} over {}s", taken, elapsed);
+    assert!(db.get("k00001").await.unwrap().is_some());
+}
+
+#[tokio::test]
+async fn manual_background() {
+    let dir = tmp("manual_background");
+    let options = DbOptions {
+        manual_background: true,
+        async_flush: true,
+        flush_threshold: Some(200),
+        compaction_trigger: 2,
+        ..DbOptions::default()
+    };
+    let mut db = Db::open(&dir, options).await.unwrap();
+    assert_eq!(db.run_background_once().await.unwrap(), None);
+    for round in 0..2 {
+        for i in 0..20 {
+            db.put(&format!("k{:02}", i), &format!("v{}", round))
+                .await
+                .unwrap();
+        }
+        assert_eq!(db.ssts().len(), round);
+        assert!(!db.maybe_compact().unwrap());
+        assert_eq!(
+            db.run_background_once().await.unwrap(),
+            Some(BackgroundWork::Flush)
+        );
+        assert_eq!(db.ssts().len(), round + 1);
+    }
+    tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+    db.put("x", "1").await.unwrap();
+    assert!(!db.maybe_compact().unwrap());
+    assert_eq!(db.ssts().len(), 2);
+    assert_eq!(
+        db.run_background_once().await.unwrap(),
+        Some(BackgroundWork::Compaction)
+    );
+    assert_eq!(db.ssts().len(), 1);
+    assert_eq!(db.run_background_once().await.unwrap(), None);
+    assert_eq!(db.get("k05").await.unwrap().as_deref(), Some("v1"));
+    assert_eq!(db.get("x").await.unwrap().as_deref(), Some("1"));
+}
+
+#[tokio::test]
+async fn plan_compaction() {
+    let dir = tmp("plan_compaction");
+    let options = DbOptions {
+        compaction_trigger: 3,
+        level_base_bytes: 1 << 20,
+        ..DbOptions::default()
+    };
+    let mut db = Db::open(&dir, options).await.unwrap();
+    let listing = |dir: &std::path::Path| {
+        let mut files: Vec<_> = std::fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| {
+                let entry = entry.unwrap();
+                let meta = entry.metadata().unwrap();
+                (entry.path(), meta.len(), meta.modified().unwrap())
+            })
+            .collect();
+        files.sort();
+        files
+    };
+    for round in 0..2 {
+        for i in 0..50 {
+            db.put(&format!("k{:02}", i), &format!("v{}", round))
+                .await
+                .unwrap();
+        }
+        db.flush().await.unwrap();
+    }
+    assert_eq!(db.plan_compaction().await.unwrap(), None);
+    for i in 0..40 {
+        db.delete(&format!("k{:02}", i)).await.unwrap();
+    }
+    db.flush().await.unwrap();
+    let before = listing(&dir);
+    let plan = db.plan_compaction().await.unwrap().unwrap();
+    assert_eq!(listing(&dir), before);
+    assert_eq!(plan.level, 0);
+    assert_eq!(plan.target_level, 1);
+    let paths: Vec<_> = db
+        .ssts()
+        .iter()
+        .map(|sst| sst.data_path().to_path_buf())
+        .collect();
+    assert_eq!(plan.inputs, paths);
+    let total: u64 = db.ssts().iter().map(|sst| sst.size()).sum();
+    assert_eq!(plan.input_bytes, total);
+    let deleted = db.ssts()[0].size();
+    assert_eq!(plan.estimated_output_bytes, total - deleted);
+    assert_eq!(db.compact_leveled().await.unwrap(), 1);
+    assert!(db
+        .ssts()
+        .iter()
+        .all(|sst| !plan.inputs.contains(&sst.data_path().to_path_buf())));
+    let written: u64 = db.ssts().iter().map(|sst| sst.size()).sum();
+    assert!(
+        written <= plan.estimated_output_bytes,
+        "{} {:?}",
+        written,
+        plan
+    );
+    assert_eq!(db.plan_compaction().await.unwrap(), None);
+}
+
+#[tokio::test]
+async fn maintain() {
+    let dir = tmp("maintain");
+    let mut db = Db::open(&dir, DbOptions::default()).await.unwrap();
+    db.put("other", "x").await.unwrap();
+    db.flush().await.unwrap();
+    assert_eq!(db.maintain().await.unwrap(), 0);
+    db.put("k", "v0").await.unwrap();
+    db.put("gone", "1").await.unwrap();
+    db.flush().await.unwrap();
+    let dropped = db.snapshot().await.unwrap();
+    db.put("k", "v1").await.unwrap();
+    db.delete("gone").await.unwrap();
+    db.flush().await.unwrap();
+    let live = db.snapshot().await.unwrap();
+    db.put("k", "v2").await.unwrap();
+    db.flush().await.unwrap();
+    let values =
+        |versions: Vec<(u64, String)>| versions.into_iter().map(|(_, v)| v).collect::<Vec<_>>();
+    assert_eq!(values(db.get_versions("k").await.unwrap()).len(), 3);
+    drop(dropped);
+    assert_eq!(db.min_seq_in_use(), live.seq());
+    assert_eq!(db.maintain().await.unwrap(), 1);
+    assert_eq!(db.ssts().len(), 1);
+    assert_eq!(
+        values(db.get_versions("k").await.unwrap()),
+        vec!["v2".to_string(), "v1".to_string()]
+    );
+    assert!(db.get_versions("gone").await.unwrap().is_empty());
+    let opts = ScanOptions {
+        snapshot: Some(live.seq()),
+        ..ScanOptions::default()
+    };
+    let mut iter = db.scan_options(opts).await.unwrap();
+    let mut seen = Vec::new();
+    while let Some(kv) = iter.next().await.unwrap() {
+        seen.push(kv);
+    }
+    assert_eq!(
+        seen,
+        vec![
+            ("k".to_string(), "v1".to_string()),
+            ("other".to_string(), "x".to_string())
+        ]
+    );
+    drop(iter);
+    drop(live);
+    assert_eq!(db.maintain().await.unwrap(), 1);
+    assert_eq!(
+        values(db.get_versions("k").await.unwrap()),
+        vec!["v2".to_string()]
+    );
+    assert_eq!(db.maintain().await.unwrap(), 0);
+}
+
+#[tokio::test]
+async fn compaction_status() {
+    use std::sync::Arc;
+    let dir = tmp("compaction_status");
+    let options = DbOptions {
+        compaction_rate_limit: Some(Arc::new(RateLimiter::new(100_000))),
+        compaction_trigger: 2,
+        ..DbOptions::default()
+    };
+    let mut db = Db::open(&dir, options).await.unwrap();
+    assert_eq!(db.compaction_status(), None);
+    let value = "x".repeat(300);
+    for round in 0..2 {
+        for i in 0..100 {
+            db.put(&format!("k{:03}{}", i, round), &value)
+                .await
+                .unwrap();
+        }
+        db.flush().await.unwrap();
+    }
+    let paths: Vec<_> = db
+        .ssts()
+        .iter()
+        .map(|sst| sst.data_path().to_path_buf())
+        .collect();
+    let total: u64 = db.ssts().iter().map(|sst| sst.size()).sum();
+    assert!(db.maybe_compact().unwrap());
+    let mut seen = Vec::new();
+    while let Some(status) = db.compaction_status() {
+        if status.total_bytes > 0 {
+            assert_eq!(status.inputs, paths);
+            assert_eq!(status.total_bytes, total);
+        }
+        seen.push(status.percent());
+        tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+    }
+    assert!(seen.len() > 5, "{:?}", seen);
+    assert!(seen.windows(2).all(|w| w[0] <= w[1]), "{:?}", seen);
+    assert!(seen[0] < 50.0 && *seen.last().unwrap() > 80.0, "{:?}", seen);
+    db.put("y", "1").await.unwrap();
+    assert_eq!(db.ssts().len(), 1);
+    assert_eq!(db.compaction_status(), None);
+}
+
+#[tokio::test]
+async fn drop_shadowed() {
+    let dir = tmp("drop_shadowed");
+    let mut db = Db::open(&dir, DbOptions::default()).await.unwrap();
+    for i in 10..20 {
+        db.put(&format!("k{}", i), "old").await.unwrap();
+    }
+    db.flush().await.unwrap();
+    // partly shadowed, stays
+    db.put("k05", "a").await.unwrap();
+    db.put("k12", "b").await.unwrap();
+    db.flush().await.unwrap();
+    for i in 10..20 {
+        if i % 2 == 0 {
+            db.put(&format!("k{}", i), "new").await.unwrap();
+        } else {
+            db.delete(&format!("k{}", i)).await.unwrap();
+        }
+    }
+    db.put("k30", "c").await.unwrap();
+    db.flush().await.unwrap();
+    let snap = db.snapshot().await.unwrap();
+    db.put("k05", "a2").await.unwrap();
+    db.flush().await.unwrap();
+    assert_eq!(db.ssts().len(), 4);
+    let oldest = db.ssts()[3].data_path().to_path_buf();
+    // the second is shadowed only by a write the snapshot doesn't see
+    assert_eq!(db.drop_shadowed_ssts().await.unwrap(), 1);
+    assert_eq!(db.ssts().len(), 3);
+    assert!(db.ssts().iter().all(|sst| sst.data_path() != oldest));
+    // nothing else goes while the snapshot needs k05 a
+    assert_eq!(db.drop_shadowed_ssts().await.unwrap(), 0);
+    drop(snap);
+    assert_eq!(db.drop_shadowed_ssts().await.unwrap(), 1);
+    assert_eq!(db.ssts().len(), 2);
+    for i in 10..20 {
+        let expect = (i % 2 == 0).then(|| "new".to_string());
+        assert_eq!(db.get(&format!("k{}", i)).await.unwrap(), expect, "{}", i);
+    }
+    assert_eq!(db.get("k05").await.unwrap().as_deref(), Some("a2"));
+    assert_eq!(db.get("k30").await.unwrap().as_deref(), Some("c"));
+    drop(db);
+    let options = DbOptions {
+        auto_drop_shadowed: true,
+        ..DbOptions::default()
+    };
+    let mut db = Db::open(&dir, options).await.unwrap();
+    assert_eq!(db.get("k14").await.unwrap().as_deref(), Some("new"));
+    let n = db.ssts().len();
+    db.put("k05", "a3").await.unwrap();
+    db.flush().await.unwrap();
+    assert_eq!(db.ssts().len(), n);
+    assert_eq!(db.get("k05").await.unwrap().as_deref(), Some("a3"));
+}
+
+// the index of an sst written before the last key was kept
+fn forget_max_key(sst: &Sst) {
+    let mut meta: serde_json::Value =
+        serde_json::from_slice(&std::fs::read(sst.index_path()).unwrap()).unwrap();
+    meta.as_object_mut().unwrap().remove("max_key");
+    std::fs::write(sst.index_path(), meta.to_string()).unwrap();
+}
+
+#[tokio::test]
+async fn drop_shadowed_unknown_range() {
+    let dir = tmp("drop_shadowed_unknown_range");
+    let mut db = Db::new(&dir).await.unwrap();
+    db.put("a", "old").await.unwrap();
+    db.put("z", "old").await.unwrap();
+    db.flush().await.unwrap();
+    db.put("a", "new").await.unwrap();
+    db.flush().await.unwrap();
+    let oldest = db.ssts()[1].clone();
+    drop(db);
+    forget_max_key(&oldest);
+    let mut db = Db::new(&dir).await.unwrap();
+    assert_eq!(db.ssts()[1].meta().await.unwrap().max_key(), None);
+    // z is only in the oldest
+    assert_eq!(db.drop_shadowed_ssts().await.unwrap(), 0);
+    assert_eq!(db.get("z").await.unwrap().as_deref(), Some("old"));
+    // every key it holds is newer elsewhere now, unknown range or not
+    db.put("z", "new").await.unwrap();
+    db.flush().await.unwrap();
+    assert_eq!(db.drop_shadowed_ssts().await.unwrap(), 1);
+    assert_eq!(db.get("a").await.unwrap().as_deref(), Some("new"));
+    assert_eq!(db.get("z").await.unwrap().as_deref(), Some("new"));
+}
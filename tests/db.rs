@@ -0,0 +1,2374 @@
+mod common;
+
+use common::tmp;
+use lsm_based_db_in_rust::*;
+
+#[tokio::test]
+async fn tombstone_in_memtable_hides_sst() {
+    let dir = tmp("tombstone_in_memtable_hides_sst");
+    let mut db = Db::new(&dir).await.unwrap();
+    db.put("a", "1").await.unwrap();
+    db.put("b", "2").await.unwrap();
+    db.flush().await.unwrap();
+    db.delete("a").await.unwrap();
+    assert_eq!(db.get("a").await.unwrap(), None);
+    assert_eq!(db.get("b").await.unwrap(), Some("2".into()));
+    let mut it = db.scan("", None).await.unwrap();
+    assert_eq!(it.next().await.unwrap(), Some(("b".into(), "2".into())));
+    assert_eq!(it.next().await.unwrap(), None);
+    drop(db);
+    let mut db = Db::new(&dir).await.unwrap();
+    assert_eq!(db.get("a").await.unwrap(), None);
+    db.flush().await.unwrap();
+    assert_eq!(db.get("a").await.unwrap(), None);
+    db.compact().await.unwrap();
+    assert_eq!(db.get("a").await.unwrap(), None);
+    assert_eq!(db.get("b").await.unwrap(), Some("2".into()));
+}
+
+#[tokio::test]
+async fn parallel_reads_newest_wins() {
+    let dir = tmp("parallel_reads_newest_wins");
+    let mut db = Db::open(
+        &dir,
+        DbOptions {
+            parallel_reads: true,
+            ..Default::default()
+        },
+    )
+    .await
+    .unwrap();
+    for v in ["1", "2", "3"] {
+        db.put("k", v).await.unwrap();
+        for i in 0..100 {
+            db.put(&format!("f{}{}", v, i), "x").await.unwrap();
+        }
+        db.flush().await.unwrap();
+    }
+    assert_eq!(db.ssts().len(), 3);
+    for _ in 0..20 {
+        assert_eq!(db.get("k").await.unwrap(), Some("3".into()));
+    }
+    db.delete("k").await.unwrap();
+    db.flush().await.unwrap();
+    assert_eq!(db.get("k").await.unwrap(), None);
+    assert_eq!(db.get("nope").await.unwrap(), None);
+    drop(db);
+    let mut db = Db::new(&dir).await.unwrap();
+    db.put("k", "4").await.unwrap();
+    db.flush().await.unwrap();
+    let db = Db::open(
+        &dir,
+        DbOptions {
+            parallel_reads: true,
+            ..Default::default()
+        },
+    )
+    .await
+    .unwrap();
+    assert_eq!(db.get("k").await.unwrap(), Some("4".into()));
+}
+
+#[test]
+fn error_sources() {
+    use std::error::Error;
+
+    let io = DBError::from(std::io::Error::new(std::io::ErrorKind::NotFound, "gone"));
+    let source = io.source().unwrap();
+    let source = source.downcast_ref::<std::io::Error>().unwrap();
+    assert_eq!(source.kind(), std::io::ErrorKind::NotFound);
+
+    let serde = DBError::from(serde_json::from_str::<u64>("x").unwrap_err());
+    assert!(serde.source().unwrap().is::<serde_json::Error>());
+
+    assert!(DBError::Timeout.source().is_none());
+    assert!(DBError::InvalidInput("bad".into()).source().is_none());
+}
+
+#[tokio::test]
+async fn stream_combinators() {
+    use futures::{StreamExt, TryStreamExt};
+    let dir = tmp("stream_combinators");
+    let mut db = Db::new(&dir).await.unwrap();
+    for i in 0..10 {
+        db.put(&format!("k{}", i), &i.to_string()).await.unwrap();
+    }
+    db.flush().await.unwrap();
+    db.put("k3", "x").await.unwrap();
+    let s = db.scan("k2", None).await.unwrap().into_stream();
+    let got: Vec<_> = s.take(3).try_collect().await.unwrap();
+    assert_eq!(
+        got,
+        vec![
+            ("k2".into(), "2".into()),
+            ("k3".into(), "x".into()),
+            ("k4".into(), "4".into())
+        ]
+    );
+}
+
+#[tokio::test]
+async fn flags_roundtrip() {
+    let dir = tmp("flags_roundtrip");
+    let mut db = Db::new(&dir).await.unwrap();
+    db.put_with_flags("a", "1", 7).await.unwrap();
+    db.put("b", "2").await.unwrap();
+    assert_eq!(db.get_flags("a").await.unwrap(), Some(7));
+    db.flush().await.unwrap();
+    db.put("c", "3").await.unwrap();
+    db.flush().await.unwrap();
+    assert_eq!(db.get_flags("a").await.unwrap(), Some(7));
+    db.compact().await.unwrap();
+    assert_eq!(db.get_flags("a").await.unwrap(), Some(7));
+    assert_eq!(db.get_flags("b").await.unwrap(), Some(0));
+    assert_eq!(db.get_flags("z").await.unwrap(), None);
+}
+
+#[tokio::test]
+async fn verify_report() {
+    let dir = tmp("verify_report");
+    let mut db = Db::new(&dir).await.unwrap();
+    for i in 0..100 {
+        db.put(&format!("k{:03}", i), "v").await.unwrap();
+    }
+    db.flush().await.unwrap();
+    let r = db.verify().await.unwrap();
+    assert!(r.is_clean(), "{:?}", r.problems);
+    assert_eq!(r.records_checked, 100);
+    // corrupt: swap two lines in data file
+    let path = db.ssts()[0].data_path().to_path_buf();
+    let text = std::fs::read_to_string(&path).unwrap();
+    let mut lines: Vec<&str> = text.lines().collect();
+    lines.swap(3, 4);
+    std::fs::write(&path, lines.join("\n") + "\n").unwrap();
+    std::fs::write(dir.join("00000099.index"), "{}").unwrap();
+    let r = db.verify().await.unwrap();
+    let problems: Vec<String> = r.problems.iter().map(|p| p.to_string()).collect();
+    let found = |text: &str| problems.iter().any(|p| p.contains(text));
+    assert!(found("checksum mismatch"), "{:?}", problems);
+    assert!(found("key \"k003\" out of order"), "{:?}", problems);
+    assert!(found("00000099.data is missing"), "{:?}", problems);
+}
+
+#[tokio::test]
+async fn verify_overlapping_levels() {
+    let dir = tmp("verify_overlapping_levels");
+    let mut db = Db::new(&dir).await.unwrap();
+    for key in ["a", "m", "z"] {
+        db.put(key, "v").await.unwrap();
+        db.flush().await.unwrap();
+    }
+    db.compact().await.unwrap();
+    // L0 ssts may overlap each other and L1
+    db.put("b", "v").await.unwrap();
+    db.flush().await.unwrap();
+    assert!(db.verify().await.unwrap().is_clean());
+
+    let external = tmp("verify_overlapping_levels-ext");
+    std::fs::create_dir_all(&external).unwrap();
+    let mut writer = SstWriter::create(external.join("1"))
+        .await
+        .unwrap()
+        .with_level(1);
+    for key in ["c", "n"] {
+        writer
+            .add(&Put {
+                key: key.into(),
+                value: Some("w".into()),
+                seq: 100,
+                flags: 0,
+                value_ref: None,
+                merge: false,
+            })
+            .await
+            .unwrap();
+    }
+    writer.finish().await.unwrap();
+    db.ingest_sst(external.join("1")).await.unwrap();
+    let report = db.verify().await.unwrap();
+    assert_eq!(report.problems.len(), 1, "{:?}", report.problems);
+    assert!(report.problems[0].description.contains("L1 sst overlaps"));
+}
+
+#[tokio::test]
+async fn put_if_absent_concurrent() {
+    let dir = tmp("put_if_absent_concurrent");
+    let db = std::sync::Arc::new(tokio::sync::Mutex::new(Db::new(&dir).await.unwrap()));
+    let mut hs = vec![];
+    for i in 0..10 {
+        let db = db.clone();
+        hs.push(tokio::spawn(async move {
+            db.lock()
+                .await
+                .put_if_absent("k", &i.to_string())
+                .await
+                .unwrap()
+        }));
+    }
+    let mut wins = 0;
+    for h in hs {
+        if h.await.unwrap() {
+            wins += 1;
+        }
+    }
+    assert_eq!(wins, 1);
+    let mut db = db.lock().await;
+    db.delete("k").await.unwrap();
+    assert!(db.put_if_absent("k", "x").await.unwrap());
+}
+
+#[tokio::test]
+async fn level_dirs() {
+    let dir = tmp("level_dirs");
+    let opts = DbOptions {
+        level_dirs: true,
+        ..Default::default()
+    };
+    let mut db = Db::open(&dir, opts.clone()).await.unwrap();
+    db.put("a", "1").await.unwrap();
+    db.flush().await.unwrap();
+    db.put("b", "2").await.unwrap();
+    db.flush().await.unwrap();
+    db.compact().await.unwrap();
+    db.put("c", "3").await.unwrap();
+    db.flush().await.unwrap();
+    assert!(dir.join("L1/00000002.data").exists());
+    assert!(dir.join("L0/00000003.data").exists());
+    assert!(!dir.join("L0/00000000.data").exists());
+    drop(db);
+    let db = Db::open(&dir, opts).await.unwrap();
+    let mut levels = vec![];
+    for s in db.ssts() {
+        levels.push(s.meta().await.unwrap().level());
+    }
+    assert_eq!(levels, vec![0, 1]);
+    assert_eq!(db.get("a").await.unwrap(), Some("1".into()));
+    assert!(db.verify().await.unwrap().is_clean());
+}
+
+#[tokio::test]
+async fn checkpoint_copy() {
+    let dir = tmp("checkpoint_copy");
+    let cp = tmp("checkpoint_copy-cp");
+    let mut db = Db::open(
+        &dir,
+        DbOptions {
+            level_dirs: true,
+            ..Default::default()
+        },
+    )
+    .await
+    .unwrap();
+    db.put("a", "1").await.unwrap();
+    db.flush().await.unwrap();
+    db.put("b", "2").await.unwrap();
+    db.delete("a").await.unwrap();
+    db.checkpoint(&cp).await.unwrap();
+    db.put("b", "3").await.unwrap();
+    db.put("c", "4").await.unwrap();
+    db.flush().await.unwrap();
+    db.compact().await.unwrap();
+    let c = Db::open(
+        &cp,
+        DbOptions {
+            level_dirs: true,
+            ..Default::default()
+        },
+    )
+    .await
+    .unwrap();
+    assert_eq!(c.get("a").await.unwrap(), None);
+    assert_eq!(c.get("b").await.unwrap(), Some("2".into()));
+    assert_eq!(c.get("c").await.unwrap(), None);
+    assert!(c.verify().await.unwrap().is_clean());
+    assert!(db.checkpoint(&cp).await.is_err());
+}
+
+#[tokio::test]
+async fn timeouts() {
+    use std::time::Duration;
+    let dir = tmp("timeouts");
+    let mut db = Db::open(
+        &dir,
+        DbOptions {
+            compact_timeout: Some(Duration::from_nanos(1)),
+            ..Default::default()
+        },
+    )
+    .await
+    .unwrap();
+    // big enough that the merge can't finish inside the timer's first tick
+    for i in 0..3 {
+        for j in 0..8000 {
+            db.put(&format!("k{:05}", j), &format!("v{}-{:0>64}", i, j))
+                .await
+                .unwrap();
+        }
+        db.flush().await.unwrap();
+    }
+    let before = std::fs::read_dir(&dir).unwrap().count();
+    let err = db.compact().await.unwrap_err();
+    assert!(matches!(err, DBError::Timeout), "{err}");
+    assert_eq!(std::fs::read_dir(&dir).unwrap().count(), before);
+    assert_eq!(db.ssts().len(), 3);
+    assert!(db.get("k00001").await.unwrap().unwrap().starts_with("v2-"));
+    drop(db);
+    let db = Db::open(
+        &dir,
+        DbOptions {
+            scan_timeout: Some(Duration::from_nanos(1)),
+            ..Default::default()
+        },
+    )
+    .await
+    .unwrap();
+    let mut it = db.scan("", None).await.unwrap();
+    let mut got_timeout = false;
+    loop {
+        match it.next().await {
+            Ok(Some(_)) => {}
+            Ok(None) => break,
+            Err(DBError::Timeout) => {
+                got_timeout = true;
+                break;
+            }
+            Err(e) => panic!("{e}"),
+        }
+    }
+    assert!(got_timeout);
+    assert!(it.next().await.unwrap().is_none());
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn skiplist() {
+    let dir = tmp("skiplist");
+    let mut db = Db::open(
+        &dir,
+        DbOptions {
+            skip_list_memtable: true,
+            ..Default::default()
+        },
+    )
+    .await
+    .unwrap();
+    let reader = db.memtable_reader().unwrap();
+    let stop = std::sync::Arc::new(std::sync::atomic::AtomicBool::new(false));
+    let mut handles = vec![];
+    for _ in 0..3 {
+        let r = reader.clone();
+        let stop = stop.clone();
+        handles.push(std::thread::spawn(move || {
+            while !stop.load(std::sync::atomic::Ordering::Relaxed) {
+                let all = r.range("", None);
+                for w in all.windows(2) {
+                    assert!(w[0].key < w[1].key);
+                }
+                for p in all {
+                    assert!(p.value.unwrap().starts_with(&format!("{}-", p.key)));
+                }
+            }
+        }));
+    }
+    for i in 0..2000u64 {
+        let k = format!("k{:03}", i % 300);
+        db.put(&k, &format!("{}-{}", k, i)).await.unwrap();
+    }
+    stop.store(true, std::sync::atomic::Ordering::Relaxed);
+    for h in handles {
+        h.join().unwrap();
+    }
+    assert_eq!(reader.len(), 300);
+    db.flush().await.unwrap();
+    assert_eq!(db.get("k001").await.unwrap().unwrap(), "k001-1801");
+    drop(db);
+    let db = Db::open(
+        &dir,
+        DbOptions {
+            skip_list_memtable: true,
+            ..Default::default()
+        },
+    )
+    .await
+    .unwrap();
+    assert_eq!(db.get("k001").await.unwrap().unwrap(), "k001-1801");
+}
+
+#[tokio::test]
+async fn delete_if() {
+    let dir = tmp("delete_if");
+    let mut db = Db::new(&dir).await.unwrap();
+    db.put("a", "1").await.unwrap();
+    let seen = db.get("a").await.unwrap().unwrap();
+    db.put("a", "2").await.unwrap();
+    assert!(!db.delete_if("a", &seen).await.unwrap());
+    assert_eq!(db.get("a").await.unwrap().unwrap(), "2");
+    assert!(db.delete_if("a", "2").await.unwrap());
+    assert!(db.get("a").await.unwrap().is_none());
+    assert!(!db.delete_if("a", "2").await.unwrap());
+}
+
+#[tokio::test]
+async fn vlog() {
+    let dir = tmp("vlog");
+    let mut db = Db::open(
+        &dir,
+        DbOptions {
+            value_separation_threshold: Some(100),
+            ..Default::default()
+        },
+    )
+    .await
+    .unwrap();
+    let big = |i: usize| format!("{i}").repeat(1000);
+    for i in 0..20 {
+        db.put(&format!("k{i:02}"), &big(i)).await.unwrap();
+        db.put(&format!("s{i:02}"), "small").await.unwrap();
+    }
+    db.flush().await.unwrap();
+    db.put("k03", &big(99)).await.unwrap();
+    db.flush().await.unwrap();
+    let vlog_len = std::fs::metadata(dir.join("vlog")).unwrap().len();
+    db.compact().await.unwrap();
+    assert_eq!(std::fs::metadata(dir.join("vlog")).unwrap().len(), vlog_len);
+    assert!(db.ssts()[0].size() < 5000, "{}", db.ssts()[0].size());
+    assert_eq!(db.get("k03").await.unwrap().unwrap(), big(99));
+    assert_eq!(db.get("k05").await.unwrap().unwrap(), big(5));
+    assert_eq!(db.get("s05").await.unwrap().unwrap(), "small");
+    let mut it = db.scan("k00", Some("k02")).await.unwrap();
+    assert_eq!(it.next().await.unwrap().unwrap().1, big(0));
+    drop(db);
+    let db = Db::new(&dir).await.unwrap();
+    assert_eq!(db.get("k19").await.unwrap().unwrap(), big(19));
+    let cp = tmp("vlog-cp");
+    db.checkpoint(&cp).await.unwrap();
+    assert_eq!(
+        Db::new(&cp)
+            .await
+            .unwrap()
+            .get("k19")
+            .await
+            .unwrap()
+            .unwrap(),
+        big(19)
+    );
+}
+
+#[tokio::test]
+async fn first_last() {
+    let dir = tmp("first_last");
+    let mut db = Db::new(&dir).await.unwrap();
+    assert_eq!(db.first_key().await.unwrap(), None);
+    assert_eq!(db.last_key().await.unwrap(), None);
+    for i in 0..100 {
+        db.put(&format!("k{i:03}"), "v").await.unwrap();
+    }
+    db.flush().await.unwrap();
+    for i in (0..5).chain(60..100) {
+        db.delete(&format!("k{i:03}")).await.unwrap();
+    }
+    assert_eq!(db.first_key().await.unwrap().unwrap(), "k005");
+    assert_eq!(db.last_key().await.unwrap().unwrap(), "k059");
+    db.flush().await.unwrap();
+    assert_eq!(db.last_key().await.unwrap().unwrap(), "k059");
+    db.put("a", "1").await.unwrap();
+    db.put("z", "1").await.unwrap();
+    assert_eq!(db.first_key().await.unwrap().unwrap(), "a");
+    assert_eq!(db.last_key().await.unwrap().unwrap(), "z");
+    db.delete("z").await.unwrap();
+    db.compact().await.unwrap();
+    assert_eq!(db.last_key().await.unwrap().unwrap(), "k059");
+    for i in 5..60 {
+        db.delete(&format!("k{i:03}")).await.unwrap();
+    }
+    db.delete("a").await.unwrap();
+    assert_eq!(db.last_key().await.unwrap(), None);
+}
+
+#[tokio::test]
+async fn versions() {
+    let dir = tmp("versions");
+    let mut db = Db::open(
+        &dir,
+        DbOptions {
+            versions_to_keep: 3,
+            ..Default::default()
+        },
+    )
+    .await
+    .unwrap();
+    for i in 0..5 {
+        db.put("k", &format!("v{i}")).await.unwrap();
+        for j in 0..40 {
+            db.put(&format!("f{j:02}"), &format!("{i}")).await.unwrap();
+        }
+        db.flush().await.unwrap();
+    }
+    db.compact().await.unwrap();
+    assert_eq!(db.ssts().len(), 1);
+    let v: Vec<String> = db
+        .get_versions("k")
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|v| v.1)
+        .collect();
+    assert_eq!(v, ["v4", "v3", "v2"]);
+    assert_eq!(db.get("k").await.unwrap().unwrap(), "v4");
+    assert_eq!(db.get("f20").await.unwrap().unwrap(), "4");
+    assert_eq!(db.get_versions("f20").await.unwrap().len(), 3);
+    let mut it = db.scan("", None).await.unwrap();
+    let mut n = 0;
+    while let Some((k, v)) = it.next().await.unwrap() {
+        n += 1;
+        if k == "k" {
+            assert_eq!(v, "v4");
+        }
+    }
+    assert_eq!(n, 41);
+    assert!(
+        db.verify().await.unwrap().is_clean(),
+        "{:?}",
+        db.verify().await.unwrap().problems
+    );
+    db.delete("k").await.unwrap();
+    db.flush().await.unwrap();
+    db.put("k", "new").await.unwrap();
+    db.flush().await.unwrap();
+    db.compact().await.unwrap();
+    let v: Vec<String> = db
+        .get_versions("k")
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|v| v.1)
+        .collect();
+    assert_eq!(v, ["new"]);
+    db.delete("f20").await.unwrap();
+    db.flush().await.unwrap();
+    db.compact().await.unwrap();
+    assert!(db.get("f20").await.unwrap().is_none());
+    assert!(db.get_versions("f20").await.unwrap().is_empty());
+    assert_eq!(db.get("f21").await.unwrap().unwrap(), "4");
+}
+
+#[tokio::test]
+async fn iter_from() {
+    let dir = tmp("iter_from");
+    let mut db = Db::new(&dir).await.unwrap();
+    for i in (0..100).step_by(2) {
+        db.put(&format!("k{i:03}"), "s").await.unwrap();
+    }
+    db.flush().await.unwrap();
+    for i in (1..100).step_by(2) {
+        db.put(&format!("k{i:03}"), "m").await.unwrap();
+    }
+    let mut it = db.iter_from("k050").await.unwrap();
+    let mut keys = vec![];
+    while let Some((k, _)) = it.next().await.unwrap() {
+        keys.push(k);
+    }
+    assert_eq!(
+        keys,
+        (50..100).map(|i| format!("k{i:03}")).collect::<Vec<_>>()
+    );
+}
+
+#[tokio::test]
+async fn callbacks() {
+    use std::sync::{Arc, Mutex};
+    let log = Arc::new(Mutex::new(Vec::<String>::new()));
+    let (a, b, c) = (log.clone(), log.clone(), log.clone());
+    let cb = lsm_based_db_in_rust::Callbacks {
+        on_write: Some(Arc::new(move |p: &Put| {
+            a.lock()
+                .unwrap()
+                .push(format!("w {} {:?} {}", p.key, p.value, p.seq))
+        })),
+        on_flush: Some(Arc::new(move |s: &lsm_based_db_in_rust::Sst| {
+            b.lock().unwrap().push(format!("f {}", s.size() > 0))
+        })),
+        on_compaction: Some(Arc::new(move |s: &lsm_based_db_in_rust::Sst| {
+            c.lock().unwrap().push(format!("c {}", s.size() > 0))
+        })),
+    };
+    let dir = tmp("callbacks");
+    let mut db = Db::open(
+        &dir,
+        DbOptions {
+            callbacks: cb,
+            ..Default::default()
+        },
+    )
+    .await
+    .unwrap();
+    db.put("a", "1").await.unwrap();
+    db.flush().await.unwrap();
+    db.delete("a").await.unwrap();
+    db.put("b", "2").await.unwrap();
+    db.flush().await.unwrap();
+    db.compact().await.unwrap();
+    assert_eq!(
+        *log.lock().unwrap(),
+        [
+            "w a Some(\"1\") 1",
+            "f true",
+            "w a None 2",
+            "w b Some(\"2\") 3",
+            "f true",
+            "c true"
+        ]
+    );
+}
+
+#[tokio::test]
+async fn u64_keys() {
+    use lsm_based_db_in_rust::{decode_u64_key, encode_u64_key};
+    let dir = tmp("u64_keys");
+    let mut db = Db::new(&dir).await.unwrap();
+    let nums = [5u64, 1000, 9, u64::MAX, 0, 10, 99999999];
+    for n in &nums[..4] {
+        db.put(&encode_u64_key(*n), "v").await.unwrap();
+    }
+    db.flush().await.unwrap();
+    for n in &nums[4..] {
+        db.put(&encode_u64_key(*n), "v").await.unwrap();
+    }
+    let mut it = db.scan("", None).await.unwrap();
+    let mut got = vec![];
+    while let Some((k, _)) = it.next().await.unwrap() {
+        got.push(decode_u64_key(&k).unwrap());
+    }
+    let mut sorted = nums.to_vec();
+    sorted.sort();
+    assert_eq!(got, sorted);
+    assert_eq!(decode_u64_key("00000000000000FF"), None);
+    assert_eq!(decode_u64_key("+0000000000000ff"), None);
+}
+
+#[tokio::test]
+async fn range_count() {
+    let dir = tmp("range_count");
+    let mut db = Db::new(&dir).await.unwrap();
+    for i in 0..100 {
+        db.put(&format!("k{i:03}"), "a").await.unwrap();
+    }
+    db.flush().await.unwrap();
+    for i in (0..100).step_by(3) {
+        db.delete(&format!("k{i:03}")).await.unwrap();
+    }
+    db.flush().await.unwrap();
+    for i in (0..100).step_by(9) {
+        db.put(&format!("k{i:03}"), "b").await.unwrap();
+    }
+    let expected = (10..60).filter(|i| i % 3 != 0 || i % 9 == 0).count() as u64;
+    assert_eq!(db.range_count("k010", "k060").await.unwrap(), expected);
+    assert_eq!(db.range_count("x", "y").await.unwrap(), 0);
+}
+
+#[tokio::test]
+async fn idle_flush() {
+    use std::{sync::Arc, time::Duration};
+    let clock = Arc::new(lsm_based_db_in_rust::MockClock::default());
+    let dir = tmp("idle_flush");
+    let mut db = Db::open(
+        &dir,
+        DbOptions {
+            clock: Some(clock.clone()),
+            idle_flush: Some(Duration::from_secs(10)),
+            ..Default::default()
+        },
+    )
+    .await
+    .unwrap();
+    db.put("a", "1").await.unwrap();
+    clock.advance(Duration::from_secs(5));
+    assert!(!db.flush_if_idle().await.unwrap());
+    assert!(db.ssts().is_empty());
+    clock.advance(Duration::from_secs(6));
+    assert!(db.flush_if_idle().await.unwrap());
+    assert_eq!(db.ssts().len(), 1);
+    assert!(db.ssts()[0].get_record("a").await.unwrap().is_some());
+    clock.advance(Duration::from_secs(60));
+    assert!(!db.flush_if_idle().await.unwrap());
+}
+
+#[tokio::test]
+async fn idle_flush_ticker() {
+    use std::{sync::Arc, time::Duration};
+    use tokio::sync::RwLock;
+    let dir = tmp("idle_flush_ticker");
+    let options = DbOptions {
+        idle_flush: Some(Duration::from_millis(20)),
+        ..Default::default()
+    };
+    let db = Arc::new(RwLock::new(Db::open(&dir, options).await.unwrap()));
+    let ticker = Db::spawn_idle_flush(&db).await.unwrap();
+    db.write().await.put("a", "1").await.unwrap();
+    for _ in 0..100 {
+        if !db.read().await.ssts().is_empty() {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    assert_eq!(db.read().await.ssts().len(), 1);
+    drop(db);
+    tokio::time::timeout(Duration::from_secs(1), ticker)
+        .await
+        .unwrap()
+        .unwrap();
+
+    // left to run_background_once
+    let clock = Arc::new(lsm_based_db_in_rust::MockClock::default());
+    let dir = tmp("idle_flush_ticker-manual");
+    let options = DbOptions {
+        clock: Some(clock.clone()),
+        idle_flush: Some(Duration::from_secs(10)),
+        manual_background: true,
+        ..Default::default()
+    };
+    let db = Arc::new(RwLock::new(Db::open(&dir, options).await.unwrap()));
+    assert!(Db::spawn_idle_flush(&db).await.is_none());
+    let mut db = db.write().await;
+    db.put("a", "1").await.unwrap();
+    assert_eq!(db.run_background_once().await.unwrap(), None);
+    clock.advance(Duration::from_secs(11));
+    assert_eq!(
+        db.run_background_once().await.unwrap(),
+        Some(BackgroundWork::Flush)
+    );
+    assert_eq!(db.ssts().len(), 1);
+}
+
+#[tokio::test]
+async fn overlay() {
+    let base_dir = tmp("overlay-b");
+    let ov = tmp("overlay-o");
+    let mut base = Db::new(&base_dir).await.unwrap();
+    for k in ["a", "b", "c", "d"] {
+        base.put(k, &format!("base-{k}")).await.unwrap();
+    }
+    base.flush().await.unwrap();
+    base.put("e", "base-e").await.unwrap();
+    drop(base);
+    let mut db = Db::open_overlay(&base_dir, &ov, DbOptions::default())
+        .await
+        .unwrap();
+    db.put("b", "ov-b").await.unwrap();
+    db.delete("c").await.unwrap();
+    db.put("f", "ov-f").await.unwrap();
+    db.flush().await.unwrap();
+    db.put("x", "1").await.unwrap();
+    db.flush().await.unwrap();
+    db.compact().await.unwrap();
+    assert_eq!(db.get("a").await.unwrap().unwrap(), "base-a");
+    assert_eq!(db.get("b").await.unwrap().unwrap(), "ov-b");
+    assert!(db.get("c").await.unwrap().is_none());
+    assert_eq!(db.get("e").await.unwrap().unwrap(), "base-e");
+    let mut it = db.scan("", None).await.unwrap();
+    let mut got = vec![];
+    while let Some(kv) = it.next().await.unwrap() {
+        got.push(format!("{}={}", kv.0, kv.1));
+    }
+    assert_eq!(
+        got,
+        ["a=base-a", "b=ov-b", "d=base-d", "e=base-e", "f=ov-f", "x=1"]
+    );
+    drop(db);
+    let base = Db::new(&base_dir).await.unwrap();
+    assert_eq!(base.get("b").await.unwrap().unwrap(), "base-b");
+    assert_eq!(base.get("c").await.unwrap().unwrap(), "base-c");
+}
+
+#[tokio::test]
+async fn iter_sorted() {
+    let mut m = Memtable::default();
+    m.put("b".into(), "2".into(), 2);
+    m.put("a".into(), "1".into(), 1);
+    m.delete("c".into(), 3);
+    let got: Vec<_> = m
+        .iter_sorted()
+        .unwrap()
+        .map(|(k, e)| (k, e.value.as_deref()))
+        .collect();
+    assert_eq!(got, [("a", Some("1")), ("b", Some("2")), ("c", None)]);
+    assert!(Memtable::skip_list().iter_sorted().is_none());
+    let dir = tmp("iter_sorted");
+    let mut db = Db::new(&dir).await.unwrap();
+    for i in (0..100).rev() {
+        db.put(&format!("k{i:03}"), "v").await.unwrap();
+    }
+    db.put_with_flags("k050", "f", 7).await.unwrap();
+    db.delete("k010").await.unwrap();
+    db.flush().await.unwrap();
+    assert!(db.verify().await.unwrap().problems.is_empty());
+    let sst = &db.ssts()[0];
+    let mut it = sst.iter().await.unwrap();
+    let mut prev = String::new();
+    let mut n = 0;
+    while let Some(p) = it.next().await.unwrap() {
+        assert!(p.key > prev);
+        prev = p.key;
+        n += 1;
+    }
+    assert_eq!(n, 100);
+    assert_eq!(db.get_flags("k050").await.unwrap(), Some(7));
+    assert!(db.get("k010").await.unwrap().is_none());
+}
+
+#[tokio::test]
+async fn empty_key() {
+    let dir = tmp("empty_key");
+    let mut db = Db::new(&dir).await.unwrap();
+    db.put("", "empty").await.unwrap();
+    db.put("a", "1").await.unwrap();
+    assert_eq!(db.get("").await.unwrap().unwrap(), "empty");
+    drop(db);
+    let mut db = Db::new(&dir).await.unwrap();
+    assert_eq!(db.get("").await.unwrap().unwrap(), "empty");
+    db.flush().await.unwrap();
+    for i in 0..40 {
+        db.put(&format!("k{i:02}"), "v").await.unwrap();
+    }
+    db.flush().await.unwrap();
+    db.compact().await.unwrap();
+    assert_eq!(db.get("").await.unwrap().unwrap(), "empty");
+    assert_eq!(db.first_key().await.unwrap().as_deref(), Some(""));
+    {
+        let mut it = db.scan("", Some("a")).await.unwrap();
+        assert_eq!(it.next().await.unwrap().unwrap().0, "");
+        assert!(it.next().await.unwrap().is_none());
+        let mut it = db.scan_prefix("").await.unwrap();
+        let mut n = 0;
+        while it.next().await.unwrap().is_some() {
+            n += 1;
+        }
+        assert_eq!(n, 42);
+        let mut it = db.scan("a", None).await.unwrap();
+        assert_eq!(it.next().await.unwrap().unwrap().0, "a");
+    }
+    db.delete("").await.unwrap();
+    assert!(db.get("").await.unwrap().is_none());
+    db.flush().await.unwrap();
+    db.compact().await.unwrap();
+    assert!(db.get("").await.unwrap().is_none());
+    assert_eq!(db.first_key().await.unwrap().as_deref(), Some("a"));
+    let r = db.verify().await.unwrap();
+    assert!(r.problems.is_empty(), "{:?}", r.problems);
+}
+
+#[tokio::test]
+async fn batch_sorted() {
+    let dir = tmp("batch_sorted");
+    let mut db = Db::new(&dir).await.unwrap();
+    db.put("k00005", "old").await.unwrap();
+    db.put("zzz", "mem").await.unwrap();
+    db.put_batch_sorted((0..5000).map(|i| (format!("k{i:05}"), format!("v{i}"))))
+        .await
+        .unwrap();
+    assert!(db.memtable_reader().is_none());
+    assert_eq!(db.get("k00005").await.unwrap().unwrap(), "v5");
+    assert_eq!(db.get("k04999").await.unwrap().unwrap(), "v4999");
+    assert_eq!(db.get("zzz").await.unwrap().unwrap(), "mem");
+    let n = db.ssts().len();
+    assert!(db
+        .put_batch_sorted(
+            ["b", "a"]
+                .into_iter()
+                .map(|k| (k.to_string(), "x".to_string()))
+        )
+        .await
+        .is_err());
+    assert!(db
+        .put_batch_sorted(
+            ["b", "b"]
+                .into_iter()
+                .map(|k| (k.to_string(), "x".to_string()))
+        )
+        .await
+        .is_err());
+    assert_eq!(db.ssts().len(), n);
+    db.put("k00005", "newer").await.unwrap();
+    drop(db);
+    let mut db = Db::new(&dir).await.unwrap();
+    assert_eq!(db.get("k00005").await.unwrap().unwrap(), "newer");
+    assert_eq!(db.range_count("k", "l").await.unwrap(), 5000);
+    db.compact().await.unwrap();
+    assert_eq!(db.get("k00005").await.unwrap().unwrap(), "newer");
+    assert!(db.verify().await.unwrap().problems.is_empty());
+}
+
+#[tokio::test]
+async fn get_raw() {
+    let dir = tmp("get_raw");
+    let mut db = Db::open(
+        &dir,
+        DbOptions {
+            value_separation_threshold: Some(4),
+            ..Default::default()
+        },
+    )
+    .await
+    .unwrap();
+    db.put("small", "ab").await.unwrap();
+    db.put("big", "abcdefgh").await.unwrap();
+    assert_eq!(db.get_raw("small").await.unwrap().unwrap(), b"ab"[..]);
+    db.flush().await.unwrap();
+    assert_eq!(db.get_raw("big").await.unwrap().unwrap(), b"abcdefgh"[..]);
+    assert!(db.get_raw("nope").await.unwrap().is_none());
+    db.delete("small").await.unwrap();
+    assert!(db.get_raw("small").await.unwrap().is_none());
+    // bytes that aren't utf-8, from the memtable, the log and an sst
+    let binary = b"\xff\xfe\x00\x80";
+    db.put_raw("binary", binary).await.unwrap();
+    assert_eq!(db.get_raw("binary").await.unwrap().unwrap(), &binary[..]);
+    assert!(db.get("binary").await.is_err());
+    drop(db);
+    let mut db = Db::new(&dir).await.unwrap();
+    assert_eq!(db.get_raw("binary").await.unwrap().unwrap(), &binary[..]);
+    db.flush().await.unwrap();
+    db.compact().await.unwrap();
+    assert_eq!(db.get_raw("binary").await.unwrap().unwrap(), &binary[..]);
+    assert_eq!(db.get_raw("big").await.unwrap().unwrap(), b"abcdefgh"[..]);
+    assert!(db.get("binary").await.is_err());
+    db.put("binary", "text").await.unwrap();
+    assert_eq!(db.get("binary").await.unwrap().unwrap(), "text");
+}
+
+async fn collect_scan(db: &Db, o: ScanOptions) -> Vec<(String, String)> {
+    let mut it = db.scan_options(o).await.unwrap();
+    let mut v = vec![];
+    while let Some(kv) = it.next().await.unwrap() {
+        v.push(kv);
+    }
+    v
+}
+
+#[tokio::test]
+async fn scan_options() {
+    let dir = tmp("scan_options");
+    let mut db = Db::new(&dir).await.unwrap();
+    for k in ["a", "b", "c", "d", "e"] {
+        db.put(k, &format!("v{k}")).await.unwrap();
+    }
+    db.flush().await.unwrap();
+    let snap = db.get_versions("e").await.unwrap()[0].0;
+    db.put("b", "new").await.unwrap();
+    db.flush().await.unwrap();
+    db.delete("c").await.unwrap();
+    db.put("f", "vf").await.unwrap();
+    let keys =
+        |v: Vec<(String, String)>| v.into_iter().map(|(k, _)| k).collect::<Vec<_>>().join("");
+    let o = |s: &str, e: &str| ScanOptions {
+        start: s.into(),
+        end: Some(e.into()),
+        ..Default::default()
+    };
+    assert_eq!(keys(collect_scan(&db, o("b", "e")).await), "bd");
+    assert_eq!(
+        keys(
+            collect_scan(
+                &db,
+                ScanOptions {
+                    start_exclusive: true,
+                    end_inclusive: true,
+                    ..o("b", "e")
+                }
+            )
+            .await
+        ),
+        "de"
+    );
+    assert_eq!(
+        keys(
+            collect_scan(
+                &db,
+                ScanOptions {
+                    limit: Some(2),
+                    ..Default::default()
+                }
+            )
+            .await
+        ),
+        "ab"
+    );
+    assert_eq!(
+        keys(
+            collect_scan(
+                &db,
+                ScanOptions {
+                    reverse: true,
+                    limit: Some(3),
+                    ..Default::default()
+                }
+            )
+            .await
+        ),
+        "fed"
+    );
+    let ko = collect_scan(
+        &db,
+        ScanOptions {
+            keys_only: true,
+            ..Default::default()
+        },
+    )
+    .await;
+    assert!(ko.iter().all(|(_, v)| v.is_empty()));
+    assert_eq!(ko.len(), 5);
+    let snapv = collect_scan(
+        &db,
+        ScanOptions {
+            snapshot: Some(snap),
+            ..Default::default()
+        },
+    )
+    .await;
+    assert_eq!(
+        snapv
+            .iter()
+            .map(|(k, v)| format!("{k}={v}"))
+            .collect::<Vec<_>>(),
+        ["a=va", "b=vb", "c=vc", "d=vd", "e=ve"]
+    );
+    assert_eq!(
+        keys(
+            collect_scan(
+                &db,
+                ScanOptions {
+                    limit: Some(0),
+                    ..Default::default()
+                }
+            )
+            .await
+        ),
+        ""
+    );
+}
+
+#[tokio::test]
+async fn memtable_versions() {
+    let mut single = Memtable::default();
+    let mut multi = Memtable::versioned();
+    for seq in 1..=100 {
+        single.put("k".into(), "value".into(), seq);
+        multi.put("k".into(), "value".into(), seq);
+    }
+    single.put("y".into(), "1".into(), 200);
+    assert_eq!(single.len(), 2);
+    let one = single.size_bytes();
+    single.put("k".into(), "value".into(), 300);
+    assert_eq!(single.size_bytes(), one);
+    assert_eq!(multi.len(), 100);
+    assert!(multi.size_bytes() > one * 40);
+    assert_eq!(multi.get_record("k").unwrap().seq, 100);
+    let dir = tmp("memtable_versions");
+    let mut db = Db::open(
+        &dir,
+        DbOptions {
+            memtable_versions: true,
+            versions_to_keep: 3,
+            ..Default::default()
+        },
+    )
+    .await
+    .unwrap();
+    for i in 0..5 {
+        db.put("k", &format!("v{i}")).await.unwrap();
+    }
+    assert_eq!(db.get("k").await.unwrap().unwrap(), "v4");
+    assert_eq!(db.get_versions("k").await.unwrap().len(), 5);
+    let mut it = db.scan("", None).await.unwrap();
+    assert_eq!(it.next().await.unwrap().unwrap().1, "v4");
+    assert!(it.next().await.unwrap().is_none());
+    drop(it);
+    db.flush().await.unwrap();
+    db.put("z", "1").await.unwrap();
+    db.flush().await.unwrap();
+    db.compact().await.unwrap();
+    let v: Vec<_> = db
+        .get_versions("k")
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|(_, v)| v)
+        .collect();
+    assert_eq!(v, ["v4", "v3", "v2"]);
+    assert!(db.verify().await.unwrap().problems.is_empty());
+}
+
+#[tokio::test]
+async fn export() {
+    let dir = tmp("export");
+    let mut db = Db::open(
+        &dir,
+        DbOptions {
+            value_separation_threshold: Some(8),
+            ..Default::default()
+        },
+    )
+    .await
+    .unwrap();
+    for i in 0..50 {
+        db.put(&format!("k{i:02}"), &format!("value-number-{i}"))
+            .await
+            .unwrap();
+    }
+    db.flush().await.unwrap();
+    db.put("k10", "short").await.unwrap();
+    db.delete("k11").await.unwrap();
+    let out = tmp("export-out");
+    std::fs::create_dir_all(&out).unwrap();
+    let sst = std::sync::Arc::new(
+        db.export_range("k10", "k20", out.join("a"), false)
+            .await
+            .unwrap(),
+    );
+    let mut it = sst.iter().await.unwrap();
+    let mut got = vec![];
+    while let Some(p) = it.next().await.unwrap() {
+        assert!(p.value_ref.is_none());
+        got.push((p.key, p.value.unwrap()));
+    }
+    assert_eq!(got.len(), 9);
+    assert_eq!(got[0], ("k10".to_string(), "short".to_string()));
+    assert_eq!(got[1], ("k12".to_string(), "value-number-12".to_string()));
+    let with = std::sync::Arc::new(
+        db.export_range("k10", "k20", out.join("b"), true)
+            .await
+            .unwrap(),
+    );
+    assert!(with
+        .get_record("k11")
+        .await
+        .unwrap()
+        .unwrap()
+        .value
+        .is_none());
+    let mut fresh = Db::new(tmp("export-f")).await.unwrap();
+    for (k, v) in &got {
+        fresh.put(k, v).await.unwrap();
+    }
+    assert_eq!(
+        fresh.range_count("k", "l").await.unwrap(),
+        db.range_count("k10", "k20").await.unwrap()
+    );
+}
+
+#[tokio::test]
+async fn ingest() {
+    let src = tmp("ingest-s");
+    let mut a = Db::new(&src).await.unwrap();
+    for i in 0..30 {
+        a.put(&format!("k{i:02}"), "old").await.unwrap();
+    }
+    a.flush().await.unwrap();
+    for i in 0..30 {
+        a.put(&format!("k{i:02}"), "new").await.unwrap();
+    }
+    a.delete("k05").await.unwrap();
+    let out = tmp("ingest-o");
+    std::fs::create_dir_all(&out).unwrap();
+    a.export_range("k00", "k10", out.join("x"), true)
+        .await
+        .unwrap();
+    // b holds older-seq data under the same keys (seqs 1..)
+    let dir = tmp("ingest");
+    let mut b = Db::new(&dir).await.unwrap();
+    for i in 0..20 {
+        b.put(&format!("k{i:02}"), "b").await.unwrap();
+    }
+    b.flush().await.unwrap();
+    b.put("zz", "mem").await.unwrap();
+    b.ingest_sst(out.join("x")).await.unwrap();
+    assert_eq!(b.get("k03").await.unwrap().unwrap(), "new");
+    assert!(b.get("k05").await.unwrap().is_none());
+    assert_eq!(b.get("k15").await.unwrap().unwrap(), "b");
+    assert_eq!(b.get("zz").await.unwrap().unwrap(), "mem");
+    b.put("k03", "later").await.unwrap();
+    assert_eq!(b.get("k03").await.unwrap().unwrap(), "later");
+    drop(b);
+    let mut b = Db::new(&dir).await.unwrap();
+    assert_eq!(b.get("k04").await.unwrap().unwrap(), "new");
+    assert!(b.verify().await.unwrap().problems.is_empty());
+    b.compact().await.unwrap();
+    assert_eq!(b.get("k03").await.unwrap().unwrap(), "later");
+    assert_eq!(b.get("k04").await.unwrap().unwrap(), "new");
+    assert!(b.get("k05").await.unwrap().is_none());
+    // interleaving seqs are rejected
+    let dir2 = tmp("ingest-i");
+    let mut c = Db::new(&dir2).await.unwrap();
+    for i in 0..5 {
+        c.put(&format!("k{i:02}"), "c").await.unwrap();
+    }
+    for i in 0..60 {
+        c.put(&format!("a{i:02}"), "c").await.unwrap();
+    }
+    for i in 5..10 {
+        c.put(&format!("k{i:02}"), "c").await.unwrap();
+    }
+    c.flush().await.unwrap();
+    assert!(c.ingest_sst(out.join("x")).await.is_err());
+    assert_eq!(c.get("k03").await.unwrap().unwrap(), "c");
+}
+
+#[tokio::test]
+async fn borrowed_write_path() {
+    let dir = tmp("borrowed_write_path");
+    let mut db = Db::new(&dir).await.unwrap();
+    db.put_with_flags("a", "x\n\"y\"", 3).await.unwrap();
+    db.delete("b").await.unwrap();
+    db.put("a", "z").await.unwrap();
+    let log = Log::open(dir.join("log")).await.unwrap();
+    let mut r = log.reader(CorruptionPolicy::Fail).await.unwrap();
+    let mut v = vec![];
+    while let Some(p) = r.next().await.unwrap() {
+        v.push(p);
+    }
+    assert_eq!(
+        v[0],
+        Put {
+            key: "a".into(),
+            value: Some("x\n\"y\"".into()),
+            seq: 1,
+            flags: 3,
+            value_ref: None,
+            merge: false,
+        }
+    );
+    assert_eq!(
+        v[1],
+        Put {
+            key: "b".into(),
+            value: None,
+            seq: 2,
+            flags: 0,
+            value_ref: None,
+            merge: false,
+        }
+    );
+    assert_eq!(v.len(), 3);
+    let mut m = Memtable::default();
+    m.put("a".into(), "1234".into(), 1);
+    let b = m.size_bytes();
+    m.put("a".into(), "5678".into(), 2);
+    assert_eq!(m.size_bytes(), b);
+    m.put("a".into(), "".into(), 3);
+    assert_eq!(m.size_bytes(), b - 4);
+    drop(db);
+    let db = Db::new(&dir).await.unwrap();
+    assert_eq!(db.get("a").await.unwrap().unwrap(), "z");
+}
+
+#[tokio::test]
+async fn max_open_files() {
+    let dir = tmp("max_open_files");
+    let opts = DbOptions {
+        max_open_files: Some(4),
+        compaction_trigger: 1000,
+        ..Default::default()
+    };
+    let mut db = Db::open(&dir, opts.clone()).await.unwrap();
+    for i in 0..30 {
+        db.put(&format!("k{:02}", i), &format!("v{}", i))
+            .await
+            .unwrap();
+        db.flush().await.unwrap();
+    }
+    assert_eq!(db.ssts().len(), 30);
+    for round in 0..3 {
+        for i in (0..30).rev() {
+            let k = format!("k{:02}", (i * 7 + round) % 30);
+            let v = db.get(&k).await.unwrap().unwrap();
+            assert_eq!(v, format!("v{}", (i * 7 + round) % 30));
+            assert!(db.file_cache().unwrap().len() <= 4);
+        }
+    }
+    assert_eq!(db.file_cache().unwrap().len(), 4);
+    db.put("k00", "new").await.unwrap();
+    db.flush().await.unwrap();
+    assert_eq!(db.get("k00").await.unwrap().unwrap(), "new");
+    db.major_compact().await.unwrap();
+    assert_eq!(db.get("k05").await.unwrap().unwrap(), "v5");
+    assert_eq!(db.get_versions("k00").await.unwrap()[0].1, "new");
+    assert_eq!(db.file_cache().unwrap().len(), 1);
+}
+
+#[tokio::test]
+async fn truncate() {
+    let dir = tmp("truncate");
+    let mut db = Db::new(&dir).await.unwrap();
+    for i in 0..50 {
+        db.put(&format!("k{}", i), "old").await.unwrap();
+        if i % 10 == 0 {
+            db.flush().await.unwrap();
+        }
+    }
+    db.truncate().await.unwrap();
+    assert!(db.ssts().is_empty());
+    assert_eq!(db.get("k1").await.unwrap(), None);
+    assert!(db
+        .scan("", None)
+        .await
+        .unwrap()
+        .next()
+        .await
+        .unwrap()
+        .is_none());
+    let files: Vec<_> = std::fs::read_dir(&dir)
+        .unwrap()
+        .map(|e| e.unwrap().file_name().into_string().unwrap())
+        .filter(|n| n.ends_with(".data") || n.ends_with(".index"))
+        .collect();
+    assert!(files.is_empty(), "{:?}", files);
+    db.put("new", "1").await.unwrap();
+    db.put("k1", "2").await.unwrap();
+    let seq = db.flush_all_and_checkpoint().await.unwrap();
+    assert_eq!(seq, 2);
+    db.put("k2", "3").await.unwrap();
+    drop(db);
+    let db = Db::new(&dir).await.unwrap();
+    let mut it = db.scan("", None).await.unwrap();
+    let mut v = vec![];
+    while let Some(kv) = it.next().await.unwrap() {
+        v.push(kv);
+    }
+    assert_eq!(
+        v,
+        vec![
+            ("k1".to_string(), "2".to_string()),
+            ("k2".to_string(), "3".to_string()),
+            ("new".to_string(), "1".to_string())
+        ]
+    );
+}
+
+#[derive(Debug, Default)]
+struct MockSink {
+    seen: std::sync::Mutex<Vec<Put>>,
+    fail: std::sync::atomic::AtomicBool,
+}
+
+#[async_trait::async_trait]
+impl lsm_based_db_in_rust::ReplicationSink for MockSink {
+    async fn replicate(&self, records: &[Put]) -> Result<(), DBError> {
+        if self.fail.load(std::sync::atomic::Ordering::SeqCst) {
+            return Err(DBError::InvalidInput("follower down".into()));
+        }
+        self.seen.lock().unwrap().extend_from_slice(records);
+        Ok(())
+    }
+}
+
+#[tokio::test]
+async fn replication() {
+    let dir = tmp("replication");
+    let sink = std::sync::Arc::new(MockSink::default());
+    let opts = DbOptions {
+        replication: Some(sink.clone()),
+        ..Default::default()
+    };
+    let mut db = Db::open(&dir, opts.clone()).await.unwrap();
+    db.put("a", "1").await.unwrap();
+    db.put("b", "2").await.unwrap();
+    db.delete("a").await.unwrap();
+    sink.fail.store(true, std::sync::atomic::Ordering::SeqCst);
+    assert!(db.put("c", "3").await.is_err());
+    assert!(db
+        .put_batch_sorted(vec![("x".to_string(), "9".to_string())].into_iter())
+        .await
+        .is_err());
+    assert_eq!(db.get("c").await.unwrap(), None);
+    assert_eq!(db.get("x").await.unwrap(), None);
+    sink.fail.store(false, std::sync::atomic::Ordering::SeqCst);
+    db.put("d", "4").await.unwrap();
+    db.put_batch_sorted(vec![("y".to_string(), "5".to_string())].into_iter())
+        .await
+        .unwrap();
+    let seen: Vec<_> = sink
+        .seen
+        .lock()
+        .unwrap()
+        .iter()
+        .map(|p| (p.key.clone(), p.value.clone(), p.seq))
+        .collect();
+    assert_eq!(
+        seen,
+        vec![
+            ("a".into(), Some("1".into()), 1),
+            ("b".into(), Some("2".into()), 2),
+            ("a".into(), None, 3),
+            ("d".into(), Some("4".into()), 4),
+            ("y".into(), Some("5".into()), 5),
+        ]
+    );
+    drop(db);
+    let db = Db::open(&dir, Default::default()).await.unwrap();
+    assert_eq!(db.get("c").await.unwrap(), None);
+    assert_eq!(db.get("x").await.unwrap(), None);
+    assert_eq!(db.get("d").await.unwrap().unwrap(), "4");
+    assert_eq!(db.get("y").await.unwrap().unwrap(), "5");
+}
+
+#[tokio::test]
+async fn async_flush() {
+    for async_flush in [false, true] {
+        let dir = tmp(&format!("async_flush{}", async_flush));
+        let opts = DbOptions {
+            flush_threshold: Some(2000),
+            async_flush,
+            compaction_trigger: 1000,
+            ..Default::default()
+        };
+        let mut db = Db::open(&dir, opts.clone()).await.unwrap();
+        let mut crossed = false;
+        for i in 0..200 {
+            let before = db.ssts().len();
+            db.put(&format!("k{:03}", i), &format!("v{}", i))
+                .await
+                .unwrap();
+            if db.ssts().len() == before && !crossed {
+                continue;
+            }
+            crossed = true;
+        }
+        assert!(!db.ssts().is_empty());
+        // every key readable, wherever it is now
+        for i in 0..200 {
+            assert_eq!(
+                db.get(&format!("k{:03}", i)).await.unwrap().unwrap(),
+                format!("v{}", i)
+            );
+        }
+        let mut it = db.scan("", None).await.unwrap();
+        let mut n = 0;
+        while it.next().await.unwrap().is_some() {
+            n += 1;
+        }
+        assert_eq!(n, 200);
+        drop(it);
+        drop(db);
+        let db = Db::open(&dir, opts.clone()).await.unwrap();
+        for i in 0..200 {
+            assert_eq!(
+                db.get(&format!("k{:03}", i)).await.unwrap().unwrap(),
+                format!("v{}", i)
+            );
+        }
+        assert!(!dir.join("log.flushing").exists());
+    }
+    // async: the put that crosses the threshold returns with no new sst yet
+    let dir = tmp("async_flush-b");
+    let opts = DbOptions {
+        flush_threshold: Some(500),
+        async_flush: true,
+        ..Default::default()
+    };
+    let mut db = Db::open(&dir, opts.clone()).await.unwrap();
+    let mut i = 0;
+    loop {
+        db.put(&format!("k{:03}", i), "x").await.unwrap();
+        i += 1;
+        if dir.join("log.flushing").exists() {
+            break;
+        }
+    }
+    assert!(db.ssts().is_empty());
+    assert_eq!(db.get("k000").await.unwrap().unwrap(), "x");
+    db.put("k000", "y").await.unwrap();
+    assert_eq!(db.get("k000").await.unwrap().unwrap(), "y");
+    db.flush().await.unwrap();
+    assert_eq!(db.ssts().len(), 2);
+    assert_eq!(db.get("k000").await.unwrap().unwrap(), "y");
+    assert!(!dir.join("log.flushing").exists());
+    // a crash mid-flush leaves the flushing log to replay
+    db.put("z", "1").await.unwrap();
+    let mut j = 0;
+    while !dir.join("log.flushing").exists() {
+        db.put(&format!("m{:03}", j), "x").await.unwrap();
+        j += 1;
+    }
+    std::fs::copy(dir.join("log.flushing"), dir.join("saved")).unwrap();
+    drop(db);
+    tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+    std::fs::rename(dir.join("saved"), dir.join("log.flushing")).unwrap();
+    let (db, report) = Db::open_with_report(&dir, opts).await.unwrap();
+    assert!(report.records_replayed > 0);
+    assert_eq!(db.get("z").await.unwrap().unwrap(), "1");
+    assert_eq!(db.get("m000").await.unwrap().unwrap(), "x");
+    assert!(!dir.join("log.flushing").exists());
+}
+
+#[tokio::test]
+async fn min_seq_in_use() {
+    let dir = tmp("min_seq_in_use");
+    let opts = DbOptions {
+        memtable_versions: true,
+        ..Default::default()
+    };
+    let mut db = Db::open(&dir, opts).await.unwrap();
+    db.put("a", "1").await.unwrap();
+    db.put("b", "1").await.unwrap();
+    let s1 = db.snapshot().await.unwrap();
+    assert_eq!(s1.seq(), 2);
+    db.put("a", "2").await.unwrap();
+    db.delete("b").await.unwrap();
+    let s2 = db.snapshot().await.unwrap();
+    db.put("a", "3").await.unwrap();
+    assert_eq!(db.min_seq_in_use(), 2);
+    db.flush().await.unwrap();
+    db.put("c", "1").await.unwrap();
+    db.flush().await.unwrap();
+    db.compact().await.unwrap();
+    let read = |seq| ScanOptions {
+        snapshot: Some(seq),
+        ..Default::default()
+    };
+    let collect = |mut it: DbIter| async move {
+        let mut v = vec![];
+        while let Some(kv) = it.next().await.unwrap() {
+            v.push(kv);
+        }
+        v
+    };
+    let v = collect(db.scan_options(read(s1.seq())).await.unwrap()).await;
+    assert_eq!(v, vec![("a".into(), "1".into()), ("b".into(), "1".into())]);
+    let v = collect(db.scan_options(read(s2.seq())).await.unwrap()).await;
+    assert_eq!(v, vec![("a".into(), "2".into())]);
+    drop(s1);
+    assert_eq!(db.min_seq_in_use(), s2.seq());
+    drop(s2);
+    assert_eq!(db.min_seq_in_use(), 6);
+    db.compact().await.unwrap();
+    db.put("d", "1").await.unwrap();
+    db.flush().await.unwrap();
+    db.compact().await.unwrap();
+    assert_eq!(db.get_versions("a").await.unwrap().len(), 1);
+    let v = collect(db.scan("", None).await.unwrap()).await;
+    assert_eq!(v.len(), 3);
+}
+
+#[tokio::test]
+async fn snapshot_default_memtable() {
+    let dir = tmp("snapshot_default_memtable");
+    let mut db = Db::new(&dir).await.unwrap();
+    db.put("a", "1").await.unwrap();
+    db.put("b", "1").await.unwrap();
+    let snapshot = db.snapshot().await.unwrap();
+    db.put("a", "2").await.unwrap();
+    db.delete("b").await.unwrap();
+    let read = |seq| ScanOptions {
+        snapshot: Some(seq),
+        ..Default::default()
+    };
+    let collect = |mut it: DbIter| async move {
+        let mut v = vec![];
+        while let Some(kv) = it.next().await.unwrap() {
+            v.push(kv);
+        }
+        v
+    };
+    let then = vec![("a".to_string(), "1".to_string()), ("b".into(), "1".into())];
+    let v = collect(db.scan_options(read(snapshot.seq())).await.unwrap()).await;
+    assert_eq!(v, then);
+    // through the flush and into the next memtable, still held back
+    db.flush().await.unwrap();
+    db.put("a", "3").await.unwrap();
+    db.flush().await.unwrap();
+    db.compact().await.unwrap();
+    let v = collect(db.scan_options(read(snapshot.seq())).await.unwrap()).await;
+    assert_eq!(v, then);
+    assert_eq!(db.get("a").await.unwrap().unwrap(), "3");
+    assert!(db.get("b").await.unwrap().is_none());
+
+    let dir = tmp("snapshot_default_memtable-skip");
+    let opts = DbOptions {
+        skip_list_memtable: true,
+        ..Default::default()
+    };
+    let mut db = Db::open(&dir, opts).await.unwrap();
+    assert!(matches!(
+        db.snapshot().await.unwrap_err(),
+        DBError::InvalidInput(_)
+    ));
+}
+
+#[tokio::test]
+async fn get_or() {
+    let dir = tmp("get_or");
+    let mut db = Db::new(&dir).await.unwrap();
+    db.put("a", "1").await.unwrap();
+    db.put("b", "2").await.unwrap();
+    db.flush().await.unwrap();
+    db.delete("b").await.unwrap();
+    assert_eq!(db.get_or("a", "d").await.unwrap(), "1");
+    assert_eq!(db.get_or("b", "d").await.unwrap(), "d");
+    assert_eq!(db.get_or("c", "d").await.unwrap(), "d");
+    let mut called = false;
+    assert_eq!(
+        db.get_or_else("a", || {
+            called = true;
+            "x".into()
+        })
+        .await
+        .unwrap(),
+        "1"
+    );
+    assert!(!called);
+    assert_eq!(db.get_or_else("c", || "y".into()).await.unwrap(), "y");
+    assert_eq!(db.get("c").await.unwrap(), None);
+}
+
+#[tokio::test]
+async fn bulk_get_random() {
+    for parallel in [false, true] {
+        let dir = tmp(&format!("bulk_get_random-{}", parallel));
+        let mut db = Db::open(
+            &dir,
+            DbOptions {
+                parallel_reads: parallel,
+                max_open_files: Some(2),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+        for round in 0..4 {
+            for i in 0..50 {
+                if (i + round) % 7 == 0 {
+                    db.delete(&format!("k{:02}", i)).await.unwrap();
+                } else {
+                    db.put(&format!("k{:02}", i), &format!("{}-{}", i, round))
+                        .await
+                        .unwrap();
+                }
+            }
+            db.flush().await.unwrap();
+        }
+        db.put("k03", "mem").await.unwrap();
+        let keys: Vec<String> = ["k40", "k03", "zz", "k00", "k40", "k13", "a"]
+            .iter()
+            .map(|s| s.to_string())
+            .chain((0..50).rev().map(|i| format!("k{:02}", i)))
+            .collect();
+        let bulk = db.bulk_get_random(&keys).await.unwrap();
+        for (key, value) in keys.iter().zip(&bulk) {
+            assert_eq!(&db.get(key).await.unwrap(), value, "{}", key);
+        }
+        assert_eq!(bulk[1].as_deref(), Some("mem"));
+    }
+}
+
+#[derive(Debug, Default)]
+struct SlowSink {
+    n: std::sync::atomic::AtomicUsize,
+}
+
+#[async_trait::async_trait]
+impl lsm_based_db_in_rust::ReplicationSink for SlowSink {
+    async fn replicate(&self, _records: &[Put]) -> Result<(), DBError> {
+        if self.n.fetch_add(1, std::sync::atomic::Ordering::SeqCst) % 10 == 9 {
+            tokio::time::sleep(std::time::Duration::from_millis(30)).await;
+        }
+        Ok(())
+    }
+}
+
+#[tokio::test]
+async fn latency() {
+    use std::time::Duration;
+    let h = Histogram::default();
+    assert_eq!(h.p50(), Duration::ZERO);
+    for i in 1..=1000u64 {
+        h.record(Duration::from_micros(i));
+    }
+    let p50 = h.p50().as_micros() as f64;
+    assert!((p50 - 500.0).abs() < 500.0 / 16.0 + 1.0, "{}", p50);
+    let p99 = h.p99().as_micros() as f64;
+    assert!((p99 - 990.0).abs() < 990.0 / 16.0 + 1.0, "{}", p99);
+    h.record(Duration::MAX);
+    assert!(h.percentile(100.0) >= Duration::from_secs(1 << 30));
+
+    let dir = tmp("latency");
+    let mut db = Db::open(
+        &dir,
+        DbOptions {
+            record_latency: true,
+            durability: Durability::NoSync,
+            replication: Some(std::sync::Arc::new(SlowSink::default())),
+            ..Default::default()
+        },
+    )
+    .await
+    .unwrap();
+    for i in 0..100 {
+        db.put(&format!("k{}", i), "v").await.unwrap();
+    }
+    db.get("k1").await.unwrap();
+    db.flush().await.unwrap();
+    db.put("k1", "w").await.unwrap();
+    db.flush().await.unwrap();
+    db.compact().await.unwrap();
+    let l = db.latency_histogram().unwrap();
+    assert_eq!(l.put.count(), 101);
+    assert_eq!(l.get.count(), 1);
+    assert_eq!(l.flush.count(), 2);
+    assert_eq!(l.compaction.count(), 1);
+    assert!(l.put.p50() < Duration::from_millis(10), "{:?}", l.put.p50());
+    assert!(
+        l.put.p95() >= Duration::from_millis(30),
+        "{:?}",
+        l.put.p95()
+    );
+    assert!(l.put.p99() < Duration::from_millis(60));
+    let db2 = Db::new(&tmp("latency-b")).await.unwrap();
+    assert!(db2.latency_histogram().is_none());
+}
+
+#[tokio::test]
+async fn namespaces() {
+    let dir = tmp("namespaces");
+    let mut db = Db::new(&dir).await.unwrap();
+    db.put_ns("users", "a", "u-a").await.unwrap();
+    db.put_ns("users", "b", "u-b").await.unwrap();
+    db.put_ns("sessions", "a", "s-a").await.unwrap();
+    db.put_ns("user", "a", "x").await.unwrap();
+    db.put("users", "plain").await.unwrap();
+    db.flush().await.unwrap();
+    db.put_ns("users", "c", "u-c").await.unwrap();
+    assert_eq!(db.get_ns("users", "a").await.unwrap().unwrap(), "u-a");
+    assert_eq!(db.get_ns("sessions", "a").await.unwrap().unwrap(), "s-a");
+    assert_eq!(db.get_ns("sessions", "b").await.unwrap(), None);
+    db.delete_ns("sessions", "a").await.unwrap();
+    assert_eq!(db.get_ns("users", "a").await.unwrap().unwrap(), "u-a");
+    db.put_ns("sessions", "z", "s-z").await.unwrap();
+    assert_eq!(db.drop_namespace("users").await.unwrap(), 3);
+    for k in ["a", "b", "c"] {
+        assert_eq!(db.get_ns("users", k).await.unwrap(), None);
+    }
+    assert_eq!(db.get_ns("sessions", "z").await.unwrap().unwrap(), "s-z");
+    assert_eq!(db.get_ns("user", "a").await.unwrap().unwrap(), "x");
+    assert_eq!(db.get("users").await.unwrap().unwrap(), "plain");
+    assert!(db.put_ns("", "a", "v").await.is_err());
+    assert!(db.get_ns("a\0b", "a").await.is_err());
+}
+
+#[tokio::test]
+async fn paranoid() {
+    for paranoid in [false, true] {
+        let dir = tmp(&format!("paranoid-{}", paranoid));
+        let opts = DbOptions {
+            paranoid_checks: paranoid,
+            ..Default::default()
+        };
+        let mut db = Db::open(&dir, opts.clone()).await.unwrap();
+        for k in ["a", "b", "c", "d"] {
+            db.put(k, "v").await.unwrap();
+        }
+        db.flush().await.unwrap();
+        db.put("bb", "v").await.unwrap();
+        db.flush().await.unwrap();
+        let path = db.ssts()[1].data_path().to_path_buf();
+        drop(db);
+        let data = std::fs::read_to_string(&path).unwrap();
+        let mut lines: Vec<&str> = data.lines().collect();
+        lines.swap(0, 2);
+        std::fs::write(&path, lines.join("\n") + "\n").unwrap();
+        let mut db = Db::open(&dir, opts).await.unwrap();
+        let mut iter = db.scan("", None).await.unwrap();
+        let mut result = Ok(());
+        let mut keys = vec![];
+        loop {
+            match iter.next().await {
+                Ok(Some((k, _))) => keys.push(k),
+                Ok(None) => break,
+                Err(e) => {
+                    result = Err(e);
+                    break;
+                }
+            }
+        }
+        drop(iter);
+        if paranoid {
+            let err = result.unwrap_err();
+            assert!(err.to_string().contains("out of order"), "{}", err);
+            let err = db.compact().await.unwrap_err();
+            assert!(err.to_string().contains("out of order"), "{}", err);
+            assert_eq!(db.ssts().len(), 2);
+        } else {
+            result.unwrap();
+            assert_ne!(keys, vec!["a", "b", "bb", "c", "d"]);
+        }
+    }
+    let dir = tmp("paranoid-w");
+    std::fs::create_dir_all(&dir).unwrap();
+    let mut w = SstWriter::create(dir.join("s")).await.unwrap();
+    w.add(&Put {
+        key: "b".into(),
+        value: None,
+        seq: 1,
+        flags: 0,
+        value_ref: None,
+        merge: false,
+    })
+    .await
+    .unwrap();
+    assert!(w
+        .add(&Put {
+            key: "a".into(),
+            value: None,
+            seq: 2,
+            flags: 0,
+            value_ref: None,
+            merge: false,
+        })
+        .await
+        .is_err());
+}
+
+#[tokio::test]
+async fn estimate_prefix() {
+    for prefix_bloom in [None, Some(2)] {
+        let dir = tmp(&format!("estimate_prefix-{:?}", prefix_bloom));
+        let mut db = Db::open(
+            &dir,
+            DbOptions {
+                prefix_bloom_len: prefix_bloom,
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+        let counts = [("a:", 1000u64), ("b:", 50), ("c:", 5), ("d:", 300)];
+        for (p, n) in counts {
+            for i in 0..n {
+                db.put(&format!("{}{:05}", p, i), "v").await.unwrap();
+            }
+        }
+        db.flush().await.unwrap();
+        for i in 0..20 {
+            db.put(&format!("e:{}", i), "v").await.unwrap();
+        }
+        db.flush().await.unwrap();
+        for i in 0..7 {
+            db.put(&format!("f:{}", i), "v").await.unwrap();
+        }
+        for (p, n) in counts
+            .into_iter()
+            .chain([("e:", 20), ("f:", 7), ("z:", 0), ("", 1382)])
+        {
+            let est = db.estimate_keys_with_prefix(p).await.unwrap();
+            let tol = (n / 10).max(16);
+            assert!(est.abs_diff(n) <= tol, "{} {} {}", p, est, n);
+        }
+        assert_eq!(db.estimate_keys_with_prefix("c:").await.unwrap(), 5);
+        assert_eq!(db.estimate_keys_with_prefix("f:").await.unwrap(), 7);
+    }
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn batch_consistent() {
+    use std::sync::Arc;
+    let dir = tmp("batch_consistent");
+    let mut db = Db::open(
+        &dir,
+        DbOptions {
+            durability: Durability::NoSync,
+            ..Default::default()
+        },
+    )
+    .await
+    .unwrap();
+    for k in ["a", "b", "c"] {
+        db.put(k, "0").await.unwrap();
+    }
+    let db = Arc::new(tokio::sync::RwLock::new(db));
+    let writer = {
+        let db = db.clone();
+        tokio::spawn(async move {
+            for i in 1..300 {
+                for k in ["a", "b", "c"] {
+                    db.write().await.put(k, &i.to_string()).await.unwrap();
+                    tokio::task::yield_now().await;
+                }
+                if i % 100 == 0 {
+                    db.write().await.flush().await.unwrap();
+                }
+            }
+        })
+    };
+    let mut batches = 0;
+    while !writer.is_finished() {
+        let values = db
+            .read()
+            .await
+            .get_batch_consistent(&["a", "b", "c", "missing"])
+            .await
+            .unwrap();
+        let a: u32 = values[0].as_ref().unwrap().parse().unwrap();
+        let b: u32 = values[1].as_ref().unwrap().parse().unwrap();
+        let c: u32 = values[2].as_ref().unwrap().parse().unwrap();
+        // written a then b then c, so a coherent view has a >= b >= c >= a - 1
+        assert!(a >= b && b >= c && c + 1 >= a, "{} {} {}", a, b, c);
+        assert_eq!(values[3], None);
+        batches += 1;
+        tokio::task::yield_now().await;
+    }
+    writer.await.unwrap();
+    assert!(batches > 0);
+}
+
+#[tokio::test]
+async fn get_closest() {
+    let dir = tmp("get_closest");
+    let mut db = Db::open(&dir, DbOptions::default()).await.unwrap();
+    for i in 0..3000 {
+        db.put(&format!("k{:05}", i), &format!("v{}", i))
+            .await
+            .unwrap();
+    }
+    db.flush().await.unwrap();
+    for i in 100..2900 {
+        db.delete(&format!("k{:05}", i)).await.unwrap();
+    }
+    db.flush().await.unwrap();
+    db.put("k01500", "live").await.unwrap();
+    db.delete("k02950").await.unwrap();
+    let c = |k: &str| k.to_string();
+    assert_eq!(
+        db.get_closest("k02000", Seek::AtOrBefore).await.unwrap(),
+        Some((c("k01500"), c("live")))
+    );
+    assert_eq!(
+        db.get_closest("k01400", Seek::AtOrBefore).await.unwrap(),
+        Some((c("k00099"), c("v99")))
+    );
+    assert_eq!(
+        db.get_closest("k01400", Seek::AtOrAfter).await.unwrap(),
+        Some((c("k01500"), c("live")))
+    );
+    assert_eq!(
+        db.get_closest("k02950", Seek::AtOrBefore).await.unwrap(),
+        Some((c("k02949"), c("v2949")))
+    );
+    assert_eq!(
+        db.get_closest("k02950", Seek::AtOrAfter).await.unwrap(),
+        Some((c("k02951"), c("v2951")))
+    );
+    assert_eq!(
+        db.get_closest("k00050", Seek::AtOrBefore).await.unwrap(),
+        Some((c("k00050"), c("v50")))
+    );
+    assert_eq!(db.get_closest("a", Seek::AtOrBefore).await.unwrap(), None);
+    assert_eq!(db.get_closest("z", Seek::AtOrAfter).await.unwrap(), None);
+    assert_eq!(
+        db.get_closest("z", Seek::AtOrBefore).await.unwrap(),
+        Some((c("k02999"), c("v2999")))
+    );
+}
+
+#[tokio::test]
+async fn write_with_options() {
+    let dir = tmp("write_with_options");
+    let options = DbOptions {
+        durability: Durability::None,
+        ..DbOptions::default()
+    };
+    let mut db = Db::open(&dir, options.clone()).await.unwrap();
+    db.put("lost", "1").await.unwrap();
+    let mut batch = WriteBatch::new();
+    batch.put("a", "1").put("b", "2").delete("lost");
+    db.write_with_options(&batch, WriteOptions { sync: Some(true) })
+        .await
+        .unwrap();
+    let mut batch = WriteBatch::new();
+    batch.put("c", "3");
+    db.write_with_options(&batch, WriteOptions::default())
+        .await
+        .unwrap();
+    db.write_with_options(&WriteBatch::new(), WriteOptions { sync: Some(true) })
+        .await
+        .unwrap();
+    assert_eq!(db.get("a").await.unwrap().as_deref(), Some("1"));
+    assert_eq!(db.get("c").await.unwrap().as_deref(), Some("3"));
+    let a = db.get_versions("a").await.unwrap()[0].0;
+    let b = db.get_versions("b").await.unwrap()[0].0;
+    assert_eq!(b, a + 1);
+    drop(db);
+    let db = Db::open(&dir, options).await.unwrap();
+    assert_eq!(db.get("a").await.unwrap().as_deref(), Some("1"));
+    assert_eq!(db.get("b").await.unwrap().as_deref(), Some("2"));
+    assert_eq!(db.get("c").await.unwrap(), None);
+    assert_eq!(db.get("lost").await.unwrap(), None);
+}
+
+#[tokio::test]
+async fn flush_order() {
+    use std::sync::{Arc, Mutex};
+    let dir = tmp("flush_order");
+    let flushed = Arc::new(Mutex::new(Vec::new()));
+    let on_flush: SstCallback = {
+        let flushed = flushed.clone();
+        Arc::new(move |sst: &Sst| flushed.lock().unwrap().push(sst.data_path().to_path_buf()))
+    };
+    let options = DbOptions {
+        async_flush: true,
+        flush_threshold: Some(300),
+        callbacks: Callbacks {
+            on_flush: Some(on_flush),
+            ..Callbacks::default()
+        },
+        ..DbOptions::default()
+    };
+    let mut db = Db::open(&dir, options).await.unwrap();
+    for round in 0..4 {
+        for i in 0..10 {
+            let key = format!("k{}", i);
+            let value = format!("v{}-{}", round, i);
+            db.put(&key, &value).await.unwrap();
+            for j in 0..=i {
+                let expect = format!("v{}-{}", round, j);
+                assert_eq!(db.get(&format!("k{}", j)).await.unwrap(), Some(expect));
+            }
+        }
+    }
+    db.flush().await.unwrap();
+    let ssts = db.ssts();
+    assert!(ssts.len() >= 3, "{}", ssts.len());
+    for pair in ssts.windows(2) {
+        let (newer, older) = (pair[0].meta().await.unwrap(), pair[1].meta().await.unwrap());
+        assert!(newer.min_seq() > older.max_seq());
+    }
+    let order: Vec<_> = ssts
+        .iter()
+        .rev()
+        .map(|sst| sst.data_path().to_path_buf())
+        .collect();
+    assert_eq!(order, *flushed.lock().unwrap());
+    for i in 0..10 {
+        assert_eq!(
+            db.get(&format!("k{}", i)).await.unwrap(),
+            Some(format!("v3-{}", i))
+        );
+    }
+}
+
+#[tokio::test]
+async fn sharded() {
+    use std::sync::Arc;
+    let dir = tmp("sharded");
+    let db = Arc::new(
+        ShardedDb::open(&dir, 4, DbOptions::default())
+            .await
+            .unwrap(),
+    );
+    let mut tasks = Vec::new();
+    for t in 0..4 {
+        let db = db.clone();
+        tasks.push(tokio::spawn(async move {
+            for i in 0..50 {
+                db.put(&format!("k{:03}", t * 50 + i), &format!("v{}", i))
+                    .await
+                    .unwrap();
+            }
+        }));
+    }
+    for task in tasks {
+        task.await.unwrap();
+    }
+    let mut used = std::collections::HashSet::new();
+    for i in 0..200 {
+        used.insert(db.shard_of(&format!("k{:03}", i)));
+    }
+    assert_eq!(used.len(), 4);
+    db.delete("k007").await.unwrap();
+    db.put("k008", "new").await.unwrap();
+    assert_eq!(db.get("k007").await.unwrap(), None);
+    assert_eq!(db.get("k008").await.unwrap().as_deref(), Some("new"));
+    assert_eq!(db.get("k100").await.unwrap().as_deref(), Some("v0"));
+    db.flush().await.unwrap();
+    let mut iter = db.scan("", None).await.unwrap();
+    let mut keys = Vec::new();
+    while let Some((key, _)) = iter.next().await.unwrap() {
+        keys.push(key);
+    }
+    let expected: Vec<_> = (0..200)
+        .filter(|i| *i != 7)
+        .map(|i| format!("k{:03}", i))
+        .collect();
+    assert_eq!(keys, expected);
+    let mut iter = db.scan("k050", Some("k060")).await.unwrap();
+    let mut n = 0;
+    while iter.next().await.unwrap().is_some() {
+        n += 1;
+    }
+    assert_eq!(n, 10);
+    drop(iter);
+    drop(db);
+    assert!(ShardedDb::open(&dir, 3, DbOptions::default())
+        .await
+        .is_err());
+    let db = ShardedDb::open(&dir, 4, DbOptions::default())
+        .await
+        .unwrap();
+    assert_eq!(db.get("k008").await.unwrap().as_deref(), Some("new"));
+    assert_eq!(db.get("k199").await.unwrap().as_deref(), Some("v49"));
+    let hash = KeyHasher::XxHash64.hash(b"k008");
+    assert_eq!(db.shard_of("k008"), (hash % 4) as usize);
+    drop(db);
+    // written whole, with the hash it was split by
+    assert!(!dir.join("shards.tmp").exists());
+    let err = ShardedDb::open_with_hasher(&dir, 4, KeyHasher::Fnv1a, DbOptions::default()).await;
+    assert!(matches!(err, Err(DBError::InvalidInput(_))));
+
+    // a bare count was split by fnv-1a
+    let dir = tmp("sharded-count");
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("shards"), "2").unwrap();
+    let err = ShardedDb::open(&dir, 2, DbOptions::default()).await;
+    assert!(matches!(err, Err(DBError::InvalidInput(_))));
+    let db = ShardedDb::open_with_hasher(&dir, 2, KeyHasher::Fnv1a, DbOptions::default())
+        .await
+        .unwrap();
+    db.put("a", "1").await.unwrap();
+    assert_eq!(db.get("a").await.unwrap().as_deref(), Some("1"));
+}
+
+#[tokio::test]
+async fn subscribe() {
+    use futures::StreamExt;
+    let dir = tmp("subscribe");
+    let options = DbOptions {
+        change_feed_capacity: 4,
+        ..DbOptions::default()
+    };
+    let mut db = Db::open(&dir, options).await.unwrap();
+    db.put("before", "0").await.unwrap();
+    let mut feed = Box::pin(db.subscribe());
+    db.put("a", "1").await.unwrap();
+    db.delete("b").await.unwrap();
+    let mut batch = WriteBatch::new();
+    batch.put("c", "3").delete("a");
+    db.write_with_options(&batch, WriteOptions::default())
+        .await
+        .unwrap();
+    let mut changes = Vec::new();
+    for _ in 0..4 {
+        changes.push(feed.next().await.unwrap());
+    }
+    let seq0 = match &changes[0] {
+        Change::Put { seq, .. } => *seq,
+        other => panic!("{:?}", other),
+    };
+    assert_eq!(
+        changes,
+        vec![
+            Change::Put {
+                key: "a".into(),
+                value: "1".into(),
+                seq: seq0
+            },
+            Change::Delete {
+                key: "b".into(),
+                seq: seq0 + 1
+            },
+            Change::Put {
+                key: "c".into(),
+                value: "3".into(),
+                seq: seq0 + 2
+            },
+            Change::Delete {
+                key: "a".into(),
+                seq: seq0 + 3
+            },
+        ]
+    );
+    let mut late = Box::pin(db.subscribe());
+    db.put_batch_sorted(vec![("x".to_string(), "9".to_string())].into_iter())
+        .await
+        .unwrap();
+    let expect = Change::Put {
+        key: "x".into(),
+        value: "9".into(),
+        seq: seq0 + 4,
+    };
+    assert_eq!(late.next().await.unwrap(), expect);
+    assert_eq!(feed.next().await.unwrap(), expect);
+    // a slow reader
+    for i in 0..7 {
+        db.put(&format!("s{}", i), "v").await.unwrap();
+    }
+    assert_eq!(feed.next().await.unwrap(), Change::Lagged { missed: 3 });
+    for i in 3..7 {
+        match feed.next().await.unwrap() {
+            Change::Put { key, seq, .. } => {
+                assert_eq!(key, format!("s{}", i));
+                assert_eq!(seq, seq0 + 5 + i);
+            }
+            other => panic!("{:?}", other),
+        }
+    }
+    drop(db);
+    assert_eq!(feed.next().await, None);
+}
+
+#[derive(Debug)]
+struct Append;
+
+impl MergeOperator for Append {
+    fn merge(&self, _key: &str, existing: Option<&str>, operand: &str) -> String {
+        match existing {
+            Some(existing) => format!("{},{}", existing, operand),
+            None => operand.to_string(),
+        }
+    }
+}
+
+async fn merged_gets(db: &Db) -> Vec<(String, String)> {
+    let mut v = vec![];
+    for key in ["a", "b", "z"] {
+        v.push((key.to_string(), db.get(key).await.unwrap().unwrap()));
+    }
+    v
+}
+
+async fn merged_scan(db: &Db) -> Vec<(String, String)> {
+    let mut iter = db.scan("", None).await.unwrap();
+    let mut v = vec![];
+    while let Some(kv) = iter.next().await.unwrap() {
+        v.push(kv);
+    }
+    v
+}
+
+#[tokio::test]
+async fn merge_operands() {
+    let dir = tmp("merge_operands");
+    let opts = DbOptions {
+        merge_operator: Some(std::sync::Arc::new(Append)),
+        ..Default::default()
+    };
+    let mut db = Db::open(&dir, opts.clone()).await.unwrap();
+    db.put("a", "base").await.unwrap();
+    db.put("z", "other").await.unwrap();
+    db.flush().await.unwrap();
+    db.merge("a", "1").await.unwrap();
+    db.merge("b", "1").await.unwrap();
+    db.flush().await.unwrap();
+    db.merge("a", "2").await.unwrap();
+    db.flush().await.unwrap();
+    db.merge("a", "3").await.unwrap();
+    db.merge("b", "2").await.unwrap();
+    // deleted, the operands after start over from nothing
+    db.delete("z").await.unwrap();
+    db.merge("z", "x").await.unwrap();
+    let folded = vec![
+        ("a".to_string(), "base,1,2,3".to_string()),
+        ("b".into(), "1,2".into()),
+        ("z".into(), "x".into()),
+    ];
+    assert_eq!(merged_gets(&db).await, folded);
+    assert_eq!(merged_scan(&db).await, folded);
+    // the memtable's operands come back from the log
+    drop(db);
+    let mut db = Db::open(&dir, opts.clone()).await.unwrap();
+    assert_eq!(merged_gets(&db).await, folded);
+    db.flush().await.unwrap();
+    db.compact().await.unwrap();
+    assert_eq!(merged_gets(&db).await, folded);
+    assert_eq!(merged_scan(&db).await, folded);
+    assert_eq!(db.drop_shadowed_ssts().await.unwrap(), 0);
+    db.put("a", "new").await.unwrap();
+    assert_eq!(db.get("a").await.unwrap().unwrap(), "new");
+    drop(db);
+
+    let dir = tmp("merge_operands-none");
+    let mut db = Db::new(&dir).await.unwrap();
+    assert!(matches!(
+        db.merge("a", "1").await.unwrap_err(),
+        DBError::InvalidInput(_)
+    ));
+}
+
+#[tokio::test]
+async fn flush_queue() {
+    use std::sync::{Arc, Mutex};
+    let dir = tmp("flush_queue");
+    let flushed = Arc::new(Mutex::new(Vec::new()));
+    let on_flush: SstCallback = {
+        let flushed = flushed.clone();
+        Arc::new(move |sst: &Sst| flushed.lock().unwrap().push(sst.data_path().to_path_buf()))
+    };
+    let options = DbOptions {
+        async_flush: true,
+        max_flushing_memtables: 2,
+        flush_threshold: Some(1000),
+        durability: Durability::None,
+        callbacks: Callbacks {
+            on_flush: Some(on_flush),
+            ..Callbacks::default()
+        },
+        ..DbOptions::default()
+    };
+    let mut db = Db::open(&dir, options).await.unwrap();
+    // the same keys every round, so each queued memtable holds an older
+    // version of what the ones after it hold
+    let mut round = 0;
+    let mut most = 0;
+    while most < 2 && round < 100 {
+        for i in 0..40 {
+            db.put(&format!("k{:02}", i), &format!("v{}-{}", round, i))
+                .await
+                .unwrap();
+            most = most.max(db.flushing_memtables());
+            assert!(db.flushing_memtables() <= 2);
+            for j in 0..40 {
+                let expect = match j <= i {
+                    true => Some(format!("v{}-{}", round, j)),
+                    false if round > 0 => Some(format!("v{}-{}", round - 1, j)),
+                    false => None,
+                };
+                assert_eq!(db.get(&format!("k{:02}", j)).await.unwrap(), expect);
+            }
+        }
+        round += 1;
+    }
+    assert_eq!(most, 2);
+    db.flush().await.unwrap();
+    assert_eq!(db.flushing_memtables(), 0);
+    let ssts = db.ssts();
+    for pair in ssts.windows(2) {
+        let (newer, older) = (pair[0].meta().await.unwrap(), pair[1].meta().await.unwrap());
+        assert!(newer.min_seq() > older.max_seq());
+    }
+    let order: Vec<_> = ssts
+        .iter()
+        .rev()
+        .map(|sst| sst.data_path().to_path_buf())
+        .collect();
+    assert_eq!(order, *flushed.lock().unwrap());
+    drop(db);
+
+    // interrupted, the queued memtables are replayed oldest first
+    let dir = tmp("flush_queue-replay");
+    std::fs::create_dir_all(&dir).unwrap();
+    for (seq, name) in [
+        (1, "log.flushing"),
+        (2, "log.flushing.2"),
+        (3, "log.flushing.10"),
+    ] {
+        let mut log = Log::open(dir.join(name)).await.unwrap();
+        let put = Put {
+            key: "k".into(),
+            value: Some(format!("v{}", seq)),
+            seq,
+            flags: 0,
+            value_ref: None,
+            merge: false,
+        };
+        log.append_batch(&[put]).await.unwrap();
+    }
+    let db = Db::new(&dir).await.unwrap();
+    assert_eq!(db.get("k").await.unwrap(), Some("v3".into()));
+    assert!(!dir.join("log.flushing").exists());
+    assert!(!dir.join("log.flushing.10").exists());
+}
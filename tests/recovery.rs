@@ -0,0 +1,802 @@
+mod common;
+
+use std::path::PathBuf;
+
+use common::tmp;
+use lsm_based_db_in_rust::*;
+
+#[tokio::test]
+async fn legacy_log() {
+    let dir = tmp("legacy_log");
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::copy("db/log", dir.join("log")).unwrap();
+    let db = Db::new(&dir).await.unwrap();
+    assert_eq!(db.get("foo").await.unwrap(), Some("goo".into()));
+}
+
+#[tokio::test]
+async fn append_batch() {
+    let dir = tmp("append_batch");
+    std::fs::create_dir_all(&dir).unwrap();
+    let mut log = lsm_based_db_in_rust::Log::open(dir.join("log"))
+        .await
+        .unwrap();
+    let recs: Vec<Put> = (0..1000)
+        .map(|i| Put {
+            key: format!("k{i:04}"),
+            value: Some(i.to_string()),
+            seq: i,
+            flags: 0,
+            value_ref: None,
+            merge: false,
+        })
+        .collect();
+    log.append_batch(&recs).await.unwrap();
+    let m = log.hydrate(CorruptionPolicy::Fail).await.unwrap();
+    assert_eq!(m.len(), 1000);
+}
+
+#[tokio::test]
+async fn bootstrap() {
+    let dir = tmp("bootstrap");
+    let mut db = Db::new(&dir).await.unwrap();
+    for f in ["manifest", "seq", "log"] {
+        assert!(dir.join(f).exists(), "{f}");
+    }
+    assert_eq!(std::fs::read_to_string(dir.join("seq")).unwrap(), "0");
+    let m = lsm_based_db_in_rust::Manifest::load(&dir)
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(m.ssts.is_empty());
+    db.put("a", "1").await.unwrap();
+    db.put("b", "1").await.unwrap();
+    db.flush().await.unwrap();
+    db.delete("a").await.unwrap();
+    db.flush().await.unwrap();
+    db.compact().await.unwrap();
+    assert!(db.verify().await.unwrap().is_clean());
+    let m = lsm_based_db_in_rust::Manifest::load(&dir)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(m.ssts.len(), 1);
+    assert_eq!(std::fs::read_to_string(dir.join("seq")).unwrap(), "4");
+    drop(db);
+    let mut db = Db::new(&dir).await.unwrap();
+    assert_eq!(db.get("b").await.unwrap().unwrap(), "1");
+    db.put("c", "1").await.unwrap();
+    db.flush().await.unwrap();
+    assert_eq!(std::fs::read_to_string(dir.join("seq")).unwrap(), "5");
+    let cp = tmp("bootstrap-cp");
+    db.put("d", "1").await.unwrap();
+    db.checkpoint(&cp).await.unwrap();
+    let db2 = Db::new(&cp).await.unwrap();
+    assert_eq!(db2.get("d").await.unwrap().unwrap(), "1");
+    assert!(db2.verify().await.unwrap().is_clean());
+}
+
+#[tokio::test]
+async fn poison() {
+    let dir = tmp("poison");
+    let mut db = Db::new(&dir).await.unwrap();
+    db.put("a", "1").await.unwrap();
+    std::fs::create_dir(dir.join("manifest.log")).unwrap();
+    assert!(matches!(db.flush().await, Err(DBError::Io(_))));
+    assert!(db.is_poisoned());
+    assert!(matches!(db.get("a").await, Err(DBError::Poisoned)));
+    assert!(matches!(db.put("b", "1").await, Err(DBError::Poisoned)));
+    drop(db);
+    std::fs::remove_dir(dir.join("manifest.log")).unwrap();
+    let db = Db::new(&dir).await.unwrap();
+    assert_eq!(db.get("a").await.unwrap().unwrap(), "1");
+}
+
+#[tokio::test]
+async fn missing() {
+    for skip in [false, true] {
+        let dir = tmp(&format!("missing{skip}"));
+        let mut db = Db::open(
+            &dir,
+            DbOptions {
+                skip_missing: skip,
+                lazy_sst_open: true,
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+        db.put("a", "old").await.unwrap();
+        db.put("b", "1").await.unwrap();
+        db.flush().await.unwrap();
+        db.put("a", "new").await.unwrap();
+        db.flush().await.unwrap();
+        drop(db);
+        let db = Db::open(
+            &dir,
+            DbOptions {
+                skip_missing: skip,
+                lazy_sst_open: true,
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+        std::fs::remove_file(db.ssts()[0].data_path()).unwrap();
+        std::fs::remove_file(db.ssts()[0].index_path()).unwrap();
+        let r = db.get("a").await;
+        if skip {
+            assert_eq!(r.unwrap().unwrap(), "old");
+            let mut it = db.scan("", None).await.unwrap();
+            assert_eq!(it.next().await.unwrap().unwrap().1, "old");
+        } else {
+            assert!(matches!(r, Err(DBError::MissingSst(_))), "{r:?}");
+            assert!(matches!(
+                db.scan("", None).await,
+                Err(DBError::MissingSst(_))
+            ));
+        }
+        assert_eq!(db.get("b").await.unwrap_or(None).is_some(), skip);
+    }
+}
+
+#[tokio::test]
+async fn bounded_replay() {
+    let dir = tmp("bounded_replay");
+    let mut db = Db::new(&dir).await.unwrap();
+    for i in 0..3000 {
+        db.put(&format!("k{:05}", i % 2000), &format!("{i}"))
+            .await
+            .unwrap();
+    }
+    db.delete("k00007").await.unwrap();
+    drop(db);
+    let db = Db::open(
+        &dir,
+        DbOptions {
+            memtable_budget: Some(20_000),
+            ..Default::default()
+        },
+    )
+    .await
+    .unwrap();
+    assert!(db.ssts().len() > 3, "{}", db.ssts().len());
+    assert_eq!(std::fs::metadata(dir.join("log")).unwrap().len(), 0);
+    assert_eq!(db.get("k00005").await.unwrap().unwrap(), "2005");
+    assert_eq!(db.get("k01999").await.unwrap().unwrap(), "1999");
+    assert!(db.get("k00007").await.unwrap().is_none());
+    let mut db = db;
+    db.put("new", "1").await.unwrap();
+    drop(db);
+    let db = Db::new(&dir).await.unwrap();
+    assert_eq!(db.get("k00005").await.unwrap().unwrap(), "2005");
+    assert_eq!(db.get("new").await.unwrap().unwrap(), "1");
+    assert!(db.get("k00007").await.unwrap().is_none());
+}
+
+async fn corrupt_db(name: &str, middle: bool) -> PathBuf {
+    let dir = tmp(name);
+    let mut db = Db::new(&dir).await.unwrap();
+    db.put("a", "1").await.unwrap();
+    db.put("b", "2").await.unwrap();
+    drop(db);
+    let log = dir.join("log");
+    let mut data = std::fs::read(&log).unwrap();
+    if middle {
+        data.extend_from_slice(b"{garbage\n");
+        data.extend_from_slice(b"{\"key\":\"c\",\"value\":\"3\",\"seq\":9}\n");
+    } else {
+        data.extend_from_slice(b"{\"key\":\"c\",\"val");
+    }
+    std::fs::write(&log, data).unwrap();
+    dir
+}
+
+#[tokio::test]
+async fn corruption() {
+    for middle in [true, false] {
+        let dir = corrupt_db(&format!("corruption-s{middle}"), middle).await;
+        let log = std::fs::read(dir.join("log")).unwrap();
+        if middle {
+            // stopping would lose c, the log is left alone
+            let err = Db::new(&dir).await.err().unwrap();
+            assert!(matches!(err, DBError::CorruptLog(_, _)), "{err}");
+            assert_eq!(std::fs::read(dir.join("log")).unwrap(), log);
+        } else {
+            let mut db = Db::new(&dir).await.unwrap();
+            assert_eq!(db.get("b").await.unwrap().unwrap(), "2");
+            assert!(db.get("c").await.unwrap().is_none());
+            db.put("d", "4").await.unwrap();
+            drop(db);
+            let db = Db::new(&dir).await.unwrap();
+            assert_eq!(db.get("d").await.unwrap().unwrap(), "4");
+        }
+
+        let dir = corrupt_db(&format!("corruption-k{middle}"), middle).await;
+        let opts = DbOptions {
+            log_corruption: CorruptionPolicy::SkipCorrupt,
+            ..Default::default()
+        };
+        let db = Db::open(&dir, opts).await.unwrap();
+        assert_eq!(db.get("b").await.unwrap().unwrap(), "2");
+        assert_eq!(db.get("c").await.unwrap().is_some(), middle);
+
+        let dir = corrupt_db(&format!("corruption-f{middle}"), middle).await;
+        let opts = DbOptions {
+            log_corruption: CorruptionPolicy::Fail,
+            ..Default::default()
+        };
+        assert!(Db::open(&dir, opts).await.is_err());
+    }
+}
+
+#[tokio::test]
+async fn unterminated_tail() {
+    let dir = tmp("unterminated_tail");
+    let mut db = Db::new(&dir).await.unwrap();
+    db.put("a", "1").await.unwrap();
+    db.put("b", "2").await.unwrap();
+    drop(db);
+    // b went in whole but its newline didn't
+    let log = dir.join("log");
+    let data = std::fs::read(&log).unwrap();
+    std::fs::write(&log, &data[..data.len() - 1]).unwrap();
+    let mut db = Db::new(&dir).await.unwrap();
+    assert_eq!(db.get("a").await.unwrap().unwrap(), "1");
+    assert!(db.get("b").await.unwrap().is_none());
+    db.put("c", "3").await.unwrap();
+    drop(db);
+    let db = Db::new(&dir).await.unwrap();
+    assert_eq!(db.get("a").await.unwrap().unwrap(), "1");
+    assert_eq!(db.get("c").await.unwrap().unwrap(), "3");
+}
+
+#[tokio::test]
+async fn migrate() {
+    let dir = tmp("migrate");
+    std::fs::create_dir_all(&dir).unwrap();
+    // two legacy ssts, no seqs, no checksum, no manifest
+    for (id, val) in [(0, "old"), (1, "new")] {
+        let data = (0..40).map(|i| Put {
+            key: format!("k{i:02}"),
+            value: if i == 3 && id == 1 {
+                None
+            } else {
+                Some(format!("{val}\nline{i}"))
+            },
+            seq: 0,
+            flags: 0,
+            value_ref: None,
+            merge: false,
+        });
+        let sst = Sst::construct(dir.join(format!("{id:08}")), data)
+            .await
+            .unwrap();
+        let mut meta: serde_json::Value =
+            serde_json::from_slice(&std::fs::read(sst.index_path()).unwrap()).unwrap();
+        for f in ["checksum", "max_key", "level", "prefix_bloom"] {
+            meta.as_object_mut().unwrap().remove(f);
+        }
+        std::fs::write(sst.index_path(), meta.to_string()).unwrap();
+        let d = std::fs::read_to_string(sst.data_path())
+            .unwrap()
+            .replace(",\"seq\":0", "");
+        std::fs::write(sst.data_path(), d).unwrap();
+    }
+    std::fs::write(dir.join("log"), "{\"key\":\"k05\",\"value\":\"logged\"}\n").unwrap();
+    let db = Db::migrate(&dir, DbOptions::default()).await.unwrap();
+    assert_eq!(db.ssts().len(), 1);
+    let meta = db.ssts()[0].meta().await.unwrap();
+    assert!(meta.checksum().is_some() && meta.max_key() == Some("k39"));
+    assert!(db.verify().await.unwrap().problems.is_empty());
+    assert_eq!(db.get("k00").await.unwrap().unwrap(), "new\nline0");
+    assert!(db.get("k03").await.unwrap().is_none());
+    assert_eq!(db.get("k05").await.unwrap().unwrap(), "logged");
+    drop(db);
+    let db = Db::new(&dir).await.unwrap();
+    assert_eq!(db.range_count("k", "l").await.unwrap(), 39);
+}
+
+#[tokio::test]
+async fn clock_regression() {
+    use std::time::{Duration, SystemTime};
+    let base = SystemTime::UNIX_EPOCH + Duration::from_secs(1000);
+    let clock = std::sync::Arc::new(MockClock::new(base));
+    let m = MonotonicClock::new(clock.clone());
+    clock.set(base - Duration::from_secs(500));
+    assert_eq!(m.now(), base);
+    clock.set(base + Duration::from_secs(1));
+    assert_eq!(m.now(), base + Duration::from_secs(1));
+    let clock = std::sync::Arc::new(MockClock::new(base));
+    let opts = DbOptions {
+        clock: Some(clock.clone()),
+        idle_flush: Some(Duration::from_secs(10)),
+        ..Default::default()
+    };
+    let mut db = Db::open(tmp("clock_regression"), opts).await.unwrap();
+    db.put("a", "1").await.unwrap();
+    clock.set(base - Duration::from_secs(100));
+    db.put("b", "1").await.unwrap();
+    // back in time, then forward less than the interval past the last write
+    clock.set(base + Duration::from_secs(5));
+    assert!(!db.flush_if_idle().await.unwrap());
+    clock.set(base + Duration::from_secs(11));
+    assert!(db.flush_if_idle().await.unwrap());
+    // an sst flushed after the clock went back isn't dated before one
+    // flushed ahead of it
+    clock.set(base);
+    db.put("c", "1").await.unwrap();
+    db.flush().await.unwrap();
+    let newest = db.ssts()[0].meta().await.unwrap().created().unwrap();
+    let older = db.ssts()[1].meta().await.unwrap().created().unwrap();
+    assert_eq!(older, base + Duration::from_secs(11));
+    assert!(newest >= older);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn parallel_open() {
+    let dir = tmp("parallel_open");
+    let mut db = Db::new(&dir).await.unwrap();
+    for round in 0..30 {
+        db.put("same", &format!("r{round}")).await.unwrap();
+        db.put(&format!("k{round:02}"), "v").await.unwrap();
+        db.flush().await.unwrap();
+    }
+    drop(db);
+    for p in [0, 1, 4, 64] {
+        let db = Db::open(
+            &dir,
+            DbOptions {
+                open_parallelism: p,
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+        assert_eq!(db.ssts().len(), 30);
+        assert!(db.ssts().iter().all(|s| s.is_loaded()));
+        assert_eq!(db.get("same").await.unwrap().unwrap(), "r29");
+        assert_eq!(db.range_count("k", "l").await.unwrap(), 30);
+    }
+}
+
+#[tokio::test]
+async fn sync_wal() {
+    let dir = tmp("sync_wal");
+    let mut db = Db::open(
+        &dir,
+        DbOptions {
+            durability: Durability::NoSync,
+            ..Default::default()
+        },
+    )
+    .await
+    .unwrap();
+    for i in 0..100 {
+        db.put(&format!("k{i}"), "v").await.unwrap();
+    }
+    db.sync_wal().await.unwrap();
+    drop(db);
+    let db = Db::new(&dir).await.unwrap();
+    assert_eq!(db.range_count("k", "l").await.unwrap(), 100);
+}
+
+#[tokio::test]
+async fn flush_all_and_checkpoint() {
+    let dir = tmp("flush_all_and_checkpoint");
+    let mut db = Db::new(&dir).await.unwrap();
+    for i in 0..20 {
+        db.put(&format!("k{:02}", i), &format!("v{}", i))
+            .await
+            .unwrap();
+    }
+    db.delete("k03").await.unwrap();
+    let seq = db.flush_all_and_checkpoint().await.unwrap();
+    assert_eq!(seq, db.flush_all_and_checkpoint().await.unwrap());
+    let before: Vec<_> = {
+        let mut it = db.scan("", None).await.unwrap();
+        let mut v = vec![];
+        while let Some(kv) = it.next().await.unwrap() {
+            v.push(kv);
+        }
+        v
+    };
+    db.put("k00", "new").await.unwrap();
+    db.delete("k05").await.unwrap();
+    db.put("zz", "1").await.unwrap();
+    db.flush().await.unwrap();
+    db.major_compact().await.unwrap();
+    let old = Db::open_at_seq(&dir, seq, Default::default())
+        .await
+        .unwrap();
+    let mut it = old.scan("", None).await.unwrap();
+    let mut v = vec![];
+    while let Some(kv) = it.next().await.unwrap() {
+        v.push(kv);
+    }
+    assert_eq!(v, before);
+    assert_eq!(v.len(), 19);
+    assert!(Db::open_at_seq(&dir, seq + 100, Default::default())
+        .await
+        .is_err());
+    assert_eq!(db.get("k00").await.unwrap().unwrap(), "new");
+}
+
+#[tokio::test]
+async fn recovery_report() {
+    let dir = tmp("recovery_report");
+    let (mut db, report) = Db::open_with_report(&dir, Default::default())
+        .await
+        .unwrap();
+    assert!(report.bootstrapped && report.is_clean());
+    db.put("a", "1").await.unwrap();
+    db.flush().await.unwrap();
+    db.put("b", "2").await.unwrap();
+    db.put("c", "3").await.unwrap();
+    drop(db);
+    std::fs::write(dir.join("9.data.tmp"), "junk").unwrap();
+    let mut f = std::fs::OpenOptions::new()
+        .append(true)
+        .open(dir.join("log"))
+        .unwrap();
+    std::io::Write::write_all(&mut f, b"{\"key\":\"d\",\"val").unwrap();
+    drop(f);
+    let (db, report) = Db::open_with_report(&dir, Default::default())
+        .await
+        .unwrap();
+    assert!(!report.bootstrapped);
+    assert_eq!(report.ssts_opened, 1);
+    assert_eq!(report.temp_files_removed, vec![dir.join("9.data.tmp")]);
+    assert_eq!(report.records_replayed, 2);
+    assert_eq!(report.log_bytes_truncated, 15);
+    assert_eq!(report.corrupt_records_skipped, 0);
+    assert!(!report.is_clean());
+    assert!(!dir.join("9.data.tmp").exists());
+    assert_eq!(db.get("c").await.unwrap().unwrap(), "3");
+    drop(db);
+    let (_db, report) = Db::open_with_report(&dir, Default::default())
+        .await
+        .unwrap();
+    assert!(report.is_clean());
+    assert_eq!(report.records_replayed, 2);
+}
+
+#[tokio::test]
+async fn durability_none() {
+    let dir = tmp("durability_none");
+    let opts = || DbOptions {
+        durability: Durability::None,
+        ..Default::default()
+    };
+    let mut db = Db::open(&dir, opts()).await.unwrap();
+    db.put("a", "1").await.unwrap();
+    db.put("b", "2").await.unwrap();
+    db.delete("b").await.unwrap();
+    assert_eq!(std::fs::metadata(dir.join("log")).unwrap().len(), 0);
+    assert_eq!(db.get("a").await.unwrap().unwrap(), "1");
+    assert_eq!(db.get("b").await.unwrap(), None);
+    db.flush().await.unwrap();
+    db.put("c", "3").await.unwrap();
+    drop(db);
+    let db = Db::open(&dir, opts()).await.unwrap();
+    assert_eq!(db.get("a").await.unwrap().unwrap(), "1");
+    assert_eq!(db.get("c").await.unwrap(), None);
+}
+
+#[tokio::test]
+async fn wal_dir() {
+    let dir = tmp("wal_dir");
+    let wal = tmp("wal_dir-wal");
+    let opts = || DbOptions {
+        wal_dir: Some(wal.clone()),
+        flush_threshold: Some(4096),
+        async_flush: true,
+        ..Default::default()
+    };
+    let mut db = Db::open(&dir, opts()).await.unwrap();
+    for i in 0..200 {
+        db.put(&format!("k{:03}", i), &"v".repeat(50))
+            .await
+            .unwrap();
+    }
+    db.put("last", "1").await.unwrap();
+    assert!(wal.join("log").exists());
+    assert!(!dir.join("log").exists());
+    assert!(!db.ssts().is_empty());
+    for sst in db.ssts() {
+        assert!(sst.data_path().starts_with(&dir));
+    }
+    drop(db);
+    let db = Db::open(&dir, opts()).await.unwrap();
+    assert_eq!(db.get("last").await.unwrap().unwrap(), "1");
+    assert_eq!(db.get("k000").await.unwrap().unwrap(), "v".repeat(50));
+    assert!(!dir.join("log").exists());
+}
+
+#[tokio::test]
+async fn reopen() {
+    let dir = tmp("reopen");
+    let mut db = Db::open(
+        &dir,
+        DbOptions {
+            durability: Durability::NoSync,
+            ..Default::default()
+        },
+    )
+    .await
+    .unwrap();
+    for i in 0..500 {
+        db.put(&format!("k{:03}", i), &format!("v{}", i))
+            .await
+            .unwrap();
+    }
+    db.flush().await.unwrap();
+    for i in 0..500 {
+        if i % 3 == 0 {
+            db.delete(&format!("k{:03}", i)).await.unwrap();
+        }
+    }
+    db.put("new", "1").await.unwrap();
+    let keys: Vec<String> = (0..500)
+        .map(|i| format!("k{:03}", i))
+        .chain(["new".to_string()])
+        .collect();
+    let before = db.bulk_get_random(&keys).await.unwrap();
+    let db = db.reopen().await.unwrap();
+    assert_eq!(std::fs::metadata(dir.join("log")).unwrap().len(), 0);
+    assert!(db.ssts().len() >= 2);
+    assert_eq!(db.bulk_get_random(&keys).await.unwrap(), before);
+    assert_eq!(db.get("k000").await.unwrap(), None);
+    let mut db = db.reopen().await.unwrap();
+    db.put("after", "x").await.unwrap();
+    assert_eq!(db.get("after").await.unwrap().unwrap(), "x");
+}
+
+#[tokio::test]
+async fn seq_redundancy() {
+    for corrupt in [false, true] {
+        let dir = tmp(&format!("seq_redundancy-{}", corrupt));
+        let mut db = Db::new(&dir).await.unwrap();
+        for i in 0..50 {
+            db.put(&format!("k{}", i), "v").await.unwrap();
+        }
+        for i in 0..50 {
+            db.delete(&format!("k{}", i)).await.unwrap();
+        }
+        db.flush().await.unwrap();
+        db.major_compact().await.unwrap();
+        let high = db.snapshot().await.unwrap().seq();
+        assert_eq!(high, 100);
+        drop(db);
+        if corrupt {
+            std::fs::write(dir.join("seq"), "garbage").unwrap();
+        } else {
+            std::fs::remove_file(dir.join("seq")).unwrap();
+        }
+        let (mut db, report) = Db::open_with_report(&dir, DbOptions::default())
+            .await
+            .unwrap();
+        assert!(report.seq_file_rebuilt);
+        assert!(!report.is_clean());
+        assert!(db.snapshot().await.unwrap().seq() >= high);
+        db.put("new", "2").await.unwrap();
+        let versions = db.get_versions("new").await.unwrap();
+        assert!(versions[0].0 > high, "{:?}", versions);
+        assert_eq!(
+            std::fs::read_to_string(dir.join("seq"))
+                .unwrap()
+                .trim()
+                .parse::<u64>()
+                .unwrap(),
+            high + 1
+        );
+        drop(db);
+        let (_db, report) = Db::open_with_report(&dir, DbOptions::default())
+            .await
+            .unwrap();
+        assert!(!report.seq_file_rebuilt);
+    }
+}
+
+#[tokio::test]
+async fn log_read_your_writes() {
+    let dir = tmp("log_read_your_writes");
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("log");
+    let mut log = Log::open(&path).await.unwrap();
+    for i in 0..300u64 {
+        let key = format!("k{}", i);
+        let put = Put {
+            key: key.clone(),
+            value: Some("x".repeat(2000)),
+            seq: i,
+            flags: 0,
+            value_ref: None,
+            merge: false,
+        };
+        let durability = if i % 2 == 0 {
+            Durability::NoSync
+        } else {
+            Durability::Sync
+        };
+        log.append_batch_with(std::slice::from_ref(&put), durability)
+            .await
+            .unwrap();
+        assert!(
+            matches!(log.get(&key).await.unwrap(), Lookup::Present(_)),
+            "{}",
+            i
+        );
+    }
+    let memtable = log.hydrate(CorruptionPolicy::default()).await.unwrap();
+    assert_eq!(memtable.len(), 300);
+    // a half written last record is not there yet
+    use std::io::Write;
+    let mut file = std::fs::OpenOptions::new()
+        .append(true)
+        .open(&path)
+        .unwrap();
+    file.write_all(b"{\"key\":\"k1\",\"val").unwrap();
+    assert!(matches!(log.get("k1").await.unwrap(), Lookup::Present(_)));
+    assert_eq!(log.get("nope").await.unwrap(), Lookup::Absent);
+}
+
+#[tokio::test]
+async fn record_iter() {
+    use std::io::Write;
+    let dir = tmp("record_iter");
+    std::fs::create_dir_all(&dir).unwrap();
+    let put = |i: u64| Put {
+        key: format!("k{}", i),
+        value: if i.is_multiple_of(3) {
+            None
+        } else {
+            Some(format!("v{}", i))
+        },
+        seq: i,
+        flags: 0,
+        value_ref: None,
+        merge: false,
+    };
+    let mut paths = Vec::new();
+    for seg in 0..3u64 {
+        let path = dir.join(format!("seg{}", seg));
+        let mut log = Log::open(&path).await.unwrap();
+        let records: Vec<_> = (seg * 10..seg * 10 + 10).map(put).collect();
+        log.append_batch(&records).await.unwrap();
+        paths.push(path);
+    }
+    let read_all = |mut iter: RecordIter| async move {
+        let mut seen = Vec::new();
+        while let Some(put) = iter.next().await.unwrap() {
+            seen.push(put);
+        }
+        (seen, iter)
+    };
+    let valid = |range: std::ops::Range<u64>| range.map(|i| (put(i), true)).collect::<Vec<_>>();
+    let (seen, iter) = read_all(RecordIter::new(paths.clone(), CorruptionPolicy::default())).await;
+    assert_eq!(seen, valid(0..30));
+    assert_eq!(iter.stopped_at(), None);
+    // a torn tail on the middle segment
+    let len = std::fs::metadata(&paths[1]).unwrap().len();
+    let mut file = std::fs::OpenOptions::new()
+        .append(true)
+        .open(&paths[1])
+        .unwrap();
+    file.write_all(b"{\"key\":\"torn\",\"va").unwrap();
+    drop(file);
+    let (seen, iter) = read_all(RecordIter::new(paths.clone(), CorruptionPolicy::default())).await;
+    assert_eq!(seen, valid(0..20));
+    assert_eq!(iter.stopped_at(), Some((paths[1].as_path(), len)));
+    // torn, but not at the end of the last segment
+    assert!(!iter.stopped_at_tail());
+    let (seen, iter) = read_all(RecordIter::new(
+        paths.clone(),
+        CorruptionPolicy::SkipCorrupt,
+    ))
+    .await;
+    assert_eq!(seen.len(), 30);
+    assert_eq!(iter.skipped(), 1);
+    assert_eq!(iter.stopped_at(), None);
+    let mut iter = RecordIter::new(paths.clone(), CorruptionPolicy::Fail);
+    let mut result = Ok(Some((put(0), true)));
+    while let Ok(Some(_)) = result {
+        result = iter.next().await;
+    }
+    assert!(result.is_err());
+    // hydrate reads the same way
+    let log = Log::open(&paths[1]).await.unwrap();
+    assert_eq!(
+        log.hydrate(CorruptionPolicy::default())
+            .await
+            .unwrap()
+            .len(),
+        10
+    );
+
+    // the middle segment mended and the last one torn instead
+    std::fs::OpenOptions::new()
+        .write(true)
+        .open(&paths[1])
+        .unwrap()
+        .set_len(len)
+        .unwrap();
+    let mut file = std::fs::OpenOptions::new()
+        .append(true)
+        .open(&paths[2])
+        .unwrap();
+    file.write_all(b"{\"key\":\"torn\",\"va").unwrap();
+    drop(file);
+    let (seen, iter) = read_all(RecordIter::new(paths.clone(), CorruptionPolicy::default())).await;
+    assert_eq!(seen, valid(0..30));
+    assert!(iter.stopped_at_tail());
+    // a damaged record that still parses comes back flagged
+    let seg0 = std::fs::read_to_string(&paths[0]).unwrap();
+    std::fs::write(&paths[0], seg0.replacen("\"v1\"", "\"w1\"", 1)).unwrap();
+    let (seen, _) = read_all(RecordIter::new(paths.clone(), CorruptionPolicy::default())).await;
+    assert_eq!(seen.len(), 30);
+    assert_eq!(seen[1].0.value.as_deref(), Some("w1"));
+    assert_eq!(
+        seen.iter().map(|(_, valid)| *valid).collect::<Vec<_>>(),
+        (0..30).map(|i| i != 1).collect::<Vec<_>>()
+    );
+    // and stops `next_valid` like one that doesn't parse
+    let mut iter = RecordIter::new(paths.clone(), CorruptionPolicy::default());
+    assert_eq!(iter.next_valid().await.unwrap(), Some(put(0)));
+    assert_eq!(iter.position(), Some((paths[0].as_path(), 0)));
+    assert_eq!(iter.next_valid().await.unwrap(), None);
+    let at = seg0.find("{\"key\":\"k1\"").unwrap() as u64;
+    assert_eq!(iter.stopped_at(), Some((paths[0].as_path(), at)));
+    assert!(!iter.stopped_at_tail());
+    let mut iter = RecordIter::new(paths.clone(), CorruptionPolicy::SkipCorrupt);
+    let mut intact = 0;
+    while iter.next_valid().await.unwrap().is_some() {
+        intact += 1;
+    }
+    assert_eq!((intact, iter.skipped()), (29, 2));
+    let log = Log::open(&paths[0]).await.unwrap();
+    assert_eq!(log.verify().await.unwrap().len(), 1);
+}
+
+#[tokio::test]
+async fn corrupt_flushing_log() {
+    let dir = tmp("corrupt_flushing_log");
+    std::fs::create_dir_all(&dir).unwrap();
+    let put = |key: &str, value: &str, seq| Put {
+        key: key.into(),
+        value: Some(value.into()),
+        seq,
+        flags: 0,
+        value_ref: None,
+        merge: false,
+    };
+    // an async flush the crash interrupted, and the log after it
+    let mut flushing = Log::open(dir.join("log.flushing")).await.unwrap();
+    flushing
+        .append_batch(&[put("a", "1", 1), put("b", "1", 2)])
+        .await
+        .unwrap();
+    let mut log = Log::open(dir.join("log")).await.unwrap();
+    log.append_batch(&[put("c", "1", 3)]).await.unwrap();
+    let data = std::fs::read_to_string(dir.join("log.flushing")).unwrap();
+    std::fs::write(
+        dir.join("log.flushing"),
+        data.replacen("\"value\":\"1\"", "\"value\":\"9\"", 1),
+    )
+    .unwrap();
+    // the log can't be replayed past what was lost before it
+    let err = Db::new(&dir).await.err().unwrap();
+    match err {
+        DBError::CorruptLog(path, 0) => assert_eq!(path, dir.join("log.flushing")),
+        err => panic!("{err}"),
+    }
+    let opts = DbOptions {
+        log_corruption: CorruptionPolicy::SkipCorrupt,
+        ..Default::default()
+    };
+    let db = Db::open(&dir, opts).await.unwrap();
+    assert!(db.get("a").await.unwrap().is_none());
+    assert_eq!(db.get("b").await.unwrap().unwrap(), "1");
+    assert_eq!(db.get("c").await.unwrap().unwrap(), "1");
+    assert!(!dir.join("log.flushing").exists());
+}
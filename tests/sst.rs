@@ -0,0 +1,682 @@
+mod common;
+
+use common::tmp;
+use lsm_based_db_in_rust::*;
+
+#[tokio::test]
+async fn lazy_open() {
+    let dir = tmp("lazy_open");
+    let mut db = Db::new(&dir).await.unwrap();
+    for i in 0..3 {
+        db.put(&format!("k{}", i), "v").await.unwrap();
+        db.flush().await.unwrap();
+    }
+    drop(db);
+    let db = Db::open(
+        &dir,
+        DbOptions {
+            lazy_sst_open: true,
+            ..Default::default()
+        },
+    )
+    .await
+    .unwrap();
+    assert!(db.ssts().iter().all(|s| !s.is_loaded()));
+    assert_eq!(db.get("k2").await.unwrap(), Some("v".into()));
+    let loaded: Vec<_> = db.ssts().iter().map(|s| s.is_loaded()).collect();
+    assert_eq!(loaded, vec![true, false, false]);
+    let mut db = db;
+    db.put("z", "1").await.unwrap();
+    db.flush().await.unwrap();
+    let max = db.ssts()[0].meta().await.unwrap().max_seq();
+    assert_eq!(max, 4);
+    let db = Db::new(&dir).await.unwrap();
+    assert!(db.ssts().iter().all(|s| s.is_loaded()));
+}
+
+#[tokio::test]
+async fn construct_checks_order() {
+    let dir = tmp("construct_checks_order");
+    std::fs::create_dir_all(&dir).unwrap();
+    let p = |k: &str, v: &str| Put {
+        key: k.into(),
+        value: Some(v.into()),
+        seq: 1,
+        flags: 0,
+        value_ref: None,
+        merge: false,
+    };
+    let err = Sst::construct(dir.join("a"), vec![p("b", "1"), p("a", "2")].into_iter()).await;
+    assert!(matches!(err, Err(DBError::InvalidInput(_))));
+    assert!(!dir.join("a.data").exists());
+    let err = Sst::construct(dir.join("a"), vec![p("a", "1"), p("a", "2")].into_iter()).await;
+    assert!(matches!(err, Err(DBError::InvalidInput(_))));
+    let sst = Sst::construct_lenient(
+        dir.join("b"),
+        vec![p("b", "1"), p("a", "2"), p("b", "3")].into_iter(),
+    )
+    .await
+    .unwrap();
+    assert_eq!(sst.get("b").await.unwrap(), Lookup::Present("3".into()));
+    assert_eq!(sst.get("a").await.unwrap(), Lookup::Present("2".into()));
+}
+
+#[tokio::test]
+async fn verify_file_checksum() {
+    let dir = tmp("verify_file_checksum");
+    let mut db = Db::new(&dir).await.unwrap();
+    for i in 0..100 {
+        db.put(&format!("k{:03}", i), "v").await.unwrap();
+    }
+    db.flush().await.unwrap();
+    let sst = db.ssts()[0].clone();
+    assert!(sst.verify_file().await.unwrap());
+    let mut bytes = std::fs::read(sst.data_path()).unwrap();
+    bytes[500] ^= 1;
+    std::fs::write(sst.data_path(), bytes).unwrap();
+    assert!(!sst.verify_file().await.unwrap());
+    assert!(!db.verify().await.unwrap().is_clean());
+}
+
+#[tokio::test]
+async fn prefix_bloom() {
+    let dir = tmp("prefix_bloom");
+    let mut db = Db::open(
+        &dir,
+        DbOptions {
+            prefix_bloom_len: Some(4),
+            ..Default::default()
+        },
+    )
+    .await
+    .unwrap();
+    for p in ["aaaa", "bbbb", "cccc"] {
+        for i in 0..50 {
+            db.put(&format!("{p}:{i:02}"), p).await.unwrap();
+        }
+        db.flush().await.unwrap();
+    }
+    db.put("bbbb:99", "mem").await.unwrap();
+    let metas: Vec<bool> = {
+        let mut v = vec![];
+        for s in db.ssts() {
+            v.push(s.meta().await.unwrap().may_contain_prefix("bbbb:"));
+        }
+        v
+    };
+    assert_eq!(metas.iter().filter(|b| **b).count(), 1);
+    let mut it = db.scan_prefix("bbbb").await.unwrap();
+    let mut n = 0;
+    while let Some((k, _)) = it.next().await.unwrap() {
+        assert!(k.starts_with("bbbb"));
+        n += 1;
+    }
+    assert_eq!(n, 51);
+    let mut it = db.scan_prefix("b").await.unwrap();
+    let mut n = 0;
+    while it.next().await.unwrap().is_some() {
+        n += 1;
+    }
+    assert_eq!(n, 51);
+    let mut it = db.scan_prefix("dddd").await.unwrap();
+    assert!(it.next().await.unwrap().is_none());
+    db.compact().await.unwrap();
+    assert!(!db.ssts()[0]
+        .meta()
+        .await
+        .unwrap()
+        .may_contain_prefix("zzzz"));
+}
+
+#[tokio::test]
+async fn write_buffer() {
+    let dir = tmp("write_buffer");
+    std::fs::create_dir_all(&dir).unwrap();
+    let data: Vec<Put> = (0..1000)
+        .map(|i| Put {
+            key: format!("k{i:05}"),
+            value: Some("v".repeat(i % 50)),
+            seq: i as u64 + 1,
+            flags: 0,
+            value_ref: None,
+            merge: false,
+        })
+        .collect();
+    let a = Sst::construct_with(
+        SstWriter::create_with_buffer(dir.join("a"), 0)
+            .await
+            .unwrap(),
+        data.clone().into_iter(),
+    )
+    .await
+    .unwrap();
+    let b = Sst::construct(dir.join("b"), data.into_iter())
+        .await
+        .unwrap();
+    assert_eq!(
+        std::fs::read(a.data_path()).unwrap(),
+        std::fs::read(b.data_path()).unwrap()
+    );
+    let b = std::sync::Arc::new(b);
+    assert_eq!(b.get_record("k00999").await.unwrap().unwrap().seq, 1000);
+}
+
+#[tokio::test]
+async fn hasher() {
+    assert_eq!(KeyHasher::XxHash64.hash(b""), 0xEF46DB3751D8E999);
+    assert_eq!(KeyHasher::XxHash64.hash(b"abc"), 0x44BC2CF5AD770999);
+    assert_eq!(
+        KeyHasher::XxHash64.hash(b"Nobody inspects the spammish repetition"),
+        0xfbcea83c8a378bf1
+    );
+    let dir = tmp("hasher");
+    let mut db = Db::open(
+        &dir,
+        DbOptions {
+            bloom_hasher: KeyHasher::Fnv1a,
+            prefix_bloom_len: Some(3),
+            ..Default::default()
+        },
+    )
+    .await
+    .unwrap();
+    for i in 0..500 {
+        db.put(&format!("key{i:04}"), "v").await.unwrap();
+    }
+    db.flush().await.unwrap();
+    drop(db);
+    let mut db = Db::new(&dir).await.unwrap();
+    assert_eq!(
+        db.ssts()[0].meta().await.unwrap().bloom_hasher(),
+        KeyHasher::Fnv1a
+    );
+    for i in 0..500 {
+        assert!(db.get(&format!("key{i:04}")).await.unwrap().is_some());
+    }
+    assert_eq!(db.range_count("key", "kez").await.unwrap(), 500);
+    let mut it = db.scan_prefix("key0").await.unwrap();
+    assert!(it.next().await.unwrap().is_some());
+    drop(it);
+    // new ones take the default
+    db.put("zzz", "1").await.unwrap();
+    db.flush().await.unwrap();
+    assert_eq!(
+        db.ssts()[0].meta().await.unwrap().bloom_hasher(),
+        KeyHasher::XxHash64
+    );
+    assert!(db.verify().await.unwrap().problems.is_empty());
+    db.compact().await.unwrap();
+    assert_eq!(db.get("key0007").await.unwrap().unwrap(), "v");
+}
+
+#[tokio::test]
+async fn hasher_unrecorded() {
+    // filters from before the hasher was recorded were all fnv-1a
+    let dir = tmp("hasher_unrecorded");
+    std::fs::create_dir_all(&dir).unwrap();
+    let data = (0..100).map(|i| Put {
+        key: format!("k{i:03}"),
+        value: Some("v".into()),
+        seq: i + 1,
+        flags: 0,
+        value_ref: None,
+        merge: false,
+    });
+    let writer = SstWriter::create(dir.join("1"))
+        .await
+        .unwrap()
+        .with_hasher(KeyHasher::Fnv1a);
+    let sst = Sst::construct_with(writer, data).await.unwrap();
+    let index = sst.index_path().to_path_buf();
+    let mut meta: serde_json::Value =
+        serde_json::from_slice(&std::fs::read(&index).unwrap()).unwrap();
+    meta["bloom"]
+        .as_object_mut()
+        .unwrap()
+        .remove("hasher")
+        .unwrap();
+    std::fs::write(&index, serde_json::to_vec(&meta).unwrap()).unwrap();
+    let sst = Sst::open(dir.join("1")).await.unwrap();
+    assert_eq!(sst.meta().await.unwrap().bloom_hasher(), KeyHasher::Fnv1a);
+    for i in 0..100 {
+        assert!(sst.bloom_may_contain(&format!("k{i:03}")));
+    }
+}
+
+#[tokio::test]
+async fn created() {
+    use std::time::{Duration, SystemTime};
+    let t0 = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000);
+    let clock = std::sync::Arc::new(MockClock::new(t0));
+    let dir = tmp("created");
+    let mut db = Db::open(
+        &dir,
+        DbOptions {
+            clock: Some(clock.clone()),
+            ..Default::default()
+        },
+    )
+    .await
+    .unwrap();
+    db.put("a", "old").await.unwrap();
+    db.put("x", "1").await.unwrap();
+    db.flush().await.unwrap();
+    clock.advance(Duration::from_secs(10));
+    db.put("b", "mid").await.unwrap();
+    db.flush().await.unwrap();
+    clock.advance(Duration::from_secs(10));
+    db.put("a", "new").await.unwrap();
+    db.flush().await.unwrap();
+    drop(db);
+    let mut db = Db::open(
+        &dir,
+        DbOptions {
+            clock: Some(clock.clone()),
+            ..Default::default()
+        },
+    )
+    .await
+    .unwrap();
+    let metas: Vec<_> = {
+        let mut v = vec![];
+        for s in db.ssts() {
+            let m = s.meta().await.unwrap();
+            v.push((m.created().unwrap(), m.min_seq(), m.max_seq()));
+        }
+        v
+    };
+    assert_eq!(metas[0].0, t0 + Duration::from_secs(20));
+    assert_eq!(metas[2].0, t0);
+    assert_eq!((metas[2].1, metas[2].2), (1, 2));
+    assert_eq!(db.drop_ssts_before(t0).await.unwrap(), 0);
+    assert_eq!(
+        db.drop_ssts_before(t0 + Duration::from_secs(15))
+            .await
+            .unwrap(),
+        2
+    );
+    assert_eq!(db.ssts().len(), 1);
+    assert_eq!(db.get("a").await.unwrap().unwrap(), "new");
+    assert!(db.get("b").await.unwrap().is_none() && db.get("x").await.unwrap().is_none());
+    db.put("c", "1").await.unwrap();
+    db.flush().await.unwrap();
+    clock.advance(Duration::from_secs(100));
+    db.compact().await.unwrap();
+    assert_eq!(
+        db.ssts()[0].meta().await.unwrap().created().unwrap(),
+        t0 + Duration::from_secs(20)
+    );
+    assert!(db.verify().await.unwrap().problems.is_empty());
+    drop(db);
+    let db = Db::new(&dir).await.unwrap();
+    assert_eq!(db.get("c").await.unwrap().unwrap(), "1");
+}
+
+#[tokio::test]
+async fn compressed_index() {
+    let mut sizes = vec![];
+    for compressed in [false, true] {
+        let dir = tmp(&format!("compressed_index{}", compressed));
+        let opts = DbOptions {
+            compressed_index: compressed,
+            ..Default::default()
+        };
+        let mut db = Db::open(&dir, opts.clone()).await.unwrap();
+        for i in 0..2000 {
+            db.put(
+                &format!("tenant/acme/users/profile/{:06}", i),
+                &i.to_string(),
+            )
+            .await
+            .unwrap();
+        }
+        db.put("tenant/é", "x").await.unwrap();
+        db.put("tenant/éa", "y").await.unwrap();
+        db.flush().await.unwrap();
+        sizes.push(std::fs::metadata(db.ssts()[0].index_path()).unwrap().len());
+        drop(db);
+        let db = Db::open(&dir, opts).await.unwrap();
+        for i in (0..2000).step_by(37) {
+            let k = format!("tenant/acme/users/profile/{:06}", i);
+            assert_eq!(db.get(&k).await.unwrap().unwrap(), i.to_string());
+        }
+        assert_eq!(db.get("tenant/éa").await.unwrap().unwrap(), "y");
+        assert_eq!(db.get("tenant/acme/users/profile/x").await.unwrap(), None);
+        let mut it = db
+            .scan("tenant/acme/users/profile/001990", None)
+            .await
+            .unwrap();
+        assert_eq!(it.next().await.unwrap().unwrap().1, "1990");
+    }
+    assert!(sizes[1] < sizes[0], "{:?}", sizes);
+}
+
+#[tokio::test]
+async fn last_block_boundary() {
+    let dir = tmp("last_block_boundary");
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("1");
+    let mut w = SstWriter::create(&path).await.unwrap();
+    let mut seq = 100;
+    for i in 0..40 {
+        let key = format!("k{:02}", i);
+        // the boundary key has two versions split across blocks
+        let versions = if i == 15 { 2 } else { 1 };
+        for v in 0..versions {
+            w.add(&Put {
+                key: key.clone(),
+                value: Some(format!("v{}-{}", i, v)),
+                seq,
+                flags: 0,
+                value_ref: None,
+                merge: false,
+            })
+            .await
+            .unwrap();
+            seq -= 1;
+            let _ = v;
+        }
+    }
+    let sst = std::sync::Arc::new(w.finish().await.unwrap());
+    let keys: Vec<String> = sst
+        .meta()
+        .await
+        .unwrap()
+        .block_keys()
+        .map(String::from)
+        .collect();
+    assert!(keys.len() >= 3, "{:?}", keys);
+    for key in &keys {
+        let got = sst.get_record(key).await.unwrap().unwrap();
+        assert_eq!(&got.key, key);
+        assert!(got.value.unwrap().ends_with("-0"));
+    }
+    let last = keys.last().unwrap().clone();
+    assert_eq!(
+        sst.get_record("k39").await.unwrap().unwrap().value.unwrap(),
+        "v39-0"
+    );
+    assert!(last.as_str() < "k39");
+    assert_eq!(sst.get_record("k").await.unwrap(), None);
+    assert_eq!(sst.get_record("a").await.unwrap(), None);
+    assert_eq!(sst.get_record("k40").await.unwrap(), None);
+    assert_eq!(
+        sst.get_record("k15").await.unwrap().unwrap().value.unwrap(),
+        "v15-0"
+    );
+    assert_eq!(sst.get_versions("k15").await.unwrap().len(), 2);
+    for i in 0..40 {
+        let k = format!("k{:02}", i);
+        assert!(sst.get_record(&k).await.unwrap().is_some(), "{}", k);
+    }
+}
+
+#[tokio::test]
+async fn range_iter() {
+    let dir = tmp("range_iter");
+    std::fs::create_dir_all(&dir).unwrap();
+    let data: Vec<Put> = (0..600)
+        .map(|i| Put {
+            key: format!("k{:04}", i * 2),
+            value: Some("v".repeat(40)),
+            seq: i + 1,
+            flags: 0,
+            value_ref: None,
+            merge: false,
+        })
+        .collect();
+    let sst = std::sync::Arc::new(
+        Sst::construct(dir.join("s"), data.into_iter())
+            .await
+            .unwrap(),
+    );
+    assert!(sst.meta().await.unwrap().block_keys().count() > 3);
+    for (start, end) in [
+        ("k0100", "k0301"),
+        ("", "k0005"),
+        ("k1150", "zzz"),
+        ("k0500", "k0500"),
+        ("k0101", "k0102"),
+        ("a", "b"),
+    ] {
+        let mut iter = sst.range_iter(start, end).await.unwrap();
+        let mut got = vec![];
+        while let Some(put) = iter.next().await.unwrap() {
+            got.push(put.key);
+        }
+        assert_eq!(iter.next().await.unwrap(), None);
+        let want: Vec<String> = (0..600)
+            .map(|i| format!("k{:04}", i * 2))
+            .filter(|k| k.as_str() >= start && k.as_str() < end)
+            .collect();
+        assert_eq!(got, want, "{} {}", start, end);
+    }
+    let mut db = Db::new(&tmp("range_iter-db")).await.unwrap();
+    for i in 0..300 {
+        db.put(&format!("k{:03}", i), "x").await.unwrap();
+    }
+    db.flush().await.unwrap();
+    assert_eq!(db.range_count("k100", "k200").await.unwrap(), 100);
+}
+
+async fn build_sst(
+    dir: &std::path::Path,
+    name: &str,
+    recs: &[(&str, Option<&str>, u64)],
+) -> std::sync::Arc<Sst> {
+    let data: Vec<Put> = recs
+        .iter()
+        .map(|(k, v, seq)| Put {
+            key: k.to_string(),
+            value: v.map(String::from),
+            seq: *seq,
+            flags: 0,
+            value_ref: None,
+            merge: false,
+        })
+        .collect();
+    std::sync::Arc::new(
+        Sst::construct(dir.join(name), data.into_iter())
+            .await
+            .unwrap(),
+    )
+}
+
+async fn sst_contents(sst: Sst) -> Vec<(String, Option<String>, u64)> {
+    let sst = std::sync::Arc::new(sst);
+    let mut iter = sst.iter().await.unwrap();
+    let mut out = vec![];
+    while let Some(p) = iter.next().await.unwrap() {
+        out.push((p.key, p.value, p.seq));
+    }
+    out
+}
+
+#[tokio::test]
+async fn merge_into() {
+    let dir = tmp("merge_into");
+    std::fs::create_dir_all(&dir).unwrap();
+    let s = |v: &[(&str, Option<&str>, u64)]| -> Vec<(String, Option<String>, u64)> {
+        v.iter()
+            .map(|(k, v, q)| (k.to_string(), v.map(String::from), *q))
+            .collect()
+    };
+    // disjoint
+    let a = build_sst(&dir, "a1", &[("a", Some("1"), 1), ("b", Some("2"), 2)]).await;
+    let b = build_sst(&dir, "b1", &[("c", Some("3"), 3), ("d", None, 4)]).await;
+    let out = Sst::merge_into(&a, &b, dir.join("o1"), Newest::B)
+        .await
+        .unwrap();
+    assert_eq!(
+        sst_contents(out).await,
+        s(&[
+            ("a", Some("1"), 1),
+            ("b", Some("2"), 2),
+            ("c", Some("3"), 3),
+            ("d", None, 4)
+        ])
+    );
+    // fully overlapping, newer by seq regardless of which
+    let a = build_sst(&dir, "a2", &[("a", Some("old"), 1), ("b", Some("new"), 5)]).await;
+    let b = build_sst(&dir, "b2", &[("a", Some("new"), 4), ("b", Some("old"), 2)]).await;
+    let out = Sst::merge_into(&a, &b, dir.join("o2"), Newest::A)
+        .await
+        .unwrap();
+    assert_eq!(
+        sst_contents(out).await,
+        s(&[("a", Some("new"), 4), ("b", Some("new"), 5)])
+    );
+    // equal seqs (pre-seq data) decided by newest
+    let a = build_sst(&dir, "a3", &[("k", Some("a"), 0)]).await;
+    let b = build_sst(&dir, "b3", &[("k", Some("b"), 0)]).await;
+    let out = Sst::merge_into(&a, &b, dir.join("o3"), Newest::A)
+        .await
+        .unwrap();
+    assert_eq!(sst_contents(out).await, s(&[("k", Some("a"), 0)]));
+    let out = Sst::merge_into(&a, &b, dir.join("o4"), Newest::B)
+        .await
+        .unwrap();
+    assert_eq!(sst_contents(out).await, s(&[("k", Some("b"), 0)]));
+    // interleaved with tombstones over values
+    let a = build_sst(
+        &dir,
+        "a5",
+        &[("a", Some("1"), 1), ("c", None, 10), ("e", Some("5"), 5)],
+    )
+    .await;
+    let b = build_sst(
+        &dir,
+        "b5",
+        &[
+            ("b", Some("2"), 2),
+            ("c", Some("3"), 3),
+            ("d", Some("4"), 4),
+            ("e", None, 6),
+        ],
+    )
+    .await;
+    let out = Sst::merge_into(&a, &b, dir.join("o5"), Newest::A)
+        .await
+        .unwrap();
+    assert_eq!(
+        sst_contents(out).await,
+        s(&[
+            ("a", Some("1"), 1),
+            ("b", Some("2"), 2),
+            ("c", None, 10),
+            ("d", Some("4"), 4),
+            ("e", None, 6)
+        ])
+    );
+}
+
+#[tokio::test]
+async fn bloom_may_contain() {
+    let dir = tmp("bloom_may_contain");
+    std::fs::create_dir_all(&dir).unwrap();
+    let data: Vec<Put> = (0..5000)
+        .map(|i| Put {
+            key: format!("key{:06}", i),
+            value: Some("v".into()),
+            seq: i + 1,
+            flags: 0,
+            value_ref: None,
+            merge: false,
+        })
+        .collect();
+    let sst = Sst::construct(dir.join("s"), data.into_iter())
+        .await
+        .unwrap();
+    for i in 0..5000 {
+        assert!(sst.bloom_may_contain(&format!("key{:06}", i)));
+    }
+    let absent = 100_000;
+    let fp = (0..absent)
+        .filter(|i| sst.bloom_may_contain(&format!("other{}", i)))
+        .count();
+    let rate = fp as f64 / absent as f64;
+    // 10 bits a key and 6 probes come to about 0.84%
+    assert!(rate > 0.002 && rate < 0.02, "{}", rate);
+    let lazy = Sst::open_lazy(dir.join("s")).await.unwrap();
+    assert!(lazy.bloom_may_contain("other1"));
+    lazy.meta().await.unwrap();
+    assert_eq!(
+        lazy.bloom_may_contain("other1"),
+        sst.bloom_may_contain("other1")
+    );
+    assert!(lazy.meta().await.unwrap().may_contain("key000001"));
+}
+
+#[tokio::test]
+async fn sst_stats() {
+    use std::sync::Arc;
+    let dir = tmp("sst_stats");
+    std::fs::create_dir_all(&dir).unwrap();
+    let put = |key: &str, value: Option<&str>, seq| Put {
+        key: key.into(),
+        value: value.map(String::from),
+        seq,
+        flags: 0,
+        value_ref: None,
+        merge: false,
+    };
+    let sst = Sst::construct(
+        dir.join("1"),
+        vec![
+            put("aa", Some("xyz"), 5),
+            put("aa", Some("x"), 3),
+            put("bbbb", None, 4),
+            put("c", Some("12345678"), 2),
+        ]
+        .into_iter(),
+    )
+    .await
+    .unwrap();
+    let stats = sst.stats().await.unwrap().unwrap().clone();
+    assert_eq!(
+        stats,
+        SstStats {
+            entries: 4,
+            key_bytes: 9,
+            value_bytes: 12,
+            tombstones: 1,
+            overwrites: 1,
+        }
+    );
+    assert_eq!(stats.avg_value_size(), 4.0);
+    assert_eq!(stats.tombstone_ratio(), 0.25);
+    // survives reopening
+    drop(sst);
+    let sst = Arc::new(Sst::open(dir.join("1")).await.unwrap());
+    assert_eq!(sst.stats().await.unwrap(), Some(&stats));
+
+    let big = Arc::new(
+        Sst::construct(
+            dir.join("2"),
+            (0..100).map(|i| put(&format!("k{:03}", i), Some("vvvvvvvvvv"), i)),
+        )
+        .await
+        .unwrap(),
+    );
+    let deleted = Arc::new(
+        Sst::construct(
+            dir.join("3"),
+            (0..10).map(|i| put(&format!("k{:03}", i), None, 200 + i)),
+        )
+        .await
+        .unwrap(),
+    );
+    let candidates = vec![big.clone(), deleted.clone(), sst.clone()];
+    for by_overlap in [false, true] {
+        let picked = pick_compaction_input(&candidates, &[], by_overlap)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(Arc::ptr_eq(&picked, &deleted));
+    }
+    let picked = pick_compaction_input(&[big.clone(), sst.clone()], &[], false)
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(Arc::ptr_eq(&picked, &big));
+}
@@ -0,0 +1,154 @@
+#![cfg(feature = "tracing")]
+
+mod common;
+
+use std::sync::{Arc, Mutex};
+
+use common::tmp;
+use lsm_based_db_in_rust::*;
+use tracing::{
+    field::{Field, Visit},
+    span, Event, Level, Metadata, Subscriber,
+};
+
+// level, message and the other fields
+type Captured = (Level, String, Vec<(String, String)>);
+
+// keeps every event
+#[derive(Clone, Default)]
+struct Capture(Arc<Mutex<Vec<Captured>>>);
+
+struct Fields<'a>(&'a mut Vec<(String, String)>);
+
+impl Visit for Fields<'_> {
+    fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+        self.0
+            .push((field.name().to_string(), format!("{:?}", value)));
+    }
+}
+
+impl Subscriber for Capture {
+    fn enabled(&self, _: &Metadata<'_>) -> bool {
+        true
+    }
+    fn new_span(&self, _: &span::Attributes<'_>) -> span::Id {
+        span::Id::from_u64(1)
+    }
+    fn record(&self, _: &span::Id, _: &span::Record<'_>) {}
+    fn record_follows_from(&self, _: &span::Id, _: &span::Id) {}
+    fn event(&self, event: &Event<'_>) {
+        let mut fields = Vec::new();
+        event.record(&mut Fields(&mut fields));
+        let message = fields
+            .iter()
+            .position(|(name, _)| name == "message")
+            .map(|idx| fields.remove(idx).1)
+            .unwrap_or_default();
+        self.0
+            .lock()
+            .unwrap()
+            .push((*event.metadata().level(), message, fields));
+    }
+    fn enter(&self, _: &span::Id) {}
+    fn exit(&self, _: &span::Id) {}
+}
+
+impl Capture {
+    fn field(&self, message: &str, name: &str) -> Option<String> {
+        let events = self.0.lock().unwrap();
+        let (_, _, fields) = events.iter().find(|(_, m, _)| m == message)?;
+        fields
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, value)| value.clone())
+    }
+
+    fn warnings(&self) -> usize {
+        let events = self.0.lock().unwrap();
+        events
+            .iter()
+            .filter(|(level, _, _)| *level == Level::WARN)
+            .count()
+    }
+
+    fn last_warning(&self) -> Option<String> {
+        let events = self.0.lock().unwrap();
+        events
+            .iter()
+            .rev()
+            .find(|(level, _, _)| *level == Level::WARN)
+            .map(|(_, message, _)| message.clone())
+    }
+}
+
+#[test]
+fn flush_and_compaction_events() {
+    let rt = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap();
+    let capture = Capture::default();
+    let _guard = tracing::subscriber::set_default(capture.clone());
+    rt.block_on(async {
+        let dir = tmp("flush_and_compaction_events");
+        let opts = DbOptions {
+            compaction_trigger: 100,
+            ..Default::default()
+        };
+        let mut db = Db::open(&dir, opts).await.unwrap();
+        for i in 0..10 {
+            db.put(&format!("k{}", i), "v").await.unwrap();
+        }
+        db.flush().await.unwrap();
+        db.put("k0", "w").await.unwrap();
+        db.flush().await.unwrap();
+        db.compact().await.unwrap();
+    });
+    assert_eq!(capture.field("flush", "keys").as_deref(), Some("10"));
+    assert!(capture.field("flush", "bytes").is_some());
+    assert!(capture.field("flush", "elapsed_us").is_some());
+    assert_eq!(
+        capture.field("compaction started", "inputs").as_deref(),
+        Some("2")
+    );
+    assert!(capture.field("compaction finished", "bytes").is_some());
+}
+
+#[test]
+fn drop_warning() {
+    let rt = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap();
+    let capture = Capture::default();
+    let _guard = tracing::subscriber::set_default(capture.clone());
+    rt.block_on(async {
+        let dir = tmp("drop_warning");
+        let mut db = Db::new(&dir).await.unwrap();
+        db.put("a", "1").await.unwrap();
+        drop(db);
+        assert_eq!(capture.warnings(), 1);
+        assert!(capture
+            .last_warning()
+            .unwrap()
+            .contains("replayed from the log"));
+        let mut db = Db::new(&dir).await.unwrap();
+        db.put("b", "2").await.unwrap();
+        db.close().await.unwrap();
+        assert_eq!(capture.warnings(), 1);
+        let db = Db::new(&dir).await.unwrap();
+        assert!(db.memtable_reader().is_none());
+        assert_eq!(db.get("a").await.unwrap().unwrap(), "1");
+        drop(db);
+
+        let opts = DbOptions {
+            durability: Durability::None,
+            ..Default::default()
+        };
+        let mut db = Db::open(tmp("drop_warning-unlogged"), opts).await.unwrap();
+        db.put("a", "1").await.unwrap();
+        drop(db);
+        assert_eq!(capture.warnings(), 2);
+        assert!(capture.last_warning().unwrap().contains("lost"));
+    });
+}